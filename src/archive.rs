@@ -0,0 +1,344 @@
+use crate::Result;
+use crate::classify::Classifier;
+use crate::split::{FileSource, SkipReason, SkippedFile, SourceFile};
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+/// Separates the path of an archive from the path of an entry inside it, as in
+/// `vendor/drop.tar.gz!/src/lib.rs`.
+pub const ARCHIVE_PATH_SEPARATOR: &str = "!/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveKind {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` names an archive that [`ArchiveSource`] can read.
+pub(crate) fn is_archive(path: &Path) -> bool {
+    ArchiveKind::from_name(&path.to_string_lossy()).is_some()
+}
+
+/// Number of archives nested in one another that are expanded below the outermost one.
+pub const MAX_ARCHIVE_DEPTH: usize = 3;
+
+/// Number of entries read ahead of the splitter.
+const READ_AHEAD: usize = 16;
+
+/// Reads the files inside an archive, descending into nested archives.
+///
+/// Entries are read on a thread of their own, a few ahead of the splitter, so that only those
+/// are held in memory. They come in archive order, with paths made of the archive path and the
+/// entry path joined by [`ARCHIVE_PATH_SEPARATOR`]. Entries larger than `max_file_size`, nested
+/// archives included, are reported as skipped, as are archives nested deeper than
+/// [`MAX_ARCHIVE_DEPTH`].
+pub(crate) struct ArchiveSource {
+    files: Receiver<Result<SourceFile, SkippedFile>>,
+    classifier: Classifier,
+}
+
+impl ArchiveSource {
+    pub(crate) fn open(path: &Path, max_file_size: Option<u64>) -> Result<Self> {
+        let kind = ArchiveKind::from_name(&path.to_string_lossy()).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not a supported archive", path.display()),
+            )
+        })?;
+        let file = fs::File::open(path)?;
+        let prefix = path.to_string_lossy().to_string();
+        let (sender, files) = mpsc::sync_channel(READ_AHEAD);
+        thread::spawn(move || {
+            let reader = ArchiveReader {
+                sender,
+                max_file_size,
+            };
+            reader.send_archive(file, kind, &prefix, 0);
+        });
+        Ok(ArchiveSource {
+            files,
+            classifier: Classifier::new(Path::new("")),
+        })
+    }
+}
+
+impl Iterator for ArchiveSource {
+    type Item = Result<SourceFile, SkippedFile>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The reader hangs up once it has sent every entry.
+        self.files.recv().ok()
+    }
+}
+
+impl FileSource for ArchiveSource {
+    fn classifier(&self) -> &Classifier {
+        &self.classifier
+    }
+}
+
+/// Sends the entries of an archive to an [`ArchiveSource`].
+///
+/// Every method returns whether to go on, which stops being the case once the source is dropped.
+struct ArchiveReader {
+    sender: SyncSender<Result<SourceFile, SkippedFile>>,
+    max_file_size: Option<u64>,
+}
+
+impl ArchiveReader {
+    fn send(&self, file: Result<SourceFile, SkippedFile>) -> bool {
+        self.sender.send(file).is_ok()
+    }
+
+    fn skip(&self, path: &str, reason: SkipReason) -> bool {
+        self.send(Err(SkippedFile::new(Path::new(path), reason)))
+    }
+
+    /// Sends the entries of the archive `prefix`, nested `depth` archives deep. Failures only
+    /// affect the rest of the archive, so they are recorded as a skipped file.
+    fn send_archive<R: Read + Seek>(
+        &self,
+        reader: R,
+        kind: ArchiveKind,
+        prefix: &str,
+        depth: usize,
+    ) -> bool {
+        match self.read_archive(reader, kind, prefix, depth) {
+            Ok(go_on) => go_on,
+            Err(e) => self.skip(
+                prefix,
+                SkipReason::Io {
+                    message: e.to_string(),
+                },
+            ),
+        }
+    }
+
+    fn read_archive<R: Read + Seek>(
+        &self,
+        reader: R,
+        kind: ArchiveKind,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<bool> {
+        match kind {
+            ArchiveKind::Tar => self.read_tar(tar::Archive::new(reader), prefix, depth),
+            ArchiveKind::TarGz => {
+                self.read_tar(tar::Archive::new(GzDecoder::new(reader)), prefix, depth)
+            }
+            ArchiveKind::Zip => {
+                let mut archive = zip::ZipArchive::new(reader)?;
+                for index in 0..archive.len() {
+                    let mut entry = archive.by_index(index)?;
+                    if !entry.is_file() {
+                        continue;
+                    }
+                    let path = format!("{prefix}{ARCHIVE_PATH_SEPARATOR}{}", entry.name());
+                    let size = entry.size();
+                    if !self.read_entry(&mut entry, path, size, depth) {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn read_tar<R: Read>(
+        &self,
+        mut archive: tar::Archive<R>,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<bool> {
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = format!(
+                "{prefix}{ARCHIVE_PATH_SEPARATOR}{}",
+                entry.path()?.to_string_lossy()
+            );
+            let size = entry.size();
+            if !self.read_entry(&mut entry, path, size, depth) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Reads a single entry of an archive nested `depth` archives deep, expanding it in place if
+    /// it is an archive itself. `size` comes from the archive headers and is not trusted.
+    ///
+    /// Failures only affect the entry, so they are recorded as skipped files.
+    fn read_entry(&self, entry: &mut impl Read, path: String, size: u64, depth: usize) -> bool {
+        let nested_kind = ArchiveKind::from_name(&path);
+        if nested_kind.is_some() && depth >= MAX_ARCHIVE_DEPTH {
+            return self.skip(
+                &path,
+                SkipReason::ArchiveTooDeep {
+                    limit: MAX_ARCHIVE_DEPTH,
+                },
+            );
+        }
+        let limit = self.max_file_size.unwrap_or(u64::MAX);
+        if size > limit {
+            return self.skip(&path, SkipReason::TooLarge { size, limit });
+        }
+
+        // One byte past the limit is enough to tell that an entry lied about its size.
+        let mut contents = Vec::new();
+        if let Err(e) = entry
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut contents)
+        {
+            return self.skip(&path, e.into());
+        }
+        if contents.len() as u64 > limit {
+            let size = size.max(contents.len() as u64);
+            return self.skip(&path, SkipReason::TooLarge { size, limit });
+        }
+
+        match nested_kind {
+            Some(kind) => self.send_archive(Cursor::new(contents), kind, &path, depth + 1),
+            None => self.send(Ok(SourceFile::Memory {
+                path: PathBuf::from(path),
+                contents,
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    pub(crate) fn create_tar_gz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    pub(crate) fn create_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, contents) in files {
+            writer
+                .start_file(*path, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_archive_source() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let nested_zip = create_zip(&[
+            ("nested/lib.rs", b"fn nested() {}\n"),
+            ("nested/large.rs", &[b'x'; 1024]),
+        ]);
+        let archive = create_tar_gz(&[
+            ("drop/main.py", b"print('main')\n"),
+            ("drop/inner.zip", &nested_zip),
+            ("drop/README.md", b"# Readme\n"),
+        ]);
+        let archive_path = temp_dir.path().join("drop.tar.gz");
+        fs::write(&archive_path, archive).unwrap();
+
+        let source = ArchiveSource::open(&archive_path, Some(512)).unwrap();
+        let archive_path = archive_path.to_string_lossy();
+        let entries: Vec<_> = source
+            .map(|x| match x {
+                Ok(file) => (file.path().to_string_lossy().to_string(), None),
+                Err(skipped) => (skipped.path, Some(skipped.reason)),
+            })
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                (format!("{archive_path}!/drop/main.py"), None),
+                (
+                    format!("{archive_path}!/drop/inner.zip!/nested/lib.rs"),
+                    None
+                ),
+                (
+                    format!("{archive_path}!/drop/inner.zip!/nested/large.rs"),
+                    Some(SkipReason::TooLarge {
+                        size: 1024,
+                        limit: 512
+                    })
+                ),
+                (format!("{archive_path}!/drop/README.md"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_archive_source_limits_nested_archives() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let mut archive = create_zip(&[("lib.rs", b"fn deep() {}\n")]);
+        for _ in 0..=MAX_ARCHIVE_DEPTH {
+            archive = create_zip(&[("inner.zip", &archive)]);
+        }
+        let archive_path = temp_dir.path().join("deep.zip");
+        fs::write(&archive_path, &archive).unwrap();
+
+        let reasons = |max_file_size| -> Vec<SkipReason> {
+            ArchiveSource::open(&archive_path, max_file_size)
+                .unwrap()
+                .map(|x| x.err().unwrap().reason)
+                .collect()
+        };
+        assert_eq!(
+            reasons(None),
+            vec![SkipReason::ArchiveTooDeep {
+                limit: MAX_ARCHIVE_DEPTH
+            }]
+        );
+        // Nested archives count against the size limit like any other entry.
+        assert!(matches!(
+            reasons(Some(16))[..],
+            [SkipReason::TooLarge { limit: 16, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_archive_source_rejects_other_files() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = temp_dir.path().join("main.rs");
+        fs::write(&path, "fn main() {}").unwrap();
+
+        assert!(ArchiveSource::open(&path, None).is_err());
+    }
+}
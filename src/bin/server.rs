@@ -0,0 +1,416 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use clap::Parser;
+use semantic_code_search::codebases::{self, Codebase};
+use semantic_code_search::filter::SearchFilter;
+use semantic_code_search::index::{self, DEFAULT_TABLE_NAME, DistanceMetric};
+use semantic_code_search::search::{
+    self, Chunk, DEFAULT_LIMIT, DEFAULT_MMR_LAMBDA, DEFAULT_RERANK_CANDIDATES,
+    DEFAULT_VECTOR_WEIGHT, MmrOptions, RerankOptions, SearchModeKind, SearchOptions, SearchResult,
+    SimilarTo,
+};
+use semantic_code_search::{Error, Result, embed};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Largest `limit`, `rerank_candidates` and `max_per_file` a request may ask for.
+const MAX_RESULTS: usize = 1000;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Serve semantic code search over HTTP from a LanceDB database"
+)]
+struct Cli {
+    db: String,
+    #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+    table: String,
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    address: String,
+    /// Distance between embeddings; must match the vector index, if there is one.
+    #[arg(long, value_enum, default_value_t = DistanceMetric::Cosine)]
+    metric: DistanceMetric,
+    /// Load the cross-encoder model at startup, so that requests can ask for reranking.
+    #[arg(long)]
+    rerank: bool,
+}
+
+/// What every request is served from, set at startup.
+struct ServerState {
+    db: String,
+    table: String,
+    metric: DistanceMetric,
+    /// Whether the cross-encoder model was loaded.
+    rerank: bool,
+}
+
+/// Search settings shared by `/search` and `/similar`, all optional.
+#[derive(Deserialize)]
+#[serde(default)]
+struct OptionsBody {
+    limit: usize,
+    mode: SearchModeKind,
+    vector_weight: f32,
+    filter: SearchFilter,
+    rerank: bool,
+    rerank_candidates: usize,
+    mmr: bool,
+    mmr_lambda: f32,
+    max_per_file: Option<usize>,
+}
+
+impl Default for OptionsBody {
+    fn default() -> Self {
+        OptionsBody {
+            limit: DEFAULT_LIMIT,
+            mode: SearchModeKind::default(),
+            vector_weight: DEFAULT_VECTOR_WEIGHT,
+            filter: SearchFilter::default(),
+            rerank: false,
+            rerank_candidates: DEFAULT_RERANK_CANDIDATES,
+            mmr: false,
+            mmr_lambda: DEFAULT_MMR_LAMBDA,
+            max_per_file: None,
+        }
+    }
+}
+
+impl OptionsBody {
+    fn to_options(&self, state: &ServerState) -> Result<SearchOptions, ApiError> {
+        let counts = [
+            ("limit", Some(self.limit)),
+            ("rerank_candidates", Some(self.rerank_candidates)),
+            ("max_per_file", self.max_per_file),
+        ];
+        for (name, count) in counts {
+            if count.is_some_and(|x| !(1..=MAX_RESULTS).contains(&x)) {
+                return Err(ApiError::bad_request(&format!(
+                    "{name} must be between 1 and {MAX_RESULTS}"
+                )));
+            }
+        }
+        if self.rerank && !state.rerank {
+            return Err(ApiError::bad_request(
+                "reranking is disabled; start the server with --rerank",
+            ));
+        }
+        Ok(SearchOptions {
+            table_name: state.table.clone(),
+            limit: self.limit,
+            mode: self.mode.to_mode(self.vector_weight),
+            metric: state.metric,
+            filter: self.filter.clone(),
+            rerank: self.rerank.then_some(RerankOptions {
+                candidates: self.rerank_candidates,
+            }),
+            mmr: self.mmr.then_some(MmrOptions {
+                lambda: self.mmr_lambda,
+                ..Default::default()
+            }),
+            max_per_file: self.max_per_file,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchBody {
+    query: String,
+    #[serde(flatten)]
+    options: OptionsBody,
+}
+
+/// A chunk by ID, or rows `start_line..end_line` of a file, counted from 0 like those of chunks.
+/// Files the table holds no chunks of are answered with 404 without being read.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TargetBody {
+    Chunk {
+        chunk_id: String,
+    },
+    Lines {
+        file_path: String,
+        start_line: u64,
+        end_line: u64,
+    },
+}
+
+#[derive(Deserialize)]
+struct SimilarBody {
+    #[serde(flatten)]
+    target: TargetBody,
+    #[serde(flatten)]
+    options: OptionsBody,
+}
+
+/// An error sent back as `{"error": message}`.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: &str) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        let status = match &error {
+            Error::ChunkNotFound(_)
+            | Error::FileNotIndexed(_)
+            | Error::LanceDb(lancedb::Error::TableNotFound { .. }) => StatusCode::NOT_FOUND,
+            Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            Error::Glob(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError {
+            status,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+async fn search_chunks(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<SearchBody>,
+) -> Result<Json<Vec<SearchResult>>, ApiError> {
+    let options = body.options.to_options(&state)?;
+    Ok(Json(
+        search::search(&state.db, &body.query, &options).await?,
+    ))
+}
+
+async fn find_similar(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<SimilarBody>,
+) -> Result<Json<Vec<SearchResult>>, ApiError> {
+    let options = body.options.to_options(&state)?;
+    let target = match body.target {
+        TargetBody::Chunk { chunk_id } => SimilarTo::Chunk { id: chunk_id },
+        TargetBody::Lines {
+            file_path,
+            start_line,
+            end_line,
+        } => SimilarTo::Lines {
+            file_path,
+            start_line,
+            end_line,
+        },
+    };
+    Ok(Json(
+        search::find_similar(&state.db, &target, &options).await?,
+    ))
+}
+
+async fn get_chunk(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Chunk>, ApiError> {
+    Ok(Json(search::get_chunk(&state.db, &state.table, &id).await?))
+}
+
+async fn list_codebases(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Json<Vec<Codebase>>, ApiError> {
+    Ok(Json(
+        codebases::list_codebases(&state.db, &state.table).await?,
+    ))
+}
+
+/// Answers as soon as the server is up, which is only once the models are loaded.
+async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Answers whether the table can be searched.
+async fn ready(State(state): State<Arc<ServerState>>) -> Result<Response, ApiError> {
+    Ok(if index::table_exists(&state.db, &state.table).await? {
+        Json(json!({ "status": "ready" })).into_response()
+    } else {
+        ApiError {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: format!("no table named {}", state.table),
+        }
+        .into_response()
+    })
+}
+
+fn router(state: ServerState) -> Router {
+    Router::new()
+        .route("/search", post(search_chunks))
+        .route("/similar", post(find_similar))
+        .route("/chunks/{id}", get(get_chunk))
+        .route("/codebases", get(list_codebases))
+        .route("/health", get(health))
+        .route("/health/ready", get(ready))
+        .with_state(Arc::new(state))
+}
+
+async fn shutdown_signal() {
+    // Without a signal handler, the server runs until it is killed.
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // Loaded before listening, so that no request waits for them.
+    embed::load_models(cli.rerank)?;
+
+    let state = ServerState {
+        db: cli.db,
+        table: cli.table,
+        metric: cli.metric.into(),
+        rerank: cli.rerank,
+    };
+    let listener = TcpListener::bind(&cli.address).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Method, Request};
+    use semantic_code_search::embed::create_embeddings_from_file;
+    use semantic_code_search::split::find_and_split;
+    use serde_json::Value;
+    use std::fs;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    /// Sends a request to a router over `db`, returning the status and the JSON body.
+    async fn send(db: &str, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let state = ServerState {
+            db: db.to_string(),
+            table: DEFAULT_TABLE_NAME.to_string(),
+            metric: DistanceMetric::default(),
+            rerank: false,
+        };
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |x| Body::from(x.to_string())))
+            .unwrap();
+        let response = router(state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_router() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(&root)?;
+        fs::write(
+            root.join("math.py"),
+            "def add_numbers(first, second):\n    return first + second\n",
+        )?;
+        fs::write(
+            root.join("greeting.js"),
+            "function greet(name) {\n    console.log(\"Hello, \" + name);\n}\n",
+        )?;
+        let db = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+
+        // Before anything is indexed, the server is up but not ready.
+        let (status, _) = send(&db, Method::GET, "/health", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&db, Method::GET, "/health/ready", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let search = json!({ "query": "sum of two numbers" });
+        let (status, _) = send(&db, Method::POST, "/search", Some(search.clone())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let split_file_uri = temp_dir.path().join("split.parquet");
+        let embed_file_uri = temp_dir.path().join("embed.parquet");
+        find_and_split(
+            root.to_string_lossy().to_string(),
+            split_file_uri.to_string_lossy().to_string(),
+        )?;
+        create_embeddings_from_file(
+            split_file_uri.to_string_lossy().to_string(),
+            embed_file_uri.to_string_lossy().to_string(),
+        )?;
+        index::index(embed_file_uri.to_string_lossy().to_string(), db.clone()).await?;
+
+        let (status, _) = send(&db, Method::GET, "/health/ready", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, results) = send(&db, Method::POST, "/search", Some(search)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results[0]["file_name"], "math.py");
+        let id = results[0]["id"].as_str().unwrap().to_string();
+        let math_path = results[0]["file_path"].clone();
+
+        for body in [
+            json!({ "query": "sum", "limit": 0 }),
+            json!({ "query": "sum", "limit": MAX_RESULTS + 1 }),
+            json!({ "query": "sum", "rerank": true }),
+            json!({ "query": "sum", "filter": { "path_globs": ["src/{a,b"] } }),
+        ] {
+            let (status, error) = send(&db, Method::POST, "/search", Some(body)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(error["error"].is_string());
+        }
+
+        let (status, chunk) = send(&db, Method::GET, &format!("/chunks/{id}"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(chunk["file_path"], math_path);
+        let (status, _) = send(&db, Method::GET, "/chunks/missing", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let similar = json!({ "chunk_id": id, "limit": 1 });
+        let (status, results) = send(&db, Method::POST, "/similar", Some(similar)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results[0]["file_name"], "greeting.js");
+        let lines = json!({ "file_path": math_path, "start_line": 0, "end_line": 1 });
+        let (status, _) = send(&db, Method::POST, "/similar", Some(lines)).await;
+        assert_eq!(status, StatusCode::OK);
+        let missing = json!({ "chunk_id": "missing" });
+        let (status, _) = send(&db, Method::POST, "/similar", Some(missing)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // Files outside the table are not read, whether they exist or not.
+        let lines = json!({ "file_path": "/etc/passwd", "start_line": 0, "end_line": 1 });
+        let (status, _) = send(&db, Method::POST, "/similar", Some(lines)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let invalid = json!({ "chunk_id": id, "max_per_file": 0 });
+        let (status, _) = send(&db, Method::POST, "/similar", Some(invalid)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, codebases) = send(&db, Method::GET, "/codebases", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(codebases.as_array().unwrap().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+use clap::ValueEnum;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static VENDORED_PATHS: OnceLock<GlobSet> = OnceLock::new();
+static GENERATED_PATHS: OnceLock<GlobSet> = OnceLock::new();
+
+/// Path patterns for third-party code, after GitHub Linguist's `vendor.yml`.
+const VENDORED_PATTERNS: &[&str] = &[
+    "**/vendor/**",
+    "**/vendors/**",
+    "**/third_party/**",
+    "**/third-party/**",
+    "**/thirdparty/**",
+    "**/node_modules/**",
+    "**/bower_components/**",
+    "**/jspm_packages/**",
+    "**/.yarn/**",
+    "**/site-packages/**",
+    "**/Godeps/_workspace/**",
+];
+
+/// Path patterns for machine-written code, after GitHub Linguist's `generated.rb`.
+const GENERATED_PATTERNS: &[&str] = &[
+    "**/*.min.js",
+    "**/*-min.js",
+    "**/*.bundle.js",
+    "**/*_pb.js",
+    "**/*_pb.d.ts",
+    "**/*_grpc_pb.js",
+    "**/*_grpc_pb.d.ts",
+    "**/*_pb2.py",
+    "**/*_pb2_grpc.py",
+    "**/*_pb2.pyi",
+    "**/*.pb.go",
+    "**/*.pb.rs",
+    "**/*.generated.*",
+    "**/__generated__/**",
+    "**/package-lock.json",
+    "**/yarn.lock",
+    "**/pnpm-lock.yaml",
+    "**/Cargo.lock",
+    "**/poetry.lock",
+    "**/Pipfile.lock",
+];
+
+/// Lower-case markers that tools leave in the header of the files they write.
+const GENERATED_MARKERS: &[&str] = &[
+    "do not edit",
+    "@generated",
+    "auto-generated",
+    "autogenerated",
+    "automatically generated",
+    "generated by the protocol buffer compiler",
+];
+
+/// Number of leading lines searched for generated code markers.
+const HEADER_LINES: usize = 10;
+
+/// Where a source file comes from, so that non-authored code can be excluded from search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Classification {
+    /// Code written and maintained in the repository.
+    Source,
+    /// Code written by a tool, such as protobuf stubs or minified bundles.
+    Generated,
+    /// Third-party code copied into the repository.
+    Vendored,
+}
+
+impl Classification {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Classification::Source => "source",
+            Classification::Generated => "generated",
+            Classification::Vendored => "vendored",
+        }
+    }
+}
+
+/// A `.gitattributes` line that sets or unsets `linguist-generated` or `linguist-vendored`.
+struct AttributeRule {
+    base: PathBuf,
+    matcher: GlobMatcher,
+    // Patterns without a slash match the file name at any depth below `base`.
+    match_file_name: bool,
+    generated: Option<bool>,
+    vendored: Option<bool>,
+}
+
+impl AttributeRule {
+    fn is_match(&self, path: &Path) -> bool {
+        let Ok(relative_path) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        if self.match_file_name {
+            relative_path
+                .file_name()
+                .is_some_and(|x| self.matcher.is_match(x))
+        } else {
+            self.matcher.is_match(relative_path)
+        }
+    }
+}
+
+/// Classifies files as source, generated or vendored.
+///
+/// `.gitattributes` files take precedence, followed by Linguist-style path patterns and finally
+/// by markers in the file header.
+pub(crate) struct Classifier {
+    root: PathBuf,
+    rules: Vec<AttributeRule>,
+}
+
+impl Classifier {
+    pub(crate) fn new(root: &Path) -> Self {
+        Classifier {
+            root: root.to_path_buf(),
+            rules: vec![],
+        }
+    }
+
+    /// Loads the `.gitattributes` file in `directory`, if any.
+    ///
+    /// Directories must be loaded from the root down, as later rules override earlier ones.
+    pub(crate) fn load_gitattributes(&mut self, directory: &Path) {
+        if let Ok(contents) = fs::read_to_string(directory.join(".gitattributes")) {
+            self.add_gitattributes(directory, &contents);
+        }
+    }
+
+    pub(crate) fn add_gitattributes(&mut self, base: &Path, contents: &str) {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next() else {
+                continue;
+            };
+            let mut generated = None;
+            let mut vendored = None;
+            for attribute in fields {
+                let (name, value) = parse_attribute(attribute);
+                match name {
+                    "linguist-generated" => generated = Some(value),
+                    "linguist-vendored" => vendored = Some(value),
+                    _ => {}
+                }
+            }
+            if generated.is_none() && vendored.is_none() {
+                continue;
+            }
+
+            let pattern = pattern.trim_end_matches('/');
+            let match_file_name = !pattern.contains('/');
+            let Ok(glob) = GlobBuilder::new(pattern.trim_start_matches('/'))
+                .literal_separator(true)
+                .build()
+            else {
+                continue;
+            };
+            self.rules.push(AttributeRule {
+                base: base.to_path_buf(),
+                matcher: glob.compile_matcher(),
+                match_file_name,
+                generated,
+                vendored,
+            });
+        }
+    }
+
+    /// Classifies the file at `path`, given its first lines.
+    pub(crate) fn classify(&self, path: &Path, lines: &[String]) -> Classification {
+        let mut generated = None;
+        let mut vendored = None;
+        for rule in self.rules.iter().filter(|x| x.is_match(path)) {
+            generated = rule.generated.or(generated);
+            vendored = rule.vendored.or(vendored);
+        }
+        if vendored == Some(true) {
+            return Classification::Vendored;
+        }
+        if generated == Some(true) {
+            return Classification::Generated;
+        }
+
+        let relative_path = path.strip_prefix(&self.root).unwrap_or(path);
+        if vendored.is_none() && get_vendored_paths().is_match(relative_path) {
+            return Classification::Vendored;
+        }
+        if generated.is_none()
+            && (get_generated_paths().is_match(relative_path) || has_generated_marker(lines))
+        {
+            return Classification::Generated;
+        }
+        Classification::Source
+    }
+}
+
+/// Parses a `.gitattributes` attribute into its name and whether it is set.
+fn parse_attribute(attribute: &str) -> (&str, bool) {
+    if let Some(name) = attribute
+        .strip_prefix('-')
+        .or_else(|| attribute.strip_prefix('!'))
+    {
+        return (name, false);
+    }
+    match attribute.split_once('=') {
+        Some((name, value)) => (name, !matches!(value, "false" | "0")),
+        None => (attribute, true),
+    }
+}
+
+fn has_generated_marker(lines: &[String]) -> bool {
+    lines.iter().take(HEADER_LINES).any(|x| {
+        let line = x.to_lowercase();
+        GENERATED_MARKERS.iter().any(|y| line.contains(y))
+    })
+}
+
+fn build_glob_set(patterns: &[&str]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).unwrap());
+    }
+    builder.build().unwrap()
+}
+
+fn get_vendored_paths() -> &'static GlobSet {
+    VENDORED_PATHS.get_or_init(|| build_glob_set(VENDORED_PATTERNS))
+}
+
+fn get_generated_paths() -> &'static GlobSet {
+    GENERATED_PATHS.get_or_init(|| build_glob_set(GENERATED_PATTERNS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(contents: &str) -> Vec<String> {
+        contents.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_classify_by_path() {
+        let classifier = Classifier::new(Path::new("/repo"));
+        let source = lines("fn main() {}");
+
+        assert_eq!(
+            classifier.classify(Path::new("/repo/src/main.rs"), &source),
+            Classification::Source
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/vendor/serde/src/lib.rs"), &source),
+            Classification::Vendored
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/web/node_modules/react/index.js"), &source),
+            Classification::Vendored
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/web/dist/app.min.js"), &source),
+            Classification::Generated
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/api/service_pb2.py"), &source),
+            Classification::Generated
+        );
+        // Only the path below the root is considered.
+        assert_eq!(
+            Classifier::new(Path::new("/vendor/repo"))
+                .classify(Path::new("/vendor/repo/src/main.rs"), &source),
+            Classification::Source
+        );
+    }
+
+    #[test]
+    fn test_classify_by_header() {
+        let classifier = Classifier::new(Path::new("/repo"));
+        let path = Path::new("/repo/src/api.rs");
+
+        assert_eq!(
+            classifier.classify(
+                path,
+                &lines("// Code generated by protoc-gen-rust. DO NOT EDIT.\nfn main() {}")
+            ),
+            Classification::Generated
+        );
+        assert_eq!(
+            classifier.classify(path, &lines("# @generated by build.py\nx = 1")),
+            Classification::Generated
+        );
+        assert_eq!(
+            classifier.classify(
+                path,
+                &lines(&format!(
+                    "{}// @generated",
+                    "fn main() {}\n".repeat(HEADER_LINES)
+                ))
+            ),
+            Classification::Source
+        );
+    }
+
+    #[test]
+    fn test_classify_by_gitattributes() {
+        let mut classifier = Classifier::new(Path::new("/repo"));
+        classifier.add_gitattributes(
+            Path::new("/repo"),
+            "# Comment\n*.rs text eol=lf\n/api/** linguist-generated\ncontrib/* linguist-vendored=true\n*_pb2.py -linguist-generated\n",
+        );
+        classifier.add_gitattributes(
+            Path::new("/repo/api/handwritten"),
+            "*.py linguist-generated=false",
+        );
+        let source = lines("x = 1");
+
+        assert_eq!(
+            classifier.classify(Path::new("/repo/api/client.py"), &source),
+            Classification::Generated
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/api/handwritten/client.py"), &source),
+            Classification::Source
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/contrib/plugin.py"), &source),
+            Classification::Vendored
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/contrib/nested/plugin.py"), &source),
+            Classification::Source
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/proto/service_pb2.py"), &source),
+            Classification::Source
+        );
+        assert_eq!(
+            classifier.classify(Path::new("/repo/src/main.rs"), &source),
+            Classification::Source
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::Result;
+use crate::index::{codebase_predicate, delete_rows, open_table};
+use lancedb::arrow::IntoPolars;
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use polars::prelude::DataFrame;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// A repository and branch indexed in a table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Codebase {
+    /// Repository identifier, or `None` for chunks indexed without one.
+    pub repository: Option<String>,
+    /// Branch, or `None` for chunks indexed from a commit, a tag or outside git.
+    pub branch: Option<String>,
+    /// Number of distinct files.
+    pub files: u64,
+    /// Number of chunks.
+    pub chunks: u64,
+}
+
+/// A [`Codebase`] with details about its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CodebaseDescription {
+    #[serde(flatten)]
+    pub codebase: Codebase,
+    /// Commits the chunks were split at, sorted.
+    pub commits: Vec<String>,
+    /// Number of chunks of each classification.
+    pub classifications: BTreeMap<String, u64>,
+    /// Most recent author time among the chunks, in seconds since the Unix epoch, when blame
+    /// information was recorded.
+    pub last_modified: Option<i64>,
+}
+
+/// Lists the codebases in the `table_name` table of the database at `db_file_uri`, sorted by
+/// repository and branch.
+///
+/// A missing table holds no codebases.
+pub async fn list_codebases(db_file_uri: &str, table_name: &str) -> Result<Vec<Codebase>> {
+    let Some(dataframe) = read_columns(
+        db_file_uri,
+        table_name,
+        None,
+        &["repository", "branch", "file_path"],
+    )
+    .await?
+    else {
+        return Ok(vec![]);
+    };
+
+    let mut codebases: BTreeMap<(Option<String>, Option<String>), (HashSet<String>, u64)> =
+        BTreeMap::new();
+    let repositories = dataframe.column("repository")?.str()?;
+    let branches = dataframe.column("branch")?.str()?;
+    let file_paths = dataframe.column("file_path")?.str()?;
+    for ((repository, branch), file_path) in repositories.iter().zip(branches).zip(file_paths) {
+        let (files, chunks) = codebases
+            .entry((repository.map(str::to_string), branch.map(str::to_string)))
+            .or_default();
+        files.insert(file_path.unwrap_or_default().to_string());
+        *chunks += 1;
+    }
+
+    Ok(codebases
+        .into_iter()
+        .map(|((repository, branch), (files, chunks))| Codebase {
+            repository,
+            branch,
+            files: files.len() as u64,
+            chunks,
+        })
+        .collect())
+}
+
+/// Describes the codebase made of `repository` at `branch` in the `table_name` table, or returns
+/// `None` when it holds no chunks.
+pub async fn describe_codebase(
+    db_file_uri: &str,
+    table_name: &str,
+    repository: &str,
+    branch: Option<&str>,
+) -> Result<Option<CodebaseDescription>> {
+    let predicate = codebase_predicate(repository, branch);
+    let Some(dataframe) = read_columns(
+        db_file_uri,
+        table_name,
+        Some(predicate),
+        &["file_path", "commit", "classification", "last_modified"],
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    if dataframe.height() == 0 {
+        return Ok(None);
+    }
+
+    let mut files = HashSet::new();
+    let mut commits = BTreeSet::new();
+    let mut classifications = BTreeMap::new();
+    for ((file_path, commit), classification) in dataframe
+        .column("file_path")?
+        .str()?
+        .iter()
+        .zip(dataframe.column("commit")?.str()?)
+        .zip(dataframe.column("classification")?.str()?)
+    {
+        files.insert(file_path.unwrap_or_default());
+        commits.extend(commit);
+        *classifications
+            .entry(classification.unwrap_or_default().to_string())
+            .or_default() += 1;
+    }
+    let last_modified = dataframe.column("last_modified")?.i64()?.max();
+
+    Ok(Some(CodebaseDescription {
+        codebase: Codebase {
+            repository: Some(repository.to_string()),
+            branch: branch.map(str::to_string),
+            files: files.len() as u64,
+            chunks: dataframe.height() as u64,
+        },
+        commits: commits.into_iter().map(str::to_string).collect(),
+        classifications,
+        last_modified,
+    }))
+}
+
+/// Removes the codebase made of `repository` at `branch` from the `table_name` table and returns
+/// the number of chunks removed.
+pub async fn drop_codebase(
+    db_file_uri: &str,
+    table_name: &str,
+    repository: &str,
+    branch: Option<&str>,
+) -> Result<u64> {
+    delete_rows(
+        db_file_uri,
+        table_name,
+        &codebase_predicate(repository, branch),
+    )
+    .await
+}
+
+/// Reads `columns` of the rows matching `predicate`, or `None` if there is no such table.
+async fn read_columns(
+    db_file_uri: &str,
+    table_name: &str,
+    predicate: Option<String>,
+    columns: &[&str],
+) -> Result<Option<DataFrame>> {
+    let Some(table) = open_table(db_file_uri, table_name).await? else {
+        return Ok(None);
+    };
+
+    let mut query = table.query().select(Select::columns(columns));
+    if let Some(predicate) = predicate {
+        query = query.only_if(predicate);
+    }
+    Ok(Some(query.execute().await?.into_polars().await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{DEFAULT_TABLE_NAME, index};
+    use polars::df;
+    use polars::prelude::*;
+    use tempfile::TempDir;
+
+    async fn create_test_table() -> (TempDir, String) {
+        let temp_dir = TempDir::new().unwrap();
+        let parquet_path = temp_dir.path().join("test.parquet");
+        let db_path_str = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+
+        let mut df = df!(
+            "file_path" => &["src/lib.rs", "src/lib.rs", "src/main.rs", "src/lib.rs", "lib.py"],
+            "repository" => &[Some("repo"), Some("repo"), Some("repo"), Some("repo"), Some("other")],
+            "branch" => &[Some("main"), Some("main"), Some("main"), Some("dev"), None],
+            "commit" => &[Some("a1"), Some("a1"), Some("b2"), Some("c3"), None],
+            "classification" => &["source", "source", "generated", "source", "vendored"],
+            "last_modified" => &[Some(10i64), Some(30), Some(20), None, None]
+        )
+        .unwrap();
+        let mut file = std::fs::File::create(&parquet_path).unwrap();
+        ParquetWriter::new(&mut file).finish(&mut df).unwrap();
+        index(
+            parquet_path.to_string_lossy().to_string(),
+            db_path_str.clone(),
+        )
+        .await
+        .unwrap();
+
+        (temp_dir, db_path_str)
+    }
+
+    #[tokio::test]
+    async fn test_list_codebases() -> anyhow::Result<()> {
+        let (_temp_dir, db_path_str) = create_test_table().await;
+
+        let codebases = list_codebases(&db_path_str, DEFAULT_TABLE_NAME).await?;
+        assert_eq!(
+            codebases,
+            vec![
+                Codebase {
+                    repository: Some("other".to_string()),
+                    branch: None,
+                    files: 1,
+                    chunks: 1,
+                },
+                Codebase {
+                    repository: Some("repo".to_string()),
+                    branch: Some("dev".to_string()),
+                    files: 1,
+                    chunks: 1,
+                },
+                Codebase {
+                    repository: Some("repo".to_string()),
+                    branch: Some("main".to_string()),
+                    files: 2,
+                    chunks: 3,
+                },
+            ]
+        );
+        assert!(list_codebases(&db_path_str, "missing").await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_describe_codebase() -> anyhow::Result<()> {
+        let (_temp_dir, db_path_str) = create_test_table().await;
+
+        let description = describe_codebase(&db_path_str, DEFAULT_TABLE_NAME, "repo", Some("main"))
+            .await?
+            .unwrap();
+        assert_eq!(description.codebase.files, 2);
+        assert_eq!(description.codebase.chunks, 3);
+        assert_eq!(description.commits, vec!["a1", "b2"]);
+        assert_eq!(
+            description.classifications,
+            BTreeMap::from([("generated".to_string(), 1), ("source".to_string(), 2)])
+        );
+        assert_eq!(description.last_modified, Some(30));
+
+        let description = describe_codebase(&db_path_str, DEFAULT_TABLE_NAME, "other", None)
+            .await?
+            .unwrap();
+        assert_eq!(description.codebase.chunks, 1);
+        assert!(description.commits.is_empty());
+        assert_eq!(description.last_modified, None);
+
+        assert!(
+            describe_codebase(&db_path_str, DEFAULT_TABLE_NAME, "repo", Some("missing"))
+                .await?
+                .is_none()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drop_codebase() -> anyhow::Result<()> {
+        let (_temp_dir, db_path_str) = create_test_table().await;
+
+        assert_eq!(
+            drop_codebase(&db_path_str, DEFAULT_TABLE_NAME, "repo", Some("main")).await?,
+            3
+        );
+        let codebases = list_codebases(&db_path_str, DEFAULT_TABLE_NAME).await?;
+        assert_eq!(codebases.len(), 2);
+        assert!(
+            codebases
+                .iter()
+                .all(|x| x.branch.as_deref() != Some("main"))
+        );
+        Ok(())
+    }
+}
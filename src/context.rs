@@ -0,0 +1,527 @@
+use crate::languages::get_language_for_path;
+use crate::search::{self, SearchOptions, SearchResult};
+use crate::symbols::{Symbol, find_symbols};
+use crate::{Error, Result};
+use code_splitter::Sizer;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+
+static TOKENIZER: OnceLock<CoreBPE> = OnceLock::new();
+
+/// Number of tokens a context is packed into by default.
+pub const DEFAULT_TOKEN_BUDGET: usize = 4000;
+
+#[derive(Debug, Clone)]
+pub struct ContextOptions {
+    /// Options for the search the context is built from. The more results, the more there is to
+    /// fill the budget with.
+    pub search: SearchOptions,
+    /// Maximum number of tokens of the context text, counted like `count_tokens` does, one
+    /// section at a time.
+    pub token_budget: usize,
+    /// Widens results to the innermost definitions enclosing them, unless those do not fit the
+    /// budget.
+    pub expand_symbols: bool,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        ContextOptions {
+            search: SearchOptions::default(),
+            token_budget: DEFAULT_TOKEN_BUDGET,
+            expand_symbols: true,
+        }
+    }
+}
+
+/// Lines of a file put in a context, from one or more search results.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContextSection {
+    pub file_path: String,
+    /// First row of the section, counted from 0 like those of chunks.
+    pub start_line: u64,
+    /// Row after the last one of the section.
+    pub end_line: u64,
+    pub language: String,
+    /// Names of the definitions the results belong to, or were widened to.
+    pub symbols: Vec<String>,
+    /// IDs of the chunks merged into the section.
+    pub chunk_ids: Vec<String>,
+    /// Best relevance of the merged chunks.
+    pub score: f32,
+    pub text: String,
+}
+
+/// Context for a query, to put in a prompt.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Context {
+    pub query: String,
+    /// Sections in the order of `text`, most relevant first.
+    pub sections: Vec<ContextSection>,
+    /// The sections as Markdown code blocks, each headed by its path and lines counted from 1.
+    pub text: String,
+    /// Number of tokens of `text`, summed over the sections and the newlines between them.
+    pub tokens: usize,
+    pub token_budget: usize,
+    /// Number of search results left out for lack of budget.
+    pub omitted_chunks: usize,
+}
+
+/// Lines of a file, or of a chunk when its file cannot be read.
+struct Source {
+    file_path: String,
+    language: String,
+    /// Row of the first line.
+    first_row: usize,
+    lines: Vec<String>,
+}
+
+/// Rows `start_row..end_row` of a source, with the search results they hold.
+#[derive(Debug, Clone, PartialEq)]
+struct Span {
+    start_row: usize,
+    end_row: usize,
+    score: f32,
+    chunk_ids: Vec<String>,
+    symbols: Vec<String>,
+}
+
+impl Span {
+    fn new(result: &SearchResult) -> Self {
+        let start_row = result.start_line as usize;
+        Span {
+            start_row,
+            end_row: (result.end_line as usize).max(start_row + 1),
+            score: result.rerank_score.unwrap_or(result.score),
+            chunk_ids: vec![result.id.clone()],
+            symbols: result.symbol_name.iter().cloned().collect(),
+        }
+    }
+
+    /// Widens the span to the innermost of `symbols` enclosing it, if any.
+    fn expand(&self, symbols: &[Symbol]) -> Span {
+        let mut span = self.clone();
+        // Symbols come outer ones first, so the last enclosing one is the innermost.
+        let enclosing = symbols
+            .iter()
+            .rev()
+            .find(|x| x.start_row <= span.start_row && x.end_row + 1 >= span.end_row);
+        if let Some(symbol) = enclosing {
+            span.start_row = symbol.start_row;
+            span.end_row = symbol.end_row + 1;
+            span.add_symbols(symbol.name.iter().cloned());
+        }
+        span
+    }
+
+    fn add_symbols(&mut self, symbols: impl IntoIterator<Item = String>) {
+        for symbol in symbols {
+            if !self.symbols.contains(&symbol) {
+                self.symbols.push(symbol);
+            }
+        }
+    }
+}
+
+/// A span to put in the context, with the smaller spans to try instead when it does not fit.
+struct Candidate {
+    source: usize,
+    span: Span,
+    fallbacks: Vec<Span>,
+}
+
+/// Searches the database at `db_file_uri` for `query` and packs the results into a context of
+/// at most `options.token_budget` tokens.
+///
+/// Results from the same file that overlap or touch are merged, and widened to the definitions
+/// enclosing them, read from the file on disk. The most relevant sections go first; a section
+/// that does not fit is left out for smaller ones, or for the unwidened results it was made of.
+pub async fn build_context(
+    db_file_uri: &str,
+    query: &str,
+    options: &ContextOptions,
+) -> Result<Context> {
+    let results = search::search(db_file_uri, query, &options.search).await?;
+    let (sources, mut candidates) = find_candidates(&results, options.expand_symbols);
+    candidates.sort_by(|a, b| b.span.score.total_cmp(&a.span.score));
+
+    let mut context = Context {
+        query: query.to_string(),
+        sections: vec![],
+        text: String::new(),
+        tokens: 0,
+        token_budget: options.token_budget,
+        omitted_chunks: 0,
+    };
+    for candidate in candidates {
+        let source = &sources[candidate.source];
+        let chunks = candidate.span.chunk_ids.len();
+        if context.try_add(source, candidate.span)? {
+            continue;
+        }
+        if candidate.fallbacks.is_empty() {
+            context.omitted_chunks += chunks;
+        }
+        for span in candidate.fallbacks {
+            let chunks = span.chunk_ids.len();
+            if !context.try_add(source, span)? {
+                context.omitted_chunks += chunks;
+            }
+        }
+    }
+    Ok(context)
+}
+
+/// Counts the tokens of `text` with the `cl100k_base` encoding of OpenAI models.
+pub fn count_tokens(text: &str) -> Result<usize> {
+    let tokenizer = TOKENIZER
+        .get_or_try_init(tiktoken_rs::cl100k_base)
+        .map_err(|e| Error::Tokenizer(e.to_string()))?;
+    tokenizer
+        .size(text.as_bytes())
+        .map_err(|e| Error::Tokenizer(e.to_string()))
+}
+
+impl Context {
+    /// Appends `span` of `source` if the text stays within the budget, returning whether it did.
+    fn try_add(&mut self, source: &Source, span: Span) -> Result<bool> {
+        let section = ContextSection {
+            file_path: source.file_path.clone(),
+            start_line: span.start_row as u64,
+            end_line: span.end_row as u64,
+            language: source.language.clone(),
+            symbols: span.symbols,
+            chunk_ids: span.chunk_ids,
+            score: span.score,
+            text: source.lines[span.start_row - source.first_row..span.end_row - source.first_row]
+                .join("\n"),
+        };
+        let mut addition = String::new();
+        if !self.text.is_empty() {
+            addition.push('\n');
+        }
+        addition.push_str(&to_markdown(&section));
+        // Only the new text is counted, so that filling the budget takes linear time.
+        let tokens = self.tokens + count_tokens(&addition)?;
+        if tokens > self.token_budget {
+            return Ok(false);
+        }
+        self.sections.push(section);
+        self.text.push_str(&addition);
+        self.tokens = tokens;
+        Ok(true)
+    }
+}
+
+/// Groups `results` by file and merges them into candidates, widened to their definitions if
+/// `expand_symbols` is set. Results whose file cannot be read, or no longer holds their text at
+/// their lines, are candidates on their own, with the indexed text.
+fn find_candidates(
+    results: &[SearchResult],
+    expand_symbols: bool,
+) -> (Vec<Source>, Vec<Candidate>) {
+    let mut file_paths: Vec<&str> = vec![];
+    for result in results {
+        if !file_paths.contains(&result.file_path.as_str()) {
+            file_paths.push(&result.file_path);
+        }
+    }
+
+    let mut sources = vec![];
+    let mut candidates = vec![];
+    for file_path in file_paths {
+        let text = fs::read_to_string(file_path).unwrap_or_default();
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let (file_results, stale_results): (Vec<&SearchResult>, Vec<&SearchResult>) = results
+            .iter()
+            .filter(|x| x.file_path == file_path)
+            .partition(|x| holds_text(&lines, x));
+        for result in stale_results {
+            let mut lines: Vec<String> = result.text.lines().map(str::to_string).collect();
+            if lines.is_empty() {
+                lines.push(String::new());
+            }
+            let mut span = Span::new(result);
+            // The chunk text is all there is to show, whatever rows it was recorded with.
+            span.end_row = span.start_row + lines.len();
+            candidates.push(Candidate {
+                source: sources.len(),
+                span,
+                fallbacks: vec![],
+            });
+            sources.push(Source {
+                file_path: file_path.to_string(),
+                language: result.language.clone(),
+                first_row: result.start_line as usize,
+                lines,
+            });
+        }
+        if file_results.is_empty() {
+            continue;
+        }
+
+        let spans: Vec<Span> = file_results.iter().map(|x| Span::new(x)).collect();
+        let symbols = match get_language_for_path(Path::new(file_path)) {
+            Some(language) if expand_symbols => find_symbols(language, &text),
+            _ => vec![],
+        };
+        candidates.extend(file_candidates(sources.len(), spans, &symbols));
+        sources.push(Source {
+            file_path: file_path.to_string(),
+            language: file_results[0].language.clone(),
+            first_row: 0,
+            lines,
+        });
+    }
+    (sources, candidates)
+}
+
+/// Returns whether the rows of `result` in `lines` still hold its text, which may start or end
+/// within them.
+fn holds_text(lines: &[String], result: &SearchResult) -> bool {
+    let start_row = result.start_line as usize;
+    if start_row >= lines.len() || result.end_line as usize > lines.len() {
+        return false;
+    }
+    // The last row is included, as chunks ending within a line record it as their end.
+    let end_row = (result.end_line as usize + 1).min(lines.len());
+    // Lines are compared without their line breaks, which `lines` does not keep.
+    let text: Vec<&str> = result.text.lines().collect();
+    lines[start_row..end_row]
+        .join("\n")
+        .contains(text.join("\n").trim())
+}
+
+/// Merges `spans` of the same source, widened to the definitions in `symbols`. Each widened span
+/// falls back to the merged spans it was widened from, most relevant first.
+fn file_candidates(source: usize, spans: Vec<Span>, symbols: &[Symbol]) -> Vec<Candidate> {
+    let expanded = merge_spans(spans.iter().map(|x| x.expand(symbols)).collect());
+    let mut merged = merge_spans(spans);
+    merged.sort_by(|a, b| b.score.total_cmp(&a.score));
+    expanded
+        .into_iter()
+        .map(|span| {
+            // Every merged span lies within one widened span, as widening only grows spans.
+            let fallbacks: Vec<Span> = merged
+                .iter()
+                .filter(|x| x.start_row >= span.start_row && x.end_row <= span.end_row)
+                .filter(|x| (x.start_row, x.end_row) != (span.start_row, span.end_row))
+                .cloned()
+                .collect();
+            Candidate {
+                source,
+                span,
+                fallbacks,
+            }
+        })
+        .collect()
+}
+
+/// Merges the spans that overlap or touch, in the order of their rows.
+fn merge_spans(mut spans: Vec<Span>) -> Vec<Span> {
+    spans.sort_by_key(|x| x.start_row);
+    let mut merged: Vec<Span> = Vec::new();
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start_row <= last.end_row => {
+                last.end_row = last.end_row.max(span.end_row);
+                last.score = last.score.max(span.score);
+                last.chunk_ids.extend(span.chunk_ids);
+                last.add_symbols(span.symbols);
+            }
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+/// Renders `section` as a code block headed by its path, lines counted from 1 and definitions.
+fn to_markdown(section: &ContextSection) -> String {
+    let mut heading = format!(
+        "{}:{}-{}",
+        section.file_path,
+        section.start_line + 1,
+        section.end_line
+    );
+    if !section.symbols.is_empty() {
+        heading.push_str(&format!(" ({})", section.symbols.join(", ")));
+    }
+    // The fence is longer than any run of backticks in the code, so that it cannot close early.
+    let longest_run = section
+        .text
+        .split(|x| x != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!(
+        "{heading}\n{fence}{}\n{}\n{fence}\n",
+        section.language, section.text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::tests::index_directory;
+    use crate::split::SymbolKind;
+    use tempfile::TempDir;
+
+    fn span(start_row: usize, end_row: usize, score: f32, id: &str) -> Span {
+        Span {
+            start_row,
+            end_row,
+            score,
+            chunk_ids: vec![id.to_string()],
+            symbols: vec![],
+        }
+    }
+
+    fn rows(spans: &[Span]) -> Vec<(usize, usize)> {
+        spans.iter().map(|x| (x.start_row, x.end_row)).collect()
+    }
+
+    #[test]
+    fn test_merge_spans() {
+        let merged = merge_spans(vec![
+            span(10, 14, 0.5, "c"),
+            span(0, 4, 0.2, "a"),
+            span(4, 6, 0.9, "b"),
+            span(12, 20, 0.1, "d"),
+            span(21, 22, 0.3, "e"),
+        ]);
+        assert_eq!(rows(&merged), vec![(0, 6), (10, 20), (21, 22)]);
+        assert_eq!(merged[0].score, 0.9);
+        assert_eq!(merged[0].chunk_ids, vec!["a", "b"]);
+        assert_eq!(merged[1].chunk_ids, vec!["c", "d"]);
+    }
+
+    #[test]
+    fn test_file_candidates() {
+        let symbols = vec![
+            Symbol {
+                kind: SymbolKind::Impl,
+                name: Some("Point".to_string()),
+                start_row: 0,
+                end_row: 30,
+            },
+            Symbol {
+                kind: SymbolKind::Method,
+                name: Some("norm".to_string()),
+                start_row: 2,
+                end_row: 9,
+            },
+            Symbol {
+                kind: SymbolKind::Method,
+                name: Some("scale".to_string()),
+                start_row: 10,
+                end_row: 19,
+            },
+        ];
+        let candidates = file_candidates(
+            0,
+            vec![
+                span(4, 6, 0.9, "a"),
+                span(12, 15, 0.5, "b"),
+                span(40, 45, 0.3, "c"),
+            ],
+            &symbols,
+        );
+        let spans: Vec<Span> = candidates.iter().map(|x| x.span.clone()).collect();
+        assert_eq!(rows(&spans), vec![(2, 20), (40, 45)]);
+        assert_eq!(spans[0].symbols, vec!["norm", "scale"]);
+        assert_eq!(spans[0].score, 0.9);
+        assert_eq!(rows(&candidates[0].fallbacks), vec![(4, 6), (12, 15)]);
+        // Spans outside any definition are kept as they are, with nothing to fall back to.
+        assert!(candidates[1].fallbacks.is_empty());
+    }
+
+    #[test]
+    fn test_to_markdown() -> anyhow::Result<()> {
+        let section = ContextSection {
+            file_path: "src/lib.rs".to_string(),
+            start_line: 2,
+            end_line: 4,
+            language: "rust".to_string(),
+            symbols: vec!["main".to_string()],
+            chunk_ids: vec!["a".to_string()],
+            score: 1.0,
+            text: "/// ```\nfn main() {}".to_string(),
+        };
+        assert_eq!(
+            to_markdown(&section),
+            "src/lib.rs:3-4 (main)\n````rust\n/// ```\nfn main() {}\n````\n"
+        );
+        assert!(count_tokens(&section.text)? > 0);
+        assert_eq!(count_tokens("")?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_context() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(&root)?;
+        fs::write(
+            root.join("math.py"),
+            "def add_numbers(first, second):\n    return first + second\n",
+        )?;
+        fs::write(
+            root.join("greeting.js"),
+            "function greet(name) {\n    console.log(\"Hello, \" + name);\n}\n",
+        )?;
+
+        let db_path_str = index_directory(temp_dir.path(), &root).await?;
+
+        let context = build_context(
+            &db_path_str,
+            "sum of two numbers",
+            &ContextOptions::default(),
+        )
+        .await?;
+        assert_eq!(context.sections.len(), 2);
+        assert_eq!(context.omitted_chunks, 0);
+        assert!(context.sections[0].file_path.ends_with("math.py"));
+        assert!(context.sections[0].text.contains("return first + second"));
+        assert!(
+            context
+                .text
+                .contains("math.py:1-2 (add_numbers)\n```python\n")
+        );
+        assert_eq!(
+            context.tokens,
+            count_tokens(&to_markdown(&context.sections[0]))?
+                + count_tokens(&format!("\n{}", to_markdown(&context.sections[1])))?
+        );
+
+        // A budget that fits the first section alone leaves the second one out.
+        let first = to_markdown(&context.sections[0]);
+        let options = ContextOptions {
+            token_budget: count_tokens(&first)?,
+            ..Default::default()
+        };
+        let context = build_context(&db_path_str, "sum of two numbers", &options).await?;
+        assert_eq!(context.sections.len(), 1);
+        assert_eq!(context.text, first);
+        assert_eq!(context.omitted_chunks, 1);
+        assert!(context.tokens <= options.token_budget);
+
+        // Files changed since they were indexed are not trusted for the lines of their chunks.
+        fs::write(
+            root.join("math.py"),
+            "def add_numbers(first, second):\n    return second + first\n",
+        )?;
+        let context = build_context(
+            &db_path_str,
+            "sum of two numbers",
+            &ContextOptions::default(),
+        )
+        .await?;
+        assert!(context.sections[0].file_path.ends_with("math.py"));
+        assert!(context.sections[0].text.contains("return first + second"));
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+use crate::Result;
+use crate::filter::SearchFilter;
+use crate::index::{DEFAULT_TABLE_NAME, EMBEDDING_COLUMN, open_existing_table};
+use crate::locations::{ChunkLocation, LOCATION_COLUMNS, read_locations};
+use crate::search::read_embeddings;
+use lancedb::arrow::IntoPolars;
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use lancedb::{DistanceType, Table};
+use polars::prelude::DataFrame;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io;
+use tokio::task::JoinSet;
+
+/// Chunks at least this similar are reported as duplicates by default.
+pub const DEFAULT_SIMILARITY_THRESHOLD: f32 = 0.95;
+
+/// Number of nearest chunks compared with every chunk by default.
+pub const DEFAULT_MAX_NEIGHBORS: usize = 10;
+
+/// Number of chunks whose embeddings are read at a time.
+const EMBEDDING_BATCH_SIZE: usize = 1024;
+
+/// Number of nearest chunk searches run at the same time.
+const MAX_CONCURRENT_SEARCHES: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct DuplicateOptions {
+    /// Table to look for duplicates in.
+    pub table_name: String,
+    /// Lowest cosine similarity between the embeddings of two chunks for them to be duplicates.
+    pub threshold: f32,
+    /// Number of nearest chunks compared with every chunk. Higher values find more members of
+    /// large clusters directly, at the cost of one longer search per chunk.
+    pub max_neighbors: usize,
+    /// Chunks to compare. A minimum line count keeps short chunks, such as lone closing braces,
+    /// from flooding the report.
+    pub filter: SearchFilter,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        DuplicateOptions {
+            table_name: DEFAULT_TABLE_NAME.to_string(),
+            threshold: DEFAULT_SIMILARITY_THRESHOLD,
+            max_neighbors: DEFAULT_MAX_NEIGHBORS,
+            filter: SearchFilter::default(),
+        }
+    }
+}
+
+/// Two chunks whose embeddings are at least as similar as the threshold.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicatePair {
+    pub first: ChunkLocation,
+    pub second: ChunkLocation,
+    /// Cosine similarity between the embeddings of the chunks.
+    pub similarity: f32,
+}
+
+/// Chunks connected by duplicate pairs, directly or through other chunks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateCluster {
+    /// Chunks of the cluster, sorted by file path and line.
+    pub chunks: Vec<ChunkLocation>,
+    /// Lowest similarity among the pairs in the cluster.
+    pub min_similarity: f32,
+    /// Highest similarity among the pairs in the cluster.
+    pub max_similarity: f32,
+}
+
+/// Near-duplicate chunks found in a table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateReport {
+    pub threshold: f32,
+    /// Number of chunks compared.
+    pub chunks_compared: u64,
+    /// Duplicate pairs, most similar first.
+    pub pairs: Vec<DuplicatePair>,
+    /// Clusters of duplicates, largest first.
+    pub clusters: Vec<DuplicateCluster>,
+}
+
+impl DuplicateReport {
+    /// Renders the report as a Markdown document, with lines counted from 1.
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from("# Duplicate code report\n\n");
+        // Writing to a `String` cannot fail.
+        let _ = writeln!(
+            markdown,
+            "{} chunks compared with a similarity threshold of {:.2}: {} duplicate pairs in {} clusters.",
+            self.chunks_compared,
+            self.threshold,
+            self.pairs.len(),
+            self.clusters.len()
+        );
+
+        for (i, cluster) in self.clusters.iter().enumerate() {
+            let _ = writeln!(
+                markdown,
+                "\n## Cluster {} ({} chunks, similarity {:.3} to {:.3})\n",
+                i + 1,
+                cluster.chunks.len(),
+                cluster.min_similarity,
+                cluster.max_similarity
+            );
+            for chunk in &cluster.chunks {
+                let _ = writeln!(markdown, "- `{}`", chunk.reference());
+            }
+        }
+
+        if !self.pairs.is_empty() {
+            markdown
+                .push_str("\n## Pairs\n\n| Similarity | First | Second |\n| --- | --- | --- |\n");
+            for pair in &self.pairs {
+                let _ = writeln!(
+                    markdown,
+                    "| {:.3} | `{}` | `{}` |",
+                    pair.similarity,
+                    pair.first.reference(),
+                    pair.second.reference()
+                );
+            }
+        }
+        markdown
+    }
+}
+
+/// Finds the pairs and clusters of chunks in the `options.table_name` table of the database at
+/// `db_file_uri` whose embeddings are at least `options.threshold` similar.
+///
+/// Every chunk matching `options.filter` is compared with its `options.max_neighbors` nearest
+/// chunks matching the filter, by cosine distance. This takes one vector search per chunk; the
+/// embeddings are read in batches and a few searches run at once, but without a vector index
+/// built with the cosine metric every search scans the whole table, so the time taken grows with
+/// the square of the number of chunks.
+pub async fn find_duplicates(
+    db_file_uri: &str,
+    options: &DuplicateOptions,
+) -> Result<DuplicateReport> {
+    let table = open_existing_table(db_file_uri, &options.table_name).await?;
+    let predicate = options.filter.to_sql()?;
+
+    // Every location ends up in the report, but embeddings are only needed one batch at a time.
+    let mut query = table.query().select(Select::columns(LOCATION_COLUMNS));
+    if let Some(predicate) = &predicate {
+        query = query.only_if(predicate);
+    }
+    let dataframe = query.execute().await?.into_polars().await?;
+    if dataframe.height() == 0 {
+        return Ok(build_report(vec![], BTreeMap::new(), options.threshold));
+    }
+    let chunks = read_locations(&dataframe)?;
+    let indices: HashMap<&str, usize> = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.id.as_str(), i))
+        .collect();
+
+    let mut pairs = BTreeMap::new();
+    let mut searches = JoinSet::new();
+    for offset in (0..chunks.len()).step_by(EMBEDDING_BATCH_SIZE) {
+        let mut query = table
+            .query()
+            .select(Select::columns(&["id", EMBEDDING_COLUMN]))
+            .limit(EMBEDDING_BATCH_SIZE)
+            .offset(offset);
+        if let Some(predicate) = &predicate {
+            query = query.only_if(predicate);
+        }
+        let batch = query.execute().await?.into_polars().await?;
+        if batch.height() == 0 {
+            break;
+        }
+        let ids = batch.column("id")?.str()?;
+        for (id, embedding) in ids.iter().zip(read_embeddings(&batch)?) {
+            let (Some(&i), Some(embedding)) = (id.and_then(|x| indices.get(x)), embedding) else {
+                continue;
+            };
+            while searches.len() >= MAX_CONCURRENT_SEARCHES {
+                let Some(result) = searches.join_next().await else {
+                    break;
+                };
+                let (j, neighbors) = result.map_err(io::Error::from)??;
+                add_pairs(&mut pairs, &indices, j, &neighbors, options.threshold)?;
+            }
+            let (table, predicate) = (table.clone(), predicate.clone());
+            // The nearest chunk is the chunk itself.
+            let limit = options.max_neighbors + 1;
+            searches.spawn(async move {
+                let neighbors = nearest_chunks(&table, embedding, predicate, limit).await;
+                neighbors.map(|x| (i, x))
+            });
+        }
+    }
+    while let Some(result) = searches.join_next().await {
+        let (i, neighbors) = result.map_err(io::Error::from)??;
+        add_pairs(&mut pairs, &indices, i, &neighbors, options.threshold)?;
+    }
+    Ok(build_report(chunks, pairs, options.threshold))
+}
+
+/// Returns the IDs and cosine distances of the `limit` chunks matching `predicate` nearest to
+/// `embedding`.
+async fn nearest_chunks(
+    table: &Table,
+    embedding: Vec<f32>,
+    predicate: Option<String>,
+    limit: usize,
+) -> Result<DataFrame> {
+    let mut query = table
+        .vector_search(embedding)?
+        .column(EMBEDDING_COLUMN)
+        .distance_type(DistanceType::Cosine)
+        .select(Select::columns(&["id"]))
+        .limit(limit);
+    if let Some(predicate) = predicate {
+        query = query.only_if(predicate);
+    }
+    Ok(query.execute().await?.into_polars().await?)
+}
+
+/// Adds the pairs of the `i`-th chunk with its `neighbors` that are at least `threshold` similar.
+fn add_pairs(
+    pairs: &mut BTreeMap<(usize, usize), f32>,
+    indices: &HashMap<&str, usize>,
+    i: usize,
+    neighbors: &DataFrame,
+    threshold: f32,
+) -> Result<()> {
+    if neighbors.height() == 0 {
+        return Ok(());
+    }
+    let ids = neighbors.column("id")?.str()?;
+    let distances = neighbors.column("_distance")?.f32()?;
+    for (id, distance) in ids.iter().zip(distances) {
+        let (Some(&j), Some(distance)) = (id.and_then(|x| indices.get(x)), distance) else {
+            continue;
+        };
+        let similarity = 1.0 - distance;
+        if j != i && similarity >= threshold {
+            pairs.insert((i.min(j), i.max(j)), similarity);
+        }
+    }
+    Ok(())
+}
+
+/// Builds the report for the duplicate `pairs` of indices into `chunks`, grouping them into the
+/// connected components of the graph they form.
+fn build_report(
+    chunks: Vec<ChunkLocation>,
+    pairs: BTreeMap<(usize, usize), f32>,
+    threshold: f32,
+) -> DuplicateReport {
+    // Union-find over chunk indices, where every component is rooted at its lowest index.
+    let mut parents: Vec<usize> = (0..chunks.len()).collect();
+    for &(i, j) in pairs.keys() {
+        let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+        parents[root_i.max(root_j)] = root_i.min(root_j);
+    }
+
+    let mut components: BTreeMap<usize, (Vec<usize>, f32, f32)> = BTreeMap::new();
+    for (&(i, j), &similarity) in &pairs {
+        let root = find_root(&mut parents, i);
+        let (members, min, max) =
+            components
+                .entry(root)
+                .or_insert((vec![], f32::INFINITY, f32::NEG_INFINITY));
+        members.extend([i, j]);
+        *min = min.min(similarity);
+        *max = max.max(similarity);
+    }
+    let mut clusters: Vec<DuplicateCluster> = components
+        .into_values()
+        .map(|(mut members, min_similarity, max_similarity)| {
+            members.sort_unstable();
+            members.dedup();
+            let mut locations: Vec<ChunkLocation> =
+                members.into_iter().map(|x| chunks[x].clone()).collect();
+            locations
+                .sort_by(|x, y| (&x.file_path, x.start_line).cmp(&(&y.file_path, y.start_line)));
+            DuplicateCluster {
+                chunks: locations,
+                min_similarity,
+                max_similarity,
+            }
+        })
+        .collect();
+    // The sort is stable, and components are visited in order of their first chunk.
+    clusters.sort_by(|x, y| y.chunks.len().cmp(&x.chunks.len()));
+
+    let mut pairs: Vec<DuplicatePair> = pairs
+        .into_iter()
+        .map(|((i, j), similarity)| DuplicatePair {
+            first: chunks[i].clone(),
+            second: chunks[j].clone(),
+            similarity,
+        })
+        .collect();
+    pairs.sort_by(|x, y| y.similarity.total_cmp(&x.similarity));
+
+    DuplicateReport {
+        threshold,
+        chunks_compared: chunks.len() as u64,
+        pairs,
+        clusters,
+    }
+}
+
+/// Returns the root of the component of `i`, halving the path to it on the way.
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::tests::index_directory;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn location(id: &str, file_path: &str, start_line: u64) -> ChunkLocation {
+        ChunkLocation {
+            id: id.to_string(),
+            file_path: file_path.to_string(),
+            start_line,
+            end_line: start_line + 10,
+            repository: None,
+            branch: None,
+        }
+    }
+
+    #[test]
+    fn test_build_report() {
+        let chunks = vec![
+            location("a", "src/a.rs", 0),
+            location("b", "src/b.rs", 20),
+            location("c", "src/c.rs", 0),
+            location("d", "src/d.rs", 0),
+            location("e", "src/e.rs", 0),
+            location("f", "src/f.rs", 0),
+        ];
+        let pairs = BTreeMap::from([((0, 1), 0.97), ((1, 2), 0.99), ((3, 4), 0.96)]);
+
+        let report = build_report(chunks, pairs, 0.95);
+        assert_eq!(report.chunks_compared, 6);
+        assert_eq!(
+            report
+                .pairs
+                .iter()
+                .map(|x| (x.first.id.as_str(), x.second.id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("b", "c"), ("a", "b"), ("d", "e")]
+        );
+        assert_eq!(report.clusters.len(), 2);
+        assert_eq!(
+            report.clusters[0]
+                .chunks
+                .iter()
+                .map(|x| x.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(report.clusters[0].min_similarity, 0.97);
+        assert_eq!(report.clusters[0].max_similarity, 0.99);
+        assert_eq!(report.clusters[1].chunks.len(), 2);
+
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("6 chunks compared"));
+        assert!(markdown.contains("## Cluster 1 (3 chunks, similarity 0.970 to 0.990)"));
+        assert!(markdown.contains("| 0.990 | `src/b.rs:21-30` | `src/c.rs:1-10` |"));
+    }
+
+    #[tokio::test]
+    async fn test_find_duplicates() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(&root)?;
+        let function = r#"
+def read_settings(path):
+    with open(path) as file:
+        settings = json.load(file)
+    settings.setdefault("timeout", 30)
+    return settings
+"#;
+        fs::write(root.join("first.py"), function)?;
+        fs::write(root.join("second.py"), function)?;
+        fs::write(
+            root.join("other.js"),
+            "function greet(name) {\n    console.log(\"Hello, \" + name);\n}\n",
+        )?;
+
+        let db_path_str = index_directory(temp_dir.path(), &root).await?;
+
+        let report = find_duplicates(&db_path_str, &DuplicateOptions::default()).await?;
+        assert_eq!(report.chunks_compared, 3);
+        assert_eq!(report.pairs.len(), 1);
+        assert!(report.pairs[0].similarity > 0.99);
+        assert_eq!(report.clusters.len(), 1);
+        let mut file_names: Vec<&str> = report.clusters[0]
+            .chunks
+            .iter()
+            .map(|x| x.file_path.rsplit('/').next().unwrap())
+            .collect();
+        file_names.sort();
+        assert_eq!(file_names, vec!["first.py", "second.py"]);
+
+        let options = DuplicateOptions {
+            filter: SearchFilter::default().with_language("javascript"),
+            ..Default::default()
+        };
+        let report = find_duplicates(&db_path_str, &options).await?;
+        assert_eq!(report.chunks_compared, 1);
+        assert!(report.pairs.is_empty());
+        Ok(())
+    }
+}
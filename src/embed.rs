@@ -1,17 +1,22 @@
+use crate::Result;
 use anyhow::anyhow;
-use fastembed::{Embedding, EmbeddingModel, InitOptions, TextEmbedding};
+use fastembed::{
+    Embedding, EmbeddingModel, InitOptions, RerankInitOptions, RerankerModel, TextEmbedding,
+    TextRerank,
+};
 #[cfg(target_os = "macos")]
 use ort::execution_providers::{
     CoreMLExecutionProvider, ExecutionProvider, ExecutionProviderDispatch,
 };
 use polars::datatypes::DataType;
 use polars::datatypes::DataType::List;
-use polars::prelude::{Column, GetOutput, LazyFrame, ParquetWriter, col};
+use polars::prelude::{Column, GetOutput, LazyFrame, ParquetWriter, col, polars_err};
 use polars::series::Series;
 use std::fs;
 use std::sync::OnceLock;
 
 static TEXT_EMBEDDING_MODEL: OnceLock<TextEmbedding> = OnceLock::new();
+static TEXT_RERANK_MODEL: OnceLock<TextRerank> = OnceLock::new();
 
 #[cfg(target_os = "macos")]
 fn register_provider() -> anyhow::Result<ExecutionProviderDispatch> {
@@ -24,24 +29,44 @@ fn register_provider() -> anyhow::Result<ExecutionProviderDispatch> {
 }
 
 #[cfg(target_os = "windows")]
-fn register_provider() -> Result<ExecutionProviderDispatch, String> {
+fn register_provider() -> anyhow::Result<ExecutionProviderDispatch> {
     todo!()
 }
 
-fn get_text_embedding_model() -> anyhow::Result<&'static TextEmbedding> {
+fn get_text_embedding_model() -> Result<&'static TextEmbedding> {
     let execution_provider = register_provider()?;
-    TEXT_EMBEDDING_MODEL.get_or_try_init(|| {
+    let model = TEXT_EMBEDDING_MODEL.get_or_try_init(|| {
         TextEmbedding::try_new(
             InitOptions::new(EmbeddingModel::AllMiniLML6V2)
                 .with_execution_providers(vec![execution_provider]),
         )
-    })
+    })?;
+    Ok(model)
+}
+
+// The multilingual Jina reranker was also trained on code retrieval, unlike the English-only ones.
+fn get_text_rerank_model() -> Result<&'static TextRerank> {
+    let execution_provider = register_provider()?;
+    let model = TEXT_RERANK_MODEL.get_or_try_init(|| {
+        TextRerank::try_new(
+            RerankInitOptions::new(RerankerModel::JINARerankerV2BaseMultiligual)
+                .with_execution_providers(vec![execution_provider]),
+        )
+    })?;
+    Ok(model)
 }
 
-pub fn create_embeddings_from_file(
-    input_file_uri: String,
-    output_file_uri: String,
-) -> anyhow::Result<()> {
+/// Loads the embedding model, and the reranking model if `rerank` is set, so that long-running
+/// processes pay for it once at startup rather than on their first query.
+pub fn load_models(rerank: bool) -> Result<()> {
+    get_text_embedding_model()?;
+    if rerank {
+        get_text_rerank_model()?;
+    }
+    Ok(())
+}
+
+pub fn create_embeddings_from_file(input_file_uri: String, output_file_uri: String) -> Result<()> {
     let mut output_file = fs::File::create(output_file_uri)?;
 
     // Read a dataframe from a file.
@@ -54,15 +79,17 @@ pub fn create_embeddings_from_file(
         .with_column(col("text").alias("embedding").map_list(
             move |x| {
                 let as_string_chunked = x.as_series().unwrap().str()?;
-                let embeddings: Vec<Series> = as_string_chunked
-                    .into_iter()
-                    .flat_map(|y| {
-                        model
-                            .embed::<String>(vec![y.unwrap().into()], Some(32))
-                            .unwrap()
-                    })
-                    .map(|z| z.into_iter().collect::<Series>())
-                    .collect();
+                let mut embeddings: Vec<Series> = Vec::with_capacity(as_string_chunked.len());
+                for y in as_string_chunked.into_iter() {
+                    let embedding = model
+                        .embed::<String>(vec![y.unwrap_or_default().into()], Some(32))
+                        .map_err(|e| polars_err!(ComputeError: "failed to embed text: {e:#}"))?;
+                    embeddings.extend(
+                        embedding
+                            .into_iter()
+                            .map(|z| z.into_iter().collect::<Series>()),
+                    );
+                }
 
                 //let series = Series::new("embeddings".into(), &embeddings);
                 Ok(Some(Column::new("embedding".into(), &embeddings)))
@@ -75,12 +102,29 @@ pub fn create_embeddings_from_file(
     Ok(())
 }
 
-pub fn create_embeddings_from_string(input_string: String) -> anyhow::Result<Embedding> {
+pub fn create_embeddings_from_string(input_string: String) -> Result<Embedding> {
     let model: &TextEmbedding = get_text_embedding_model()?;
     let embedding = model.embed::<String>(vec![input_string], Some(32))?;
     Ok(embedding[0].clone())
 }
 
+/// Scores how relevant each of `documents` is to `query` with a cross-encoder, which reads both
+/// together and is more accurate, but much slower, than comparing embeddings.
+///
+/// Scores are returned in the order of `documents`, higher being more relevant.
+pub fn rerank(query: &str, documents: &[&str]) -> Result<Vec<f32>> {
+    if documents.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let model: &TextRerank = get_text_rerank_model()?;
+    let mut scores = vec![f32::NEG_INFINITY; documents.len()];
+    for result in model.rerank(query, documents.to_vec(), false, Some(32))? {
+        scores[result.index] = result.score;
+    }
+    Ok(scores)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,4 +233,17 @@ mod tests {
             assert!(!embedding.is_null());
         }
     }
+
+    #[test]
+    fn test_rerank() {
+        let documents = [
+            "function greet(name) {\n    console.log(\"Hello, \" + name);\n}",
+            "def add_numbers(first, second):\n    return first + second",
+        ];
+
+        let scores = rerank("add two numbers", &documents).unwrap();
+        assert_eq!(scores.len(), 2);
+        assert!(scores[1] > scores[0]);
+        assert!(rerank("add two numbers", &[]).unwrap().is_empty());
+    }
 }
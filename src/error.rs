@@ -0,0 +1,54 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("Polars error: {0}")]
+    Polars(#[from] polars::prelude::PolarsError),
+    #[error("LanceDB error: {0}")]
+    LanceDb(#[from] lancedb::Error),
+    #[error("git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("watch error: {0}")]
+    Watch(#[from] notify_debouncer_mini::notify::Error),
+    #[error("invalid glob: {0}")]
+    Glob(#[from] globset::Error),
+    #[error("no chunk with ID {0}")]
+    ChunkNotFound(String),
+    #[error("no chunks of {0} are indexed")]
+    FileNotIndexed(String),
+    #[error(
+        "table {table} lacks the columns {}, as it was created by an earlier version; drop it and index again, or index into another table",
+        .missing.join(", ")
+    )]
+    OutdatedTable { table: String, missing: Vec<String> },
+    #[error(
+        "rebuild threshold {0} is below {min}, the fewest rows product quantization can be trained on",
+        min = crate::index::MIN_REBUILD_THRESHOLD
+    )]
+    RebuildThresholdTooLow(usize),
+    #[error("reranking is disabled; start the server with --rerank")]
+    RerankingDisabled,
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("tokenizer error: {0}")]
+    Tokenizer(String),
+    #[error("failed to build the worker pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    // The embedding runtime reports its errors through `anyhow`.
+    #[error("embedding error: {0:#}")]
+    Embedding(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        Error::Embedding(error)
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
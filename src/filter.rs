@@ -0,0 +1,376 @@
+use crate::Result;
+use crate::index::{escape_like, quote_literal};
+use crate::split::{Classification, SymbolKind};
+use globset::GlobBuilder;
+use serde::Deserialize;
+use std::ops::{Bound, RangeBounds};
+
+/// Limits a search to some of the chunks in a table.
+///
+/// Every condition added with the same method matches chunks matching any of its values, except
+/// for excluded classifications, which leave out chunks matching any of them, and the filter
+/// matches chunks matching every kind of condition. The filter compiles to a LanceDB SQL
+/// predicate, which is applied before the nearest chunks are picked, so that a search still
+/// returns as many results as requested when few chunks match.
+///
+/// Filters deserialize from objects with any of the fields below, each holding a list of values
+/// except for the line counts and modification times, so that servers can take them in requests.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchFilter {
+    languages: Vec<String>,
+    path_globs: Vec<String>,
+    path_prefixes: Vec<String>,
+    file_names: Vec<String>,
+    repositories: Vec<String>,
+    branches: Vec<String>,
+    min_lines: Option<u64>,
+    max_lines: Option<u64>,
+    symbol_kinds: Vec<SymbolKind>,
+    classifications: Vec<Classification>,
+    excluded_classifications: Vec<Classification>,
+    modified_after: Option<i64>,
+    modified_before: Option<i64>,
+}
+
+impl SearchFilter {
+    /// Matches chunks in `language`, such as `rust` or `python`.
+    pub fn with_language(mut self, language: impl Into<String>) -> Self {
+        self.languages.push(language.into());
+        self
+    }
+
+    /// Matches chunks whose whole file path matches `glob`, where `*` and `?` stay within a
+    /// path component and `**` spans any number of them; use a leading `**/` to match relative
+    /// paths anywhere in the tree.
+    pub fn with_path_glob(mut self, glob: impl Into<String>) -> Self {
+        self.path_globs.push(glob.into());
+        self
+    }
+
+    /// Matches chunks whose file path starts with `prefix`.
+    pub fn with_path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Matches chunks of files named exactly `file_name`.
+    pub fn with_file_name(mut self, file_name: impl Into<String>) -> Self {
+        self.file_names.push(file_name.into());
+        self
+    }
+
+    pub fn with_repository(mut self, repository: impl Into<String>) -> Self {
+        self.repositories.push(repository.into());
+        self
+    }
+
+    pub fn with_branch(mut self, branch: impl Into<String>) -> Self {
+        self.branches.push(branch.into());
+        self
+    }
+
+    /// Matches chunks spanning a number of lines in `range`, such as `5..` or `..=40`.
+    pub fn with_line_count(mut self, range: impl RangeBounds<u64>) -> Self {
+        self.min_lines = match range.start_bound() {
+            Bound::Included(x) => Some(*x),
+            Bound::Excluded(x) => Some(x + 1),
+            Bound::Unbounded => None,
+        };
+        self.max_lines = match range.end_bound() {
+            Bound::Included(x) => Some(*x),
+            Bound::Excluded(x) => Some(x.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+        self
+    }
+
+    /// Matches chunks of definitions of `kind`.
+    pub fn with_symbol_kind(mut self, kind: SymbolKind) -> Self {
+        self.symbol_kinds.push(kind);
+        self
+    }
+
+    /// Matches chunks of files classified as `classification`.
+    pub fn with_classification(mut self, classification: Classification) -> Self {
+        self.classifications.push(classification);
+        self
+    }
+
+    /// Leaves out chunks of files classified as `classification`, such as generated or vendored
+    /// code.
+    pub fn exclude_classification(mut self, classification: Classification) -> Self {
+        self.excluded_classifications.push(classification);
+        self
+    }
+
+    /// Matches chunks whose lines were last changed after `time`, in seconds since the Unix epoch.
+    /// Chunks without blame information never match.
+    pub fn with_modified_after(mut self, time: i64) -> Self {
+        self.modified_after = Some(time);
+        self
+    }
+
+    /// Matches chunks whose lines were last changed before `time`, in seconds since the Unix
+    /// epoch. Chunks without blame information never match.
+    pub fn with_modified_before(mut self, time: i64) -> Self {
+        self.modified_before = Some(time);
+        self
+    }
+
+    /// Whether the filter matches every chunk.
+    pub fn is_empty(&self) -> bool {
+        *self == SearchFilter::default()
+    }
+
+    /// Compiles the filter to a LanceDB SQL predicate, or `None` if it matches every chunk.
+    pub fn to_sql(&self) -> Result<Option<String>> {
+        let mut conditions = Vec::new();
+        push_in(&mut conditions, "language", &self.languages);
+        if !self.path_globs.is_empty() {
+            let mut globs = Vec::new();
+            for glob in &self.path_globs {
+                // The translation below assumes a well-formed glob.
+                GlobBuilder::new(glob).literal_separator(true).build()?;
+                globs.push(format!(
+                    "regexp_match(file_path, {})",
+                    quote_literal(&glob_to_regex(glob))
+                ));
+            }
+            conditions.push(any_of(globs));
+        }
+        if !self.path_prefixes.is_empty() {
+            conditions.push(any_of(
+                self.path_prefixes
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "file_path LIKE {}",
+                            quote_literal(&format!("{}%", escape_like(x)))
+                        )
+                    })
+                    .collect(),
+            ));
+        }
+        push_in(&mut conditions, "file_name", &self.file_names);
+        push_in(&mut conditions, "repository", &self.repositories);
+        push_in(&mut conditions, "branch", &self.branches);
+        // Chunks span rows `start_line..end_line`.
+        if let Some(min_lines) = self.min_lines {
+            conditions.push(format!("end_line - start_line >= {min_lines}"));
+        }
+        if let Some(max_lines) = self.max_lines {
+            conditions.push(format!("end_line - start_line <= {max_lines}"));
+        }
+        let symbol_kinds: Vec<&str> = self.symbol_kinds.iter().map(|x| x.as_str()).collect();
+        push_in(&mut conditions, "symbol_kind", &symbol_kinds);
+        let classifications: Vec<&str> = self.classifications.iter().map(|x| x.as_str()).collect();
+        push_in(&mut conditions, "classification", &classifications);
+        if !self.excluded_classifications.is_empty() {
+            let values: Vec<String> = self
+                .excluded_classifications
+                .iter()
+                .map(|x| quote_literal(x.as_str()))
+                .collect();
+            conditions.push(format!("classification NOT IN ({})", values.join(", ")));
+        }
+        if let Some(time) = self.modified_after {
+            conditions.push(format!("last_modified > {time}"));
+        }
+        if let Some(time) = self.modified_before {
+            conditions.push(format!("last_modified < {time}"));
+        }
+
+        Ok(match conditions.len() {
+            0 => None,
+            1 => conditions.pop(),
+            _ => Some(
+                conditions
+                    .iter()
+                    .map(|x| format!("({x})"))
+                    .collect::<Vec<_>>()
+                    .join(" AND "),
+            ),
+        })
+    }
+}
+
+/// Adds a condition matching rows whose `column` is one of `values`, if there are any.
+fn push_in(conditions: &mut Vec<String>, column: &str, values: &[impl AsRef<str>]) {
+    if values.is_empty() {
+        return;
+    }
+    let values: Vec<String> = values.iter().map(|x| quote_literal(x.as_ref())).collect();
+    conditions.push(format!("{column} IN ({})", values.join(", ")));
+}
+
+fn any_of(conditions: Vec<String>) -> String {
+    conditions.join(" OR ")
+}
+
+/// Translates a well-formed path glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::from("^");
+    let mut in_alternatives = false;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    // Any number of leading directories, including none.
+                    regex.push_str("(?:.*/)?");
+                    i += 1;
+                } else {
+                    regex.push_str(".*");
+                }
+                i += 1;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                i += 1;
+                if chars.get(i) == Some(&'!') || chars.get(i) == Some(&'^') {
+                    regex.push('^');
+                    i += 1;
+                }
+                // A closing bracket right after the opening one is part of the class.
+                let start = i;
+                while i < chars.len() && (chars[i] != ']' || i == start) {
+                    if matches!(chars[i], '\\' | '[' | '&' | '~') {
+                        regex.push('\\');
+                    }
+                    regex.push(chars[i]);
+                    i += 1;
+                }
+                regex.push(']');
+            }
+            '{' => {
+                regex.push_str("(?:");
+                in_alternatives = true;
+            }
+            '}' if in_alternatives => {
+                regex.push(')');
+                in_alternatives = false;
+            }
+            ',' if in_alternatives => regex.push('|'),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                push_literal(&mut regex, chars[i]);
+            }
+            c => push_literal(&mut regex, c),
+        }
+        i += 1;
+    }
+    regex.push('$');
+    regex
+}
+
+fn push_literal(regex: &mut String, c: char) {
+    if "\\.+*?()|[]{}^$#&-~".contains(c) {
+        regex.push('\\');
+    }
+    regex.push(c);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_to_regex() {
+        assert_eq!(glob_to_regex("src/*.rs"), r"^src/[^/]*\.rs$");
+        assert_eq!(glob_to_regex("**/tests/**"), r"^(?:.*/)?tests/.*$");
+        assert_eq!(glob_to_regex("lib?.{rs,py}"), r"^lib[^/]\.(?:rs|py)$");
+        assert_eq!(glob_to_regex("[!a-c]].md"), r"^[^a-c]\]\.md$");
+        assert_eq!(glob_to_regex(r"a\*b"), r"^a\*b$");
+    }
+
+    #[test]
+    fn test_to_sql() -> anyhow::Result<()> {
+        assert!(SearchFilter::default().is_empty());
+        assert_eq!(SearchFilter::default().to_sql()?, None);
+        assert_eq!(
+            SearchFilter::default().with_language("rust").to_sql()?,
+            Some("language IN ('rust')".to_string())
+        );
+
+        let filter = SearchFilter::default()
+            .with_language("rust")
+            .with_language("python")
+            .with_path_glob("**/src/*.rs")
+            .with_path_prefix("it's_here/")
+            .with_path_prefix("lib/")
+            .with_file_name("main.rs")
+            .with_repository("repo")
+            .with_branch("main")
+            .with_line_count(5..)
+            .with_symbol_kind(SymbolKind::Function)
+            .with_symbol_kind(SymbolKind::Method)
+            .with_classification(Classification::Source)
+            .exclude_classification(Classification::Generated)
+            .exclude_classification(Classification::Vendored)
+            .with_modified_after(1_700_000_000);
+        assert!(!filter.is_empty());
+        assert_eq!(
+            filter.to_sql()?.unwrap(),
+            [
+                "(language IN ('rust', 'python'))",
+                r"(regexp_match(file_path, '^(?:.*/)?src/[^/]*\.rs$'))",
+                r"(file_path LIKE 'it''s\_here/%' OR file_path LIKE 'lib/%')",
+                "(file_name IN ('main.rs'))",
+                "(repository IN ('repo'))",
+                "(branch IN ('main'))",
+                "(end_line - start_line >= 5)",
+                "(symbol_kind IN ('function', 'method'))",
+                "(classification IN ('source'))",
+                "(classification NOT IN ('generated', 'vendored'))",
+                "(last_modified > 1700000000)",
+            ]
+            .join(" AND ")
+        );
+
+        assert_eq!(
+            SearchFilter::default().with_line_count(2..10).to_sql()?,
+            Some("(end_line - start_line >= 2) AND (end_line - start_line <= 9)".to_string())
+        );
+        assert_eq!(
+            SearchFilter::default()
+                .with_modified_before(1_800_000_000)
+                .to_sql()?,
+            Some("last_modified < 1800000000".to_string())
+        );
+        assert!(
+            SearchFilter::default()
+                .with_path_glob("src/{a,b")
+                .to_sql()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize() -> anyhow::Result<()> {
+        let filter: SearchFilter = serde_json::from_str(
+            r#"{
+                "languages": ["rust"],
+                "min_lines": 5,
+                "symbol_kinds": ["method"],
+                "excluded_classifications": ["generated"],
+                "modified_after": 1700000000
+            }"#,
+        )?;
+        assert_eq!(
+            filter,
+            SearchFilter::default()
+                .with_language("rust")
+                .with_line_count(5..)
+                .with_symbol_kind(SymbolKind::Method)
+                .exclude_classification(Classification::Generated)
+                .with_modified_after(1_700_000_000)
+        );
+        assert!(serde_json::from_str::<SearchFilter>(r#"{"language": "rust"}"#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,611 @@
+use crate::Result;
+use crate::classify::Classifier;
+use crate::split::{FileSource, SkipReason, SkippedFile, SourceFile};
+use git2::{BlameOptions, Delta, DiffFindOptions, FileMode, ObjectType, Oid, Repository, Tree};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Files changed between two revisions, with paths relative to the repository root.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FileChanges {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub renamed: Vec<RenamedFile>,
+    pub deleted: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RenamedFile {
+    pub from: String,
+    pub to: String,
+}
+
+impl FileChanges {
+    /// Paths whose content exists in the new revision and must be split again.
+    pub(crate) fn paths_to_split(&self) -> HashSet<PathBuf> {
+        self.added
+            .iter()
+            .chain(&self.modified)
+            .chain(self.renamed.iter().map(|x| &x.to))
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Paths whose chunks from the old revision are no longer valid.
+    pub(crate) fn paths_to_delete(&self) -> Vec<String> {
+        self.added
+            .iter()
+            .chain(&self.modified)
+            .chain(&self.deleted)
+            .chain(self.renamed.iter().flat_map(|x| [&x.from, &x.to]))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Identifies a repository by the URL of its `origin` remote, falling back to its path.
+pub(crate) fn repository_id(repository: &Repository) -> String {
+    if let Some(url) = repository
+        .find_remote("origin")
+        .ok()
+        .and_then(|x| x.url().map(str::to_string))
+    {
+        return url;
+    }
+
+    let path = repository.workdir().unwrap_or(repository.path());
+    path.canonicalize()
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Returns the name of the local branch `revision` refers to, following symbolic references such
+/// as `HEAD`, or `None` when it names a commit or a tag.
+pub(crate) fn branch_name(repository: &Repository, revision: &str) -> Option<String> {
+    let (_, reference) = repository.revparse_ext(revision).ok()?;
+    let reference = reference?.resolve().ok()?;
+    if !reference.is_branch() {
+        return None;
+    }
+    reference.shorthand().map(str::to_string)
+}
+
+/// Identifies the repository containing `path`, along with its checked-out branch.
+pub(crate) fn discover_codebase(path: &Path) -> Option<(String, Option<String>)> {
+    let repository = Repository::discover(path).ok()?;
+    Some((repository_id(&repository), branch_name(&repository, "HEAD")))
+}
+
+/// Walks the files of a commit straight from the object database, without a checkout.
+///
+/// Paths are relative to the repository root and follow the same order as the directory
+/// walker: the files of a tree first, sorted by name, then its subtrees, also sorted by name.
+pub(crate) struct GitTreeSource {
+    repository: Repository,
+    files: VecDeque<(PathBuf, Oid)>,
+    classifier: Classifier,
+    repository_id: String,
+    branch: Option<String>,
+    commit: String,
+}
+
+impl GitTreeSource {
+    pub(crate) fn open(repository_path: &Path, revision: &str) -> Result<Self> {
+        let repository = Repository::open(repository_path)?;
+        let mut classifier = Classifier::new(Path::new(""));
+        let mut files = VecDeque::new();
+
+        let commit = {
+            let commit = repository.revparse_single(revision)?.peel_to_commit()?;
+            collect_tree(
+                &repository,
+                &commit.tree()?,
+                Path::new(""),
+                &mut classifier,
+                &mut files,
+            )?;
+            commit.id().to_string()
+        };
+
+        Ok(GitTreeSource {
+            repository_id: repository_id(&repository),
+            branch: branch_name(&repository, revision),
+            repository,
+            files,
+            classifier,
+            commit,
+        })
+    }
+
+    pub(crate) fn repository_id(&self) -> &str {
+        &self.repository_id
+    }
+
+    /// Local branch the revision refers to, if any.
+    pub(crate) fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    /// SHA of the commit being walked.
+    pub(crate) fn commit(&self) -> &str {
+        &self.commit
+    }
+
+    /// Drops every file whose path is not in `paths`.
+    pub(crate) fn retain_paths(&mut self, paths: &HashSet<PathBuf>) {
+        self.files.retain(|(x, _)| paths.contains(x));
+    }
+}
+
+impl Iterator for GitTreeSource {
+    type Item = Result<SourceFile, SkippedFile>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, id) = self.files.pop_front()?;
+        match self.repository.find_blob(id) {
+            Ok(blob) => Some(Ok(SourceFile::Memory {
+                path,
+                contents: blob.content().to_vec(),
+            })),
+            Err(e) => Some(Err(SkippedFile::new(
+                &path,
+                SkipReason::Io {
+                    message: e.to_string(),
+                },
+            ))),
+        }
+    }
+}
+
+impl FileSource for GitTreeSource {
+    fn classifier(&self) -> &Classifier {
+        &self.classifier
+    }
+}
+
+/// Files changed between two commits of a repository.
+pub(crate) struct RevisionDiff {
+    pub(crate) repository_id: String,
+    /// Local branch `to_revision` refers to, if any.
+    pub(crate) branch: Option<String>,
+    pub(crate) from_commit: String,
+    pub(crate) to_commit: String,
+    pub(crate) changes: FileChanges,
+}
+
+/// Lists the files changed from `from_revision` to `to_revision`, detecting renames.
+pub(crate) fn diff_revisions(
+    repository_path: &Path,
+    from_revision: &str,
+    to_revision: &str,
+) -> Result<RevisionDiff> {
+    let repository = Repository::open(repository_path)?;
+    let from_commit = repository
+        .revparse_single(from_revision)?
+        .peel_to_commit()?;
+    let to_commit = repository.revparse_single(to_revision)?.peel_to_commit()?;
+
+    let mut diff =
+        repository.diff_tree_to_tree(Some(&from_commit.tree()?), Some(&to_commit.tree()?), None)?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+    let mut changes = FileChanges::default();
+    for delta in diff.deltas() {
+        let old_path = delta
+            .old_file()
+            .path()
+            .map(|x| x.to_string_lossy().to_string());
+        let new_path = delta
+            .new_file()
+            .path()
+            .map(|x| x.to_string_lossy().to_string());
+        match (delta.status(), old_path, new_path) {
+            (Delta::Added | Delta::Copied, _, Some(path)) => changes.added.push(path),
+            (Delta::Modified | Delta::Typechange, _, Some(path)) => changes.modified.push(path),
+            (Delta::Deleted, Some(path), _) => changes.deleted.push(path),
+            (Delta::Renamed, Some(from), Some(to)) => {
+                changes.renamed.push(RenamedFile { from, to })
+            }
+            _ => {}
+        }
+    }
+
+    Ok(RevisionDiff {
+        repository_id: repository_id(&repository),
+        branch: branch_name(&repository, to_revision),
+        from_commit: from_commit.id().to_string(),
+        to_commit: to_commit.id().to_string(),
+        changes,
+    })
+}
+
+/// Last commit that touched a range of lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LastChange {
+    pub(crate) commit: String,
+    pub(crate) author: String,
+    /// Author time, in seconds since the Unix epoch.
+    pub(crate) time: i64,
+}
+
+/// Blame of a single file, as ranges of lines counted from 1.
+pub(crate) struct FileBlame {
+    // Lines that are not committed yet have no last change.
+    hunks: Vec<(usize, usize, Option<LastChange>)>,
+}
+
+impl FileBlame {
+    /// Returns the most recent change to lines `start..=end`.
+    pub(crate) fn last_change(&self, start: usize, end: usize) -> Option<&LastChange> {
+        self.hunks
+            .iter()
+            .filter(|(x, y, _)| *x <= end && *y >= start)
+            .filter_map(|(_, _, x)| x.as_ref())
+            .max_by_key(|x| x.time)
+    }
+}
+
+enum BlameTarget {
+    /// Files in the working directory rooted at the given canonical path, edits included.
+    WorkingDirectory(PathBuf),
+    /// Files at the given commit, by path relative to the repository root.
+    Commit(Oid),
+}
+
+/// Computes the history of files from a local repository.
+///
+/// A repository handle cannot be shared across threads, so one is opened for every file.
+pub(crate) struct Blamer {
+    repository_path: PathBuf,
+    target: BlameTarget,
+}
+
+impl Blamer {
+    /// Blames files in the working directory of the repository containing `path`, if any.
+    pub(crate) fn discover(path: &Path) -> Option<Self> {
+        let repository = Repository::discover(path).ok()?;
+        let workdir = repository.workdir()?.canonicalize().ok()?;
+        Some(Blamer {
+            repository_path: repository.path().to_path_buf(),
+            target: BlameTarget::WorkingDirectory(workdir),
+        })
+    }
+
+    /// Blames files at `commit` of the repository at `repository_path`.
+    pub(crate) fn at_commit(repository_path: &Path, commit: &str) -> Result<Self> {
+        Ok(Blamer {
+            repository_path: repository_path.to_path_buf(),
+            target: BlameTarget::Commit(Oid::from_str(commit)?),
+        })
+    }
+
+    /// Blames the file at `path`, whose current content is `contents`.
+    ///
+    /// Returns `None` when the file has no history, for instance because it is not tracked.
+    pub(crate) fn blame(&self, path: &Path, contents: &[u8]) -> Option<FileBlame> {
+        let repository = Repository::open(&self.repository_path).ok()?;
+        let mut options = BlameOptions::new();
+        let blame = match &self.target {
+            BlameTarget::WorkingDirectory(workdir) => {
+                let canonical_path = path.canonicalize().ok()?;
+                let relative_path = canonical_path.strip_prefix(workdir).ok()?;
+                // Blaming the current content attributes uncommitted edits to no commit.
+                repository
+                    .blame_file(relative_path, Some(&mut options))
+                    .ok()?
+                    .blame_buffer(contents)
+                    .ok()?
+            }
+            BlameTarget::Commit(commit) => {
+                options.newest_commit(*commit);
+                repository.blame_file(path, Some(&mut options)).ok()?
+            }
+        };
+
+        let hunks = blame
+            .iter()
+            .map(|x| {
+                let start = x.final_start_line();
+                let end = start + x.lines_in_hunk().max(1) - 1;
+                let last_change = (!x.final_commit_id().is_zero()).then(|| {
+                    let signature = x.final_signature();
+                    LastChange {
+                        commit: x.final_commit_id().to_string(),
+                        author: signature.name().unwrap_or_default().to_string(),
+                        time: signature.when().seconds(),
+                    }
+                });
+                (start, end, last_change)
+            })
+            .collect();
+        Some(FileBlame { hunks })
+    }
+}
+
+/// Collects the blobs under `tree`, loading `.gitattributes` files into `classifier` on the way.
+///
+/// Submodules and symbolic links are not followed.
+fn collect_tree(
+    repository: &Repository,
+    tree: &Tree,
+    base: &Path,
+    classifier: &mut Classifier,
+    files: &mut VecDeque<(PathBuf, Oid)>,
+) -> Result<()> {
+    let mut blobs = Vec::new();
+    let mut subtrees = Vec::new();
+    for entry in tree.iter() {
+        let Some(name) = entry.name() else {
+            continue;
+        };
+        match entry.kind() {
+            Some(ObjectType::Tree) => subtrees.push((name.to_string(), entry.id())),
+            Some(ObjectType::Blob) if entry.filemode() != i32::from(FileMode::Link) => {
+                blobs.push((name.to_string(), entry.id()))
+            }
+            _ => {}
+        }
+    }
+    blobs.sort();
+    subtrees.sort();
+
+    if let Some((_, id)) = blobs.iter().find(|(x, _)| x == ".gitattributes") {
+        let blob = repository.find_blob(*id)?;
+        classifier.add_gitattributes(base, &String::from_utf8_lossy(blob.content()));
+    }
+    files.extend(blobs.into_iter().map(|(x, y)| (base.join(x), y)));
+
+    for (name, id) in subtrees {
+        let subtree = repository.find_tree(id)?;
+        collect_tree(repository, &subtree, &base.join(name), classifier, files)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use git2::{IndexAddOption, Signature, Time};
+    use std::fs;
+
+    /// Writes `files` into the working directory of `repository` and commits all changes.
+    pub(crate) fn commit_files(
+        repository: &Repository,
+        files: &[(&str, &str)],
+        message: &str,
+    ) -> Oid {
+        let workdir = repository.workdir().unwrap();
+        for (path, contents) in files {
+            let path = workdir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let mut index = repository.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.update_all(["*"], None).unwrap();
+        index.write().unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let parents: Vec<_> = repository
+            .head()
+            .ok()
+            .and_then(|x| x.peel_to_commit().ok())
+            .into_iter()
+            .collect();
+        // Space commits a minute apart so that their order is reflected in their time.
+        let time = parents
+            .first()
+            .map_or(1_700_000_000, |x| x.time().seconds() + 60);
+        let signature = Signature::new("Test", "test@example.com", &Time::new(time, 0)).unwrap();
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_git_tree_source() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let repository = Repository::init(temp_dir.path()).unwrap();
+        let first_commit = commit_files(
+            &repository,
+            &[
+                ("src/lib.rs", "fn first() {}\n"),
+                ("README.md", "# Readme\n"),
+                ("src/api/mod.rs", "fn api() {}\n"),
+                (".gitattributes", "src/api/** linguist-generated\n"),
+            ],
+            "First commit",
+        );
+        repository
+            .tag_lightweight(
+                "v1",
+                &repository.find_object(first_commit, None).unwrap(),
+                false,
+            )
+            .unwrap();
+        commit_files(
+            &repository,
+            &[("src/lib.rs", "fn second() {}\n")],
+            "Second commit",
+        );
+
+        let mut source = GitTreeSource::open(temp_dir.path(), "v1").unwrap();
+        assert_eq!(source.commit(), first_commit.to_string());
+        assert_eq!(source.branch(), None);
+        assert_eq!(
+            source.repository_id(),
+            temp_dir.path().canonicalize().unwrap().to_string_lossy()
+        );
+
+        let files: Vec<_> = source.by_ref().map(|x| x.unwrap()).collect();
+        let paths: Vec<_> = files.iter().map(|x| x.path().to_path_buf()).collect();
+        assert_eq!(
+            paths,
+            [
+                ".gitattributes",
+                "README.md",
+                "src/lib.rs",
+                "src/api/mod.rs"
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+        match &files[2] {
+            SourceFile::Memory { contents, .. } => assert_eq!(contents, b"fn first() {}\n"),
+            SourceFile::Disk(_) => panic!("Git files should be read into memory"),
+        }
+        assert_eq!(
+            source
+                .classifier()
+                .classify(Path::new("src/api/mod.rs"), &[]),
+            crate::split::Classification::Generated
+        );
+    }
+
+    #[test]
+    fn test_branch_name() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let repository = Repository::init(temp_dir.path()).unwrap();
+        let commit = commit_files(&repository, &[("src/lib.rs", "fn lib() {}\n")], "Commit");
+        repository
+            .branch("release", &repository.find_commit(commit).unwrap(), false)
+            .unwrap();
+        repository
+            .tag_lightweight("v1", &repository.find_object(commit, None).unwrap(), false)
+            .unwrap();
+        let head = repository.head().unwrap().shorthand().unwrap().to_string();
+
+        assert_eq!(branch_name(&repository, "HEAD"), Some(head.clone()));
+        assert_eq!(
+            branch_name(&repository, "release"),
+            Some("release".to_string())
+        );
+        assert_eq!(branch_name(&repository, "v1"), None);
+        assert_eq!(branch_name(&repository, &commit.to_string()), None);
+        assert_eq!(
+            discover_codebase(&temp_dir.path().join("src")),
+            Some((
+                temp_dir
+                    .path()
+                    .canonicalize()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                Some(head)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_repository_id_uses_origin() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let repository = Repository::init(temp_dir.path()).unwrap();
+        repository
+            .remote("origin", "https://example.com/org/repo.git")
+            .unwrap();
+
+        assert_eq!(
+            repository_id(&repository),
+            "https://example.com/org/repo.git"
+        );
+    }
+
+    #[test]
+    fn test_diff_revisions() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let repository = Repository::init(temp_dir.path()).unwrap();
+        let renamed_contents =
+            "fn renamed() {\n    println!(\"This file keeps its content\");\n}\n";
+        let first_commit = commit_files(
+            &repository,
+            &[
+                ("src/modified.rs", "fn modified() {}\n"),
+                ("src/deleted.rs", "fn deleted() {}\n"),
+                ("src/old_name.rs", renamed_contents),
+                ("src/unchanged.rs", "fn unchanged() {}\n"),
+            ],
+            "First commit",
+        );
+        fs::remove_file(temp_dir.path().join("src/deleted.rs")).unwrap();
+        fs::remove_file(temp_dir.path().join("src/old_name.rs")).unwrap();
+        let second_commit = commit_files(
+            &repository,
+            &[
+                ("src/modified.rs", "fn modified() { todo!() }\n"),
+                ("src/new_name.rs", renamed_contents),
+                ("src/added.rs", "fn added() {}\n"),
+            ],
+            "Second commit",
+        );
+
+        let diff = diff_revisions(temp_dir.path(), &first_commit.to_string(), "HEAD").unwrap();
+        assert_eq!(diff.from_commit, first_commit.to_string());
+        assert_eq!(diff.to_commit, second_commit.to_string());
+        assert_eq!(
+            diff.changes,
+            FileChanges {
+                added: vec!["src/added.rs".to_string()],
+                modified: vec!["src/modified.rs".to_string()],
+                renamed: vec![RenamedFile {
+                    from: "src/old_name.rs".to_string(),
+                    to: "src/new_name.rs".to_string()
+                }],
+                deleted: vec!["src/deleted.rs".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_blamer() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let repository = Repository::init(temp_dir.path()).unwrap();
+        let first_commit = commit_files(
+            &repository,
+            &[("src/lib.rs", "fn first() {}\nfn second() {}\n")],
+            "First commit",
+        );
+        let second_commit = commit_files(
+            &repository,
+            &[("src/lib.rs", "fn first() {}\nfn second() { todo!() }\n")],
+            "Second commit",
+        );
+
+        let blamer = Blamer::at_commit(temp_dir.path(), &first_commit.to_string()).unwrap();
+        let blame = blamer.blame(Path::new("src/lib.rs"), &[]).unwrap();
+        assert_eq!(
+            blame.last_change(1, 2).map(|x| x.commit.clone()),
+            Some(first_commit.to_string())
+        );
+
+        let blamer = Blamer::discover(temp_dir.path()).unwrap();
+        let path = temp_dir.path().join("src/lib.rs");
+        let contents = b"fn first() {}\nfn second() { todo!() }\nfn third() {}\n";
+        let blame = blamer.blame(&path, contents).unwrap();
+        let first_line = blame.last_change(1, 1).unwrap();
+        assert_eq!(first_line.commit, first_commit.to_string());
+        assert_eq!(first_line.author, "Test");
+        assert_eq!(first_line.time, 1_700_000_000);
+        assert_eq!(
+            blame.last_change(1, 2).map(|x| x.commit.clone()),
+            Some(second_commit.to_string())
+        );
+        assert_eq!(blame.last_change(3, 3), None);
+
+        assert!(
+            blamer
+                .blame(&temp_dir.path().join("untracked.rs"), b"")
+                .is_none()
+        );
+    }
+}
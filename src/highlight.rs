@@ -0,0 +1,142 @@
+use crate::languages::Language;
+use crate::symbols::SymbolKind;
+use tree_sitter::{Node, Parser};
+
+/// Syntax category of a piece of source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Highlight {
+    Comment,
+    String,
+    Number,
+    Keyword,
+    Type,
+    /// Name of a function or method being defined.
+    Function,
+}
+
+/// A line of source text, as consecutive pieces with their category, if any.
+pub(crate) type HighlightedLine = Vec<(Option<Highlight>, String)>;
+
+/// Splits `text` into lines, highlighted from its syntax tree in `language`. Text in other
+/// languages, or that fails to parse, is not highlighted.
+pub(crate) fn highlight_lines(language: Option<&Language>, text: &str) -> Vec<HighlightedLine> {
+    let highlights = language
+        .map(|x| find_highlights(x, text))
+        .unwrap_or_default();
+
+    let mut lines = vec![vec![]];
+    let mut position = 0;
+    let mut push = |lines: &mut Vec<HighlightedLine>, end: usize, highlight: Option<Highlight>| {
+        for (i, piece) in text[position..end].split('\n').enumerate() {
+            if i > 0 {
+                lines.push(vec![]);
+            }
+            let piece = piece.strip_suffix('\r').unwrap_or(piece);
+            if !piece.is_empty() {
+                lines
+                    .last_mut()
+                    .unwrap()
+                    .push((highlight, piece.to_string()));
+            }
+        }
+        position = end;
+    };
+    for (start, end, highlight) in highlights {
+        push(&mut lines, start, None);
+        push(&mut lines, end, Some(highlight));
+    }
+    push(&mut lines, text.len(), None);
+    lines
+}
+
+/// Returns the byte ranges of `text` to highlight, in order and without overlaps.
+fn find_highlights(language: &Language, text: &str) -> Vec<(usize, usize, Highlight)> {
+    let mut parser = Parser::new();
+    if parser.set_language(&language.grammar).is_err() {
+        return vec![];
+    }
+    let Some(tree) = parser.parse(text, None) else {
+        return vec![];
+    };
+
+    let mut highlights = Vec::new();
+    // Walked with an explicit stack, like symbols are, and in source order.
+    let mut pending: Vec<Node> = vec![tree.root_node()];
+    while let Some(node) = pending.pop() {
+        if let Some(highlight) = node_highlight(language, node) {
+            // Nothing inside a highlighted node, such as a string, is highlighted again.
+            highlights.push((node.start_byte(), node.end_byte(), highlight));
+            continue;
+        }
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.children(&mut cursor).collect();
+        pending.extend(children.into_iter().rev());
+    }
+    highlights
+}
+
+fn node_highlight(language: &Language, node: Node) -> Option<Highlight> {
+    let kind = node.kind();
+    if kind.contains("comment") {
+        Some(Highlight::Comment)
+    } else if kind.contains("string") {
+        Some(Highlight::String)
+    } else if kind.contains("integer") || kind.contains("float") || kind == "number" {
+        Some(Highlight::Number)
+    } else if kind == "type_identifier" || kind == "primitive_type" {
+        Some(Highlight::Type)
+    } else if !node.is_named() && !kind.is_empty() && kind.chars().all(|x| x.is_alphabetic()) {
+        // Anonymous nodes spelled with letters are keywords, such as `fn` or `return`.
+        Some(Highlight::Keyword)
+    } else if kind.contains("identifier") {
+        // The name of a definition is highlighted after the kind of definition.
+        let parent = node.parent()?;
+        let symbol_kind = language.symbol_kind(parent.kind())?;
+        (parent.child_by_field_name("name") == Some(node)).then_some(match symbol_kind {
+            SymbolKind::Function | SymbolKind::Method => Highlight::Function,
+            _ => Highlight::Type,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages::get_language_for_path;
+    use std::path::Path;
+
+    #[test]
+    fn test_highlight_lines() {
+        let text = "fn main() {\n    // Greet.\r\n    let name: &str = \"world\";\n}\n";
+        let language = get_language_for_path(Path::new("main.rs"));
+        let lines = highlight_lines(language, text);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0][..2],
+            [
+                (Some(Highlight::Keyword), "fn".to_string()),
+                (None, " ".to_string()),
+            ]
+        );
+        assert_eq!(lines[0][2], (Some(Highlight::Function), "main".to_string()));
+        assert_eq!(
+            lines[1],
+            vec![
+                (None, "    ".to_string()),
+                (Some(Highlight::Comment), "// Greet.".to_string()),
+            ]
+        );
+        assert!(lines[2].contains(&(Some(Highlight::Keyword), "let".to_string())));
+        assert!(lines[2].contains(&(Some(Highlight::Type), "str".to_string())));
+        assert!(lines[2].contains(&(Some(Highlight::String), "\"world\"".to_string())));
+        assert!(lines[4].is_empty());
+
+        // Without a language, lines are kept as they are.
+        assert_eq!(
+            highlight_lines(None, "a\nb"),
+            vec![vec![(None, "a".to_string())], vec![(None, "b".to_string())],]
+        );
+    }
+}
@@ -1,40 +1,604 @@
-use lancedb::connect;
-use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use crate::embed::create_embeddings_from_file;
+use crate::git::diff_revisions;
+pub use crate::git::{FileChanges, RenamedFile};
+use crate::split::{SplitOptions, SplitReport, split_git_paths};
+use crate::{Error, Result};
+use arrow::array::{AsArray, RecordBatch, RecordBatchIterator, RecordBatchReader};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, FieldRef, Schema};
+use arrow::error::ArrowError;
+use clap::ValueEnum;
+use lancedb::arrow::IntoPolars;
+use lancedb::index::Index;
+use lancedb::index::scalar::FtsIndexBuilder;
+use lancedb::index::vector::{IvfHnswPqIndexBuilder, IvfHnswSqIndexBuilder, IvfPqIndexBuilder};
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use lancedb::{DistanceType, Table, connect};
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
 
-pub async fn index(input_file_uri: String, db_file_uri: String) -> anyhow::Result<()> {
+/// Name of the table chunks are indexed into by default.
+pub const DEFAULT_TABLE_NAME: &str = "codebases";
+
+/// Column holding the chunk embeddings.
+pub const EMBEDDING_COLUMN: &str = "embedding";
+
+/// Column holding the chunk text.
+pub const TEXT_COLUMN: &str = "text";
+
+/// Number of rows missing from the vector index that triggers a rebuild by default.
+pub const DEFAULT_REBUILD_THRESHOLD: usize = 10_000;
+
+/// Fewest rows product quantization can be trained on, and so the lowest rebuild threshold.
+pub const MIN_REBUILD_THRESHOLD: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// Table to index chunks into, so that a database can hold separate sets of codebases.
+    pub table_name: String,
+    /// Vector index to maintain on the embeddings, if any.
+    pub vector_index: Option<VectorIndexOptions>,
+    /// Whether to maintain a BM25 full-text index on the chunk text, for lexical and hybrid
+    /// search.
+    pub full_text_index: bool,
+    /// Number of rows missing from the vector or full-text index, or of rows in a table without a
+    /// vector index, at which indexing chunks builds that index again.
+    ///
+    /// Must be at least [`MIN_REBUILD_THRESHOLD`]. Rows missing from the full-text index are still
+    /// searched, just more slowly.
+    pub rebuild_threshold: usize,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            table_name: DEFAULT_TABLE_NAME.to_string(),
+            vector_index: None,
+            full_text_index: true,
+            rebuild_threshold: DEFAULT_REBUILD_THRESHOLD,
+        }
+    }
+}
+
+/// Approximate nearest neighbour index algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorIndexType {
+    /// Inverted file index with product quantization.
+    #[default]
+    IvfPq,
+    /// HNSW graphs within the partitions of an inverted file index, with product quantization.
+    IvfHnswPq,
+    /// HNSW graphs within the partitions of an inverted file index, with scalar quantization.
+    IvfHnswSq,
+}
+
+/// Distance between embeddings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceMetric {
+    L2,
+    #[default]
+    Cosine,
+    Dot,
+}
+
+impl From<DistanceMetric> for DistanceType {
+    fn from(metric: DistanceMetric) -> Self {
+        match metric {
+            DistanceMetric::L2 => DistanceType::L2,
+            DistanceMetric::Cosine => DistanceType::Cosine,
+            DistanceMetric::Dot => DistanceType::Dot,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VectorIndexOptions {
+    pub index_type: VectorIndexType,
+    /// Distance the index is built for; searches must use the same one.
+    pub metric: DistanceMetric,
+    /// Number of IVF partitions; `None` picks one from the number of rows.
+    pub num_partitions: Option<u32>,
+    /// Number of PQ sub-vectors, which must divide the embedding dimension; `None` picks one from
+    /// the dimension. Ignored by [`VectorIndexType::IvfHnswSq`].
+    pub num_sub_vectors: Option<u32>,
+}
+
+impl VectorIndexOptions {
+    fn to_index(&self) -> Index {
+        let distance_type = self.metric.into();
+        match self.index_type {
+            VectorIndexType::IvfPq => {
+                let mut builder = IvfPqIndexBuilder::default().distance_type(distance_type);
+                if let Some(num_partitions) = self.num_partitions {
+                    builder = builder.num_partitions(num_partitions);
+                }
+                if let Some(num_sub_vectors) = self.num_sub_vectors {
+                    builder = builder.num_sub_vectors(num_sub_vectors);
+                }
+                Index::IvfPq(builder)
+            }
+            VectorIndexType::IvfHnswPq => {
+                let mut builder = IvfHnswPqIndexBuilder::default().distance_type(distance_type);
+                if let Some(num_partitions) = self.num_partitions {
+                    builder = builder.num_partitions(num_partitions);
+                }
+                if let Some(num_sub_vectors) = self.num_sub_vectors {
+                    builder = builder.num_sub_vectors(num_sub_vectors);
+                }
+                Index::IvfHnswPq(builder)
+            }
+            VectorIndexType::IvfHnswSq => {
+                let mut builder = IvfHnswSqIndexBuilder::default().distance_type(distance_type);
+                if let Some(num_partitions) = self.num_partitions {
+                    builder = builder.num_partitions(num_partitions);
+                }
+                Index::IvfHnswSq(builder)
+            }
+        }
+    }
+}
+
+/// Rows covered by an index of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IndexStatus {
+    pub indexed_rows: usize,
+    pub unindexed_rows: usize,
+}
+
+/// Outcome of an [`index_git_diff`] run.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffIndexReport {
+    /// SHA of the revision the table was up to date with.
+    pub from_commit: String,
+    /// SHA of the revision the table is now up to date with.
+    pub to_commit: String,
+    pub changes: FileChanges,
+    /// Number of chunks removed for changed, renamed or deleted files.
+    pub chunks_deleted: u64,
+    /// Number of chunks inserted for added, modified or renamed files.
+    pub chunks_inserted: u64,
+    /// Report for the files that were split again.
+    pub split: SplitReport,
+}
+
+/// Indexes the chunks in `input_file_uri` into the default table, using the default
+/// [`IndexOptions`].
+pub async fn index(input_file_uri: String, db_file_uri: String) -> Result<()> {
+    index_with_options(input_file_uri, db_file_uri, &IndexOptions::default()).await
+}
+
+/// Indexes the chunks in `input_file_uri` into the `options.table_name` table of the database at
+/// `db_file_uri`, creating the table if needed.
+pub async fn index_with_options(
+    input_file_uri: String,
+    db_file_uri: String,
+    options: &IndexOptions,
+) -> Result<()> {
+    if options.rebuild_threshold < MIN_REBUILD_THRESHOLD {
+        return Err(Error::RebuildThresholdTooLow(options.rebuild_threshold));
+    }
     let file = File::open(input_file_uri)?;
 
     let reader_builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
-    let record_batch_reader = reader_builder.build()?;
+    let record_batch_reader = fixed_size_embeddings(reader_builder.build()?)?;
 
     let db_connection = connect(db_file_uri.as_str()).execute().await?;
 
     // Check if the table exists.
-    let maybe_table = db_connection.open_table("codebases").execute().await;
-    if maybe_table.is_err() {
-        // Table does not exist, so let's create it and load it with data.
-        db_connection
-            .create_table("codebases", record_batch_reader)
-            .execute()
-            .await?;
-    } else {
-        // Table exists already; add the new records.
-        maybe_table?.add(record_batch_reader).execute().await?;
+    let table = match db_connection
+        .open_table(&options.table_name)
+        .execute()
+        .await
+    {
+        Err(lancedb::Error::TableNotFound { .. }) => {
+            // Table does not exist, so let's create it and load it with data.
+            db_connection
+                .create_table(&options.table_name, record_batch_reader)
+                .execute()
+                .await?
+        }
+        maybe_table => {
+            // Table exists already; add the new records.
+            let table = maybe_table?;
+            check_columns(&table, &record_batch_reader.schema()).await?;
+            table.add(record_batch_reader).execute().await?;
+            table
+        }
     };
 
+    if let Some(vector_index) = &options.vector_index {
+        let unindexed_rows = match index_status(&table, EMBEDDING_COLUMN).await? {
+            Some(status) => status.unindexed_rows,
+            None => table.count_rows(None).await?,
+        };
+        if unindexed_rows >= options.rebuild_threshold {
+            build_vector_index(&table, vector_index).await?;
+        }
+    }
+    if options.full_text_index
+        && table
+            .schema()
+            .await?
+            .column_with_name(TEXT_COLUMN)
+            .is_some()
+    {
+        // Unlike vector indexes, a full-text index can be built on any number of rows.
+        let rebuild = match index_status(&table, TEXT_COLUMN).await? {
+            Some(status) => status.unindexed_rows >= options.rebuild_threshold,
+            None => true,
+        };
+        if rebuild {
+            build_full_text_index(&table).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Builds the full-text index on the text of the `table_name` table, replacing any previous one.
+pub async fn create_full_text_index(db_file_uri: &str, table_name: &str) -> Result<IndexStatus> {
+    let table = open_existing_table(db_file_uri, table_name).await?;
+    build_full_text_index(&table).await?;
+    Ok(index_status(&table, TEXT_COLUMN)
+        .await?
+        .unwrap_or(IndexStatus {
+            indexed_rows: 0,
+            unindexed_rows: 0,
+        }))
+}
+
+/// Builds the vector index on the embeddings of the `table_name` table, replacing any previous
+/// one, so that searches no longer scan every row.
+pub async fn create_vector_index(
+    db_file_uri: &str,
+    table_name: &str,
+    options: &VectorIndexOptions,
+) -> Result<IndexStatus> {
+    let table = open_existing_table(db_file_uri, table_name).await?;
+    build_vector_index(&table, options).await?;
+    Ok(index_status(&table, EMBEDDING_COLUMN)
+        .await?
+        .unwrap_or(IndexStatus {
+            indexed_rows: 0,
+            unindexed_rows: 0,
+        }))
+}
+
+/// Returns how many rows the vector index of the `table_name` table covers, or `None` if the table
+/// has no vector index.
+pub async fn get_vector_index_status(
+    db_file_uri: &str,
+    table_name: &str,
+) -> Result<Option<IndexStatus>> {
+    match open_table(db_file_uri, table_name).await? {
+        Some(table) => index_status(&table, EMBEDDING_COLUMN).await,
+        None => Ok(None),
+    }
+}
+
+async fn build_vector_index(table: &Table, options: &VectorIndexOptions) -> Result<()> {
+    table
+        .create_index(&[EMBEDDING_COLUMN], options.to_index())
+        .replace(true)
+        .execute()
+        .await?;
+    Ok(())
+}
+
+async fn build_full_text_index(table: &Table) -> Result<()> {
+    table
+        .create_index(&[TEXT_COLUMN], Index::FTS(FtsIndexBuilder::default()))
+        .replace(true)
+        .execute()
+        .await?;
     Ok(())
 }
 
+/// Returns how many rows the index on `column` covers, or `None` if there is no such index.
+pub(crate) async fn index_status(table: &Table, column: &str) -> Result<Option<IndexStatus>> {
+    let indices = table.list_indices().await?;
+    let Some(index) = indices
+        .iter()
+        .find(|x| x.columns.iter().any(|y| y == column))
+    else {
+        return Ok(None);
+    };
+    Ok(table.index_stats(&index.name).await?.map(|x| IndexStatus {
+        indexed_rows: x.num_indexed_rows,
+        unindexed_rows: x.num_unindexed_rows,
+    }))
+}
+
+/// Fails if `table` lacks columns of `schema`, as tables created before chunks had IDs, codebases
+/// and history cannot take chunks split since. There is no migration: such tables must be indexed
+/// again from scratch.
+async fn check_columns(table: &Table, schema: &Schema) -> Result<()> {
+    let table_schema = table.schema().await?;
+    let missing: Vec<String> = schema
+        .fields()
+        .iter()
+        .map(|x| x.name())
+        .filter(|x| table_schema.column_with_name(x).is_none())
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(Error::OutdatedTable {
+        table: table.name().to_string(),
+        missing,
+    })
+}
+
+/// Converts the embedding column from the variable-size lists written by polars to the
+/// fixed-size lists LanceDB requires for vector search, taking the size from the first embedding.
+///
+/// Batches without an embedding column are passed through untouched.
+fn fixed_size_embeddings(
+    mut reader: ParquetRecordBatchReader,
+) -> Result<RecordBatchIterator<Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>> + Send>>> {
+    let schema = reader.schema();
+    let first_batch = reader.next().transpose()?;
+    let dimension = match (&first_batch, schema.index_of(EMBEDDING_COLUMN)) {
+        (Some(batch), Ok(index)) => batch
+            .column(index)
+            .as_list_opt::<i32>()
+            .and_then(|x| x.iter().flatten().next())
+            .map(|x| x.len() as i32),
+        _ => None,
+    };
+    let Some(dimension) = dimension else {
+        return Ok(RecordBatchIterator::new(
+            Box::new(first_batch.into_iter().map(Ok).chain(reader)),
+            schema,
+        ));
+    };
+
+    let index = schema.index_of(EMBEDDING_COLUMN)?;
+    let data_type = DataType::FixedSizeList(
+        Arc::new(Field::new("item", DataType::Float32, true)),
+        dimension,
+    );
+    let mut fields: Vec<FieldRef> = schema.fields().iter().cloned().collect();
+    fields[index] = Arc::new(
+        fields[index]
+            .as_ref()
+            .clone()
+            .with_data_type(data_type.clone()),
+    );
+    let fixed_schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+
+    let batch_schema = fixed_schema.clone();
+    let batches = first_batch.into_iter().map(Ok).chain(reader).map(move |x| {
+        let batch = x?;
+        let mut columns = batch.columns().to_vec();
+        columns[index] = cast(&columns[index], &data_type)?;
+        RecordBatch::try_new(batch_schema.clone(), columns)
+    });
+    Ok(RecordBatchIterator::new(Box::new(batches), fixed_schema))
+}
+
+/// Brings the `options.table_name` table from `from_revision` to `to_revision` of the local git
+/// repository at `repository_path`, touching only the files that changed in between.
+///
+/// Added, modified and renamed files are split and embedded again at `to_revision`, and all the
+/// chunks previously stored for changed or deleted paths of this codebase are removed. New
+/// chunks are inserted first and old ones deleted afterwards, so that a failed run leaves the old
+/// chunks in place. The table is expected to hold the repository at `from_revision`, as written
+/// by [`crate::split::find_and_split_git`] or by a previous run of this function.
+///
+/// The codebase is identified by the repository and the branch `to_revision` names, unless
+/// `split_options` overrides them; a `to_revision` naming a commit or a tag has no branch.
+pub async fn index_git_diff(
+    repository_path: String,
+    from_revision: String,
+    to_revision: String,
+    db_file_uri: String,
+    split_options: &SplitOptions,
+    options: &IndexOptions,
+) -> Result<DiffIndexReport> {
+    let repository_path = Path::new(&repository_path);
+    let diff = diff_revisions(repository_path, &from_revision, &to_revision)?;
+    let repository = split_options
+        .repository
+        .clone()
+        .unwrap_or(diff.repository_id);
+    let branch = split_options.branch.clone().or(diff.branch);
+    let split_options = SplitOptions {
+        repository: Some(repository.clone()),
+        branch: branch.clone(),
+        ..split_options.clone()
+    };
+
+    let temp_dir = tempfile::tempdir()?;
+    let split_file_uri = temp_dir.path().join("split.parquet");
+    let embed_file_uri = temp_dir.path().join("embed.parquet");
+    let split = split_git_paths(
+        repository_path,
+        &diff.to_commit,
+        &diff.changes.paths_to_split(),
+        split_file_uri.to_string_lossy().to_string(),
+        &split_options,
+    )?;
+    if split.chunks_written > 0 {
+        create_embeddings_from_file(
+            split_file_uri.to_string_lossy().to_string(),
+            embed_file_uri.to_string_lossy().to_string(),
+        )?;
+    }
+
+    // Every path to split is also one whose old chunks go, so an empty list means no changes.
+    let paths_to_delete = diff.changes.paths_to_delete();
+    let chunks_deleted = if paths_to_delete.is_empty() {
+        0
+    } else {
+        let predicate = format!(
+            "{} AND file_path IN ({})",
+            codebase_predicate(&repository, branch.as_deref()),
+            paths_to_delete
+                .iter()
+                .map(|x| quote_literal(x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let input_file_uri =
+            (split.chunks_written > 0).then(|| embed_file_uri.to_string_lossy().to_string());
+        replace_rows(input_file_uri, &db_file_uri, &predicate, options).await?
+    };
+
+    Ok(DiffIndexReport {
+        from_commit: diff.from_commit,
+        to_commit: diff.to_commit,
+        changes: diff.changes,
+        chunks_deleted,
+        chunks_inserted: split.chunks_written,
+        split,
+    })
+}
+
+/// Whether the database at `db_file_uri` has a `table_name` table.
+pub async fn table_exists(db_file_uri: &str, table_name: &str) -> Result<bool> {
+    Ok(open_table(db_file_uri, table_name).await?.is_some())
+}
+
+/// Opens the `table_name` table of the database at `db_file_uri`, or returns `None` if there is
+/// no such table.
+pub(crate) async fn open_table(db_file_uri: &str, table_name: &str) -> Result<Option<Table>> {
+    let db_connection = connect(db_file_uri).execute().await?;
+    match db_connection.open_table(table_name).execute().await {
+        Ok(table) => Ok(Some(table)),
+        Err(lancedb::Error::TableNotFound { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Like [`open_table`], but fails if there is no such table.
+pub(crate) async fn open_existing_table(db_file_uri: &str, table_name: &str) -> Result<Table> {
+    let table = open_table(db_file_uri, table_name).await?;
+    Ok(table.ok_or_else(|| lancedb::Error::TableNotFound {
+        name: table_name.to_string(),
+    })?)
+}
+
+/// Deletes the rows matching `predicate` from the `table_name` table, if it exists, and returns
+/// how many there were.
+pub(crate) async fn delete_rows(
+    db_file_uri: &str,
+    table_name: &str,
+    predicate: &str,
+) -> Result<u64> {
+    let Some(table) = open_table(db_file_uri, table_name).await? else {
+        return Ok(0);
+    };
+
+    let count = table.count_rows(Some(predicate.to_string())).await?;
+    if count > 0 {
+        table.delete(predicate).await?;
+    }
+    Ok(count as u64)
+}
+
+/// Indexes the embeddings at `input_file_uri`, if any, in place of the rows matching `predicate`,
+/// and returns how many old rows were deleted.
+///
+/// The new rows are added before the old ones are deleted, so that searches never miss a file
+/// that is being indexed again. Added rows get higher row IDs than every existing row, which is
+/// what tells the old rows apart from the new ones.
+pub(crate) async fn replace_rows(
+    input_file_uri: Option<String>,
+    db_file_uri: &str,
+    predicate: &str,
+    options: &IndexOptions,
+) -> Result<u64> {
+    let last_row_id = match open_table(db_file_uri, &options.table_name).await? {
+        Some(table) => {
+            let dataframe = table
+                .query()
+                .only_if(predicate)
+                .select(Select::columns(&["file_path"]))
+                .with_row_id()
+                .execute()
+                .await?
+                .into_polars()
+                .await?;
+            dataframe.column("_rowid")?.u64()?.max()
+        }
+        None => None,
+    };
+
+    if let Some(input_file_uri) = input_file_uri {
+        index_with_options(input_file_uri, db_file_uri.to_string(), options).await?;
+    }
+    match last_row_id {
+        Some(last_row_id) => {
+            let predicate = format!("({predicate}) AND _rowid <= {last_row_id}");
+            delete_rows(db_file_uri, &options.table_name, &predicate).await
+        }
+        None => Ok(0),
+    }
+}
+
+/// Filters the rows of the codebase made of `repository` at `branch`, or with no branch.
+pub(crate) fn codebase_predicate(repository: &str, branch: Option<&str>) -> String {
+    let branch = match branch {
+        Some(branch) => format!("branch = {}", quote_literal(branch)),
+        None => "branch IS NULL".to_string(),
+    };
+    format!("repository = {} AND {branch}", quote_literal(repository))
+}
+
+/// Quotes `value` as an SQL string literal for LanceDB filters.
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Escapes the wildcards of `LIKE` patterns in `value`, so that it matches only itself.
+pub(crate) fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use lancedb::arrow::IntoPolars;
-    use lancedb::query::ExecutableQuery;
+    use crate::git::tests::commit_files;
+    use crate::split::{find_and_split, find_and_split_git};
+    use arrow::array::{ArrayRef, ListArray, RecordBatch, StringArray};
+    use arrow::datatypes::{DataType, Float32Type};
+    use parquet::arrow::ArrowWriter;
     use polars::df;
     use polars::prelude::*;
+    use std::sync::Arc;
     use tempfile::TempDir;
 
+    /// Splits, embeds and indexes the files under `root` into the default table of a database in
+    /// `temp_dir`, returning the path of the database.
+    pub(crate) async fn index_directory(temp_dir: &Path, root: &Path) -> anyhow::Result<String> {
+        let split_file_uri = temp_dir.join("split.parquet");
+        let embed_file_uri = temp_dir.join("embed.parquet");
+        let db_path_str = temp_dir.join("test.db").to_string_lossy().to_string();
+        find_and_split(
+            root.to_string_lossy().to_string(),
+            split_file_uri.to_string_lossy().to_string(),
+        )?;
+        create_embeddings_from_file(
+            split_file_uri.to_string_lossy().to_string(),
+            embed_file_uri.to_string_lossy().to_string(),
+        )?;
+        index(
+            embed_file_uri.to_string_lossy().to_string(),
+            db_path_str.clone(),
+        )
+        .await?;
+        Ok(db_path_str)
+    }
+
     async fn create_test_parquet() -> (TempDir, String) {
         // Create a temporary directory that will be automatically cleaned up
         let temp_dir = TempDir::new().unwrap();
@@ -115,6 +679,33 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_index_into_outdated_table() -> anyhow::Result<()> {
+        let (temp_dir, parquet_path) = create_test_parquet().await;
+        let db_path_str = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+        index(parquet_path, db_path_str.clone()).await?;
+
+        let newer_path = temp_dir.path().join("newer.parquet");
+        let mut df = df!(
+            "id" => &[4],
+            "name" => &["test4"],
+            "value" => &[40.0],
+            "repository" => &["repo"]
+        )?;
+        ParquetWriter::new(&mut std::fs::File::create(&newer_path)?).finish(&mut df)?;
+
+        let result = index(newer_path.to_string_lossy().to_string(), db_path_str).await;
+        assert!(matches!(
+            result,
+            Err(Error::OutdatedTable { ref missing, .. }) if missing == &["repository"]
+        ));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_index_with_invalid_parquet() -> Result<(), Box<dyn std::error::Error>> {
         let temp_dir = TempDir::new()?;
@@ -141,4 +732,255 @@ mod tests {
         assert!(result.is_err());
         Ok(())
     }
+
+    #[test]
+    fn test_quote_literal() {
+        assert_eq!(quote_literal("src/lib.rs"), "'src/lib.rs'");
+        assert_eq!(quote_literal("it's"), "'it''s'");
+    }
+
+    fn create_embedding_parquet(temp_dir: &TempDir, rows: usize) -> String {
+        let parquet_path = temp_dir.path().join("embeddings.parquet");
+        let embeddings = ListArray::from_iter_primitive::<Float32Type, _, _>(
+            (0..rows)
+                .map(|i| Some((0..16).map(move |j| Some(((i * 31 + j * 17) % 97) as f32 / 97.0)))),
+        );
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "file_path",
+                Arc::new(StringArray::from_iter_values(
+                    (0..rows).map(|i| format!("src/{i}.rs")),
+                )) as ArrayRef,
+            ),
+            (EMBEDDING_COLUMN, Arc::new(embeddings) as ArrayRef),
+        ])
+        .unwrap();
+        let file = std::fs::File::create(&parquet_path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        parquet_path.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_index_builds_vector_index() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let parquet_path = create_embedding_parquet(&temp_dir, 300);
+        let db_path_str = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+        let options = IndexOptions {
+            vector_index: Some(VectorIndexOptions {
+                num_partitions: Some(2),
+                num_sub_vectors: Some(4),
+                ..Default::default()
+            }),
+            rebuild_threshold: 500,
+            ..Default::default()
+        };
+
+        let too_low = IndexOptions {
+            rebuild_threshold: MIN_REBUILD_THRESHOLD - 1,
+            ..options.clone()
+        };
+        assert!(matches!(
+            index_with_options(parquet_path.clone(), db_path_str.clone(), &too_low).await,
+            Err(Error::RebuildThresholdTooLow(255))
+        ));
+        assert!(
+            open_table(&db_path_str, DEFAULT_TABLE_NAME)
+                .await?
+                .is_none()
+        );
+
+        // Too few rows to build the index yet.
+        index_with_options(parquet_path.clone(), db_path_str.clone(), &options).await?;
+        assert_eq!(
+            get_vector_index_status(&db_path_str, DEFAULT_TABLE_NAME).await?,
+            None
+        );
+        let table = open_table(&db_path_str, DEFAULT_TABLE_NAME).await?.unwrap();
+        assert!(matches!(
+            table
+                .schema()
+                .await?
+                .field_with_name(EMBEDDING_COLUMN)?
+                .data_type(),
+            DataType::FixedSizeList(_, 16)
+        ));
+
+        index_with_options(parquet_path.clone(), db_path_str.clone(), &options).await?;
+        assert_eq!(
+            get_vector_index_status(&db_path_str, DEFAULT_TABLE_NAME).await?,
+            Some(IndexStatus {
+                indexed_rows: 600,
+                unindexed_rows: 0
+            })
+        );
+
+        // New rows stay out of the index until there are enough of them.
+        index_with_options(parquet_path, db_path_str.clone(), &options).await?;
+        assert_eq!(
+            get_vector_index_status(&db_path_str, DEFAULT_TABLE_NAME).await?,
+            Some(IndexStatus {
+                indexed_rows: 600,
+                unindexed_rows: 300
+            })
+        );
+
+        let status = create_vector_index(
+            &db_path_str,
+            DEFAULT_TABLE_NAME,
+            &VectorIndexOptions {
+                index_type: VectorIndexType::IvfHnswSq,
+                metric: DistanceMetric::L2,
+                num_partitions: Some(2),
+                num_sub_vectors: None,
+            },
+        )
+        .await?;
+        assert_eq!(
+            status,
+            IndexStatus {
+                indexed_rows: 900,
+                unindexed_rows: 0
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_codebase_predicate() {
+        assert_eq!(
+            codebase_predicate("repo", Some("main")),
+            "repository = 'repo' AND branch = 'main'"
+        );
+        assert_eq!(
+            codebase_predicate("repo", None),
+            "repository = 'repo' AND branch IS NULL"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_with_table_name() -> anyhow::Result<()> {
+        let (temp_dir, parquet_path) = create_test_parquet().await;
+        let db_path_str = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+        let options = IndexOptions {
+            table_name: "tenant".to_string(),
+            ..Default::default()
+        };
+
+        index_with_options(parquet_path, db_path_str.clone(), &options).await?;
+
+        assert!(
+            open_table(&db_path_str, DEFAULT_TABLE_NAME)
+                .await?
+                .is_none()
+        );
+        let table = open_table(&db_path_str, "tenant").await?.unwrap();
+        assert_eq!(table.count_rows(None).await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_index_git_diff() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repository_path = temp_dir.path().join("repository");
+        let repository = git2::Repository::init(&repository_path)?;
+        let content = |name: &str| {
+            format!(
+                r#"
+            fn {name}() {{
+                println!("{name}");
+            }}
+            "#
+            )
+        };
+        let first_commit = commit_files(
+            &repository,
+            &[
+                ("src/modified.rs", content("modified").as_str()),
+                ("src/deleted.rs", content("deleted").as_str()),
+                ("src/unchanged.rs", content("unchanged").as_str()),
+            ],
+            "First commit",
+        );
+
+        let split_file_uri = temp_dir.path().join("split.parquet");
+        let embed_file_uri = temp_dir.path().join("embed.parquet");
+        let db_path_str = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+        // HEAD is the first commit for now, and names the checked-out branch.
+        find_and_split_git(
+            repository_path.to_string_lossy().to_string(),
+            "HEAD".to_string(),
+            split_file_uri.to_string_lossy().to_string(),
+            &SplitOptions::default(),
+        )?;
+        create_embeddings_from_file(
+            split_file_uri.to_string_lossy().to_string(),
+            embed_file_uri.to_string_lossy().to_string(),
+        )?;
+        index(
+            embed_file_uri.to_string_lossy().to_string(),
+            db_path_str.clone(),
+        )
+        .await?;
+
+        std::fs::remove_file(repository_path.join("src/deleted.rs"))?;
+        let second_commit = commit_files(
+            &repository,
+            &[
+                ("src/modified.rs", content("changed").as_str()),
+                ("src/added.rs", content("added").as_str()),
+            ],
+            "Second commit",
+        );
+
+        let report = index_git_diff(
+            repository_path.to_string_lossy().to_string(),
+            first_commit.to_string(),
+            "HEAD".to_string(),
+            db_path_str.clone(),
+            &SplitOptions::default(),
+            &IndexOptions::default(),
+        )
+        .await?;
+        assert_eq!(report.to_commit, second_commit.to_string());
+        assert_eq!(report.changes.added, vec!["src/added.rs".to_string()]);
+        assert_eq!(report.changes.modified, vec!["src/modified.rs".to_string()]);
+        assert_eq!(report.changes.deleted, vec!["src/deleted.rs".to_string()]);
+        assert_eq!(report.chunks_deleted, 2);
+        assert_eq!(report.chunks_inserted, 2);
+
+        let db = connect(&db_path_str).execute().await?;
+        let table = db.open_table("codebases").execute().await?;
+        let df = table.query().execute().await?.into_polars().await?;
+        let mut rows: Vec<(String, String)> = df
+            .column("file_path")?
+            .str()?
+            .iter()
+            .zip(df.column("commit")?.str()?.iter())
+            .map(|(x, y)| (x.unwrap().to_string(), y.unwrap().to_string()))
+            .collect();
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![
+                ("src/added.rs".to_string(), second_commit.to_string()),
+                ("src/modified.rs".to_string(), second_commit.to_string()),
+                ("src/unchanged.rs".to_string(), first_commit.to_string()),
+            ]
+        );
+        Ok(())
+    }
 }
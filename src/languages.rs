@@ -1,4 +1,6 @@
+use crate::symbols::SymbolKind;
 use code_splitter::{Splitter, WordCounter};
+use std::path::Path;
 use std::sync::OnceLock;
 
 static LANGUAGES: OnceLock<Vec<Language>> = OnceLock::new();
@@ -8,44 +10,90 @@ pub struct Language {
     pub name: String,
     pub extensions: Vec<String>,
     pub splitter: Splitter<WordCounter>,
+    pub grammar: tree_sitter::Language,
+    /// Syntax node kinds of the definitions recorded with chunks.
+    pub symbol_kinds: &'static [(&'static str, SymbolKind)],
 }
 
+impl Language {
+    /// Returns the kind of definition for syntax nodes of kind `node_kind`, if they are one.
+    pub fn symbol_kind(&self, node_kind: &str) -> Option<SymbolKind> {
+        self.symbol_kinds
+            .iter()
+            .find(|(x, _)| *x == node_kind)
+            .map(|(_, x)| *x)
+    }
+}
+
+const JAVASCRIPT_SYMBOLS: &[(&str, SymbolKind)] = &[
+    ("function_declaration", SymbolKind::Function),
+    ("generator_function_declaration", SymbolKind::Function),
+    ("class_declaration", SymbolKind::Class),
+    ("method_definition", SymbolKind::Method),
+];
+
+const RUST_SYMBOLS: &[(&str, SymbolKind)] = &[
+    ("function_item", SymbolKind::Function),
+    ("struct_item", SymbolKind::Struct),
+    ("union_item", SymbolKind::Struct),
+    ("enum_item", SymbolKind::Enum),
+    ("trait_item", SymbolKind::Trait),
+    ("impl_item", SymbolKind::Impl),
+    ("mod_item", SymbolKind::Module),
+    ("const_item", SymbolKind::Constant),
+    ("static_item", SymbolKind::Constant),
+    ("type_item", SymbolKind::Type),
+];
+
+const PYTHON_SYMBOLS: &[(&str, SymbolKind)] = &[
+    ("function_definition", SymbolKind::Function),
+    ("class_definition", SymbolKind::Class),
+];
+
+const TYPESCRIPT_SYMBOLS: &[(&str, SymbolKind)] = &[
+    ("function_declaration", SymbolKind::Function),
+    ("generator_function_declaration", SymbolKind::Function),
+    ("class_declaration", SymbolKind::Class),
+    ("abstract_class_declaration", SymbolKind::Class),
+    ("method_definition", SymbolKind::Method),
+    ("interface_declaration", SymbolKind::Interface),
+    ("enum_declaration", SymbolKind::Enum),
+    ("type_alias_declaration", SymbolKind::Type),
+    ("internal_module", SymbolKind::Module),
+];
+
 fn init_languages() -> Vec<Language> {
+    let grammar = tree_sitter::Language::new(tree_sitter_javascript::LANGUAGE);
     let javascript: Language = Language {
         name: "javascript".to_string(),
         extensions: vec!["js".to_string()],
-        splitter: Splitter::new(
-            tree_sitter::Language::new(tree_sitter_javascript::LANGUAGE),
-            WordCounter,
-        )
-        .unwrap(),
+        splitter: Splitter::new(grammar.clone(), WordCounter).unwrap(),
+        grammar,
+        symbol_kinds: JAVASCRIPT_SYMBOLS,
     };
+    let grammar = tree_sitter::Language::new(tree_sitter_rust::LANGUAGE);
     let rust: Language = Language {
         name: "rust".to_string(),
         extensions: vec!["rs".to_string()],
-        splitter: Splitter::new(
-            tree_sitter::Language::new(tree_sitter_rust::LANGUAGE),
-            WordCounter,
-        )
-        .unwrap(),
+        splitter: Splitter::new(grammar.clone(), WordCounter).unwrap(),
+        grammar,
+        symbol_kinds: RUST_SYMBOLS,
     };
+    let grammar = tree_sitter::Language::new(tree_sitter_python::LANGUAGE);
     let python: Language = Language {
         name: "python".to_string(),
         extensions: vec!["py".to_string()],
-        splitter: Splitter::new(
-            tree_sitter::Language::new(tree_sitter_python::LANGUAGE),
-            WordCounter,
-        )
-        .unwrap(),
+        splitter: Splitter::new(grammar.clone(), WordCounter).unwrap(),
+        grammar,
+        symbol_kinds: PYTHON_SYMBOLS,
     };
+    let grammar = tree_sitter::Language::new(tree_sitter_typescript::LANGUAGE_TYPESCRIPT);
     let typescript: Language = Language {
         name: "typescript".to_string(),
         extensions: vec!["ts".to_string()],
-        splitter: Splitter::new(
-            tree_sitter::Language::new(tree_sitter_typescript::LANGUAGE_TYPESCRIPT),
-            WordCounter,
-        )
-        .unwrap(),
+        splitter: Splitter::new(grammar.clone(), WordCounter).unwrap(),
+        grammar,
+        symbol_kinds: TYPESCRIPT_SYMBOLS,
     };
 
     vec![javascript, rust, python, typescript]
@@ -54,3 +102,11 @@ fn init_languages() -> Vec<Language> {
 pub fn get_languages() -> &'static [Language] {
     LANGUAGES.get_or_init(init_languages).as_slice()
 }
+
+/// Returns the language of the file at `path`, judging by its extension.
+pub fn get_language_for_path(path: &Path) -> Option<&'static Language> {
+    let extension = path.extension()?.to_str()?;
+    get_languages()
+        .iter()
+        .find(|x| x.extensions.iter().any(|y| y == extension))
+}
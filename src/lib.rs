@@ -1,6 +1,26 @@
 #![feature(once_cell_try)]
 
+mod archive;
+mod classify;
+pub mod codebases;
+pub mod context;
+pub mod duplicates;
 pub mod embed;
+mod error;
+pub mod filter;
+mod git;
+mod highlight;
 pub mod index;
+mod jsonrpc;
 mod languages;
+pub mod locations;
+pub mod lsp;
+pub mod mcp;
+pub mod search;
 pub mod split;
+mod symbols;
+pub mod topics;
+pub mod tui;
+pub mod watch;
+
+pub use error::{Error, Result};
@@ -0,0 +1,100 @@
+use crate::Result;
+use polars::prelude::DataFrame;
+use serde::Serialize;
+
+/// Columns read into a [`ChunkLocation`].
+pub(crate) const LOCATION_COLUMNS: &[&str] = &[
+    "id",
+    "file_path",
+    "start_line",
+    "end_line",
+    "repository",
+    "branch",
+];
+
+/// Where a chunk is, with lines counted as in the table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChunkLocation {
+    pub id: String,
+    pub file_path: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub repository: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl ChunkLocation {
+    /// Formats the location as `path:first-last`, with lines counted from 1.
+    pub fn reference(&self) -> String {
+        format!(
+            "{}:{}-{}",
+            self.file_path,
+            self.start_line + 1,
+            self.end_line.max(self.start_line + 1)
+        )
+    }
+
+    /// Returns the file path relative to the repository of the chunk, when it lies under the
+    /// repository path, or else the file path as it is.
+    pub fn relative_path(&self) -> &str {
+        self.repository
+            .as_deref()
+            .map(|x| x.trim_end_matches('/'))
+            .filter(|x| !x.is_empty())
+            .and_then(|x| self.file_path.strip_prefix(x))
+            .and_then(|x| x.strip_prefix('/'))
+            .unwrap_or(&self.file_path)
+    }
+}
+
+/// Reads the locations of the chunks in `dataframe`, which holds at least the
+/// [`LOCATION_COLUMNS`].
+pub(crate) fn read_locations(dataframe: &DataFrame) -> Result<Vec<ChunkLocation>> {
+    let ids = dataframe.column("id")?.str()?;
+    let file_paths = dataframe.column("file_path")?.str()?;
+    let start_lines = dataframe.column("start_line")?.u64()?;
+    let end_lines = dataframe.column("end_line")?.u64()?;
+    let repositories = dataframe.column("repository")?.str()?;
+    let branches = dataframe.column("branch")?.str()?;
+
+    Ok((0..dataframe.height())
+        .map(|i| ChunkLocation {
+            id: ids.get(i).unwrap_or_default().to_string(),
+            file_path: file_paths.get(i).unwrap_or_default().to_string(),
+            start_line: start_lines.get(i).unwrap_or_default(),
+            end_line: end_lines.get(i).unwrap_or_default(),
+            repository: repositories.get(i).map(str::to_string),
+            branch: branches.get(i).map(str::to_string),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        let mut location = ChunkLocation {
+            id: "a".to_string(),
+            file_path: "/home/user/project/src/main.rs".to_string(),
+            start_line: 0,
+            end_line: 10,
+            repository: Some("/home/user/project".to_string()),
+            branch: None,
+        };
+        assert_eq!(location.relative_path(), "src/main.rs");
+        assert_eq!(location.reference(), "/home/user/project/src/main.rs:1-10");
+
+        // Only whole path components are stripped.
+        location.repository = Some("/home/user/proj".to_string());
+        assert_eq!(location.relative_path(), "/home/user/project/src/main.rs");
+
+        // Chunks split from git objects have paths relative to the repository already.
+        location.file_path = "src/main.rs".to_string();
+        location.repository = Some("https://example.com/project.git".to_string());
+        assert_eq!(location.relative_path(), "src/main.rs");
+        location.repository = None;
+        assert_eq!(location.relative_path(), "src/main.rs");
+    }
+}
@@ -0,0 +1,631 @@
+use crate::filter::SearchFilter;
+use crate::index::{DistanceMetric, IndexOptions};
+use crate::jsonrpc::{
+    INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR, RpcError, notification,
+    parse_params, response,
+};
+use crate::search::{
+    self, DEFAULT_LIMIT, DEFAULT_VECTOR_WEIGHT, SearchModeKind, SearchOptions, SearchResult,
+    SimilarTo,
+};
+use crate::split::SplitOptions;
+use crate::watch::{WatchOptions, reindex_paths, to_root_path};
+use crate::{Result, embed};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use url::Url;
+
+/// Command run by the "Find similar code" code action.
+pub const FIND_SIMILAR_COMMAND: &str = "semanticCodeSearch.findSimilar";
+
+/// Error code for requests sent before `initialize`.
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+/// Largest message body accepted, far above what a client sends, so that a malformed
+/// `Content-Length` cannot make the server allocate without bound.
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+/// `MessageType` of `window/logMessage` notifications.
+const LOG_ERROR: u8 = 1;
+const LOG_INFO: u8 = 3;
+
+#[derive(Debug, Clone)]
+pub struct LspOptions {
+    /// Directory the table was split from, given the same way. Defaults to the workspace root the
+    /// editor sends, which then must be how the directory was given when splitting it.
+    pub root: Option<PathBuf>,
+    /// Number of results returned unless a request says otherwise.
+    pub limit: usize,
+    /// Distance between embeddings; must match the vector index, if there is one.
+    pub metric: DistanceMetric,
+    /// Options for splitting saved files.
+    pub split: SplitOptions,
+    /// Options for indexing saved files; `index.table_name` is also the table searched.
+    pub index: IndexOptions,
+}
+
+impl Default for LspOptions {
+    fn default() -> Self {
+        LspOptions {
+            root: None,
+            limit: DEFAULT_LIMIT,
+            metric: DistanceMetric::default(),
+            split: SplitOptions::default(),
+            index: IndexOptions::default(),
+        }
+    }
+}
+
+/// A Language Server Protocol server answering workspace symbol queries with semantic search
+/// over the database at `db_file_uri`, and keeping it up to date as files are saved.
+///
+/// Besides `workspace/symbol`, it answers:
+/// - `semanticSearch` requests, with a `query` and optionally a `limit`, a `mode` (`vector`,
+///   `fullText` or `hybrid`) and a `filter`, with the matching chunks;
+/// - `textDocument/codeAction` requests on a selection, with a "Find similar code" action running
+///   [`FIND_SIMILAR_COMMAND`], whose `workspace/executeCommand` result is the chunks most similar
+///   to the saved contents of the selected lines.
+///
+/// Chunks are returned as objects with their `uri` and `range`, among other fields.
+pub struct LspServer {
+    db_file_uri: String,
+    options: LspOptions,
+    /// Root saved files are re-indexed below, as given and canonicalized.
+    root: Option<(PathBuf, PathBuf)>,
+    initialized: bool,
+    shut_down: bool,
+    exited: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Position {
+    line: u64,
+    character: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Range {
+    start: Position,
+    end: Position,
+}
+
+impl Range {
+    /// Returns the rows of the lines the range touches, the end excluded, like those of chunks.
+    fn rows(&self) -> (u64, u64) {
+        let end = self.end.line + u64::from(self.end.character > 0);
+        (self.start.line, end.max(self.start.line + 1))
+    }
+}
+
+#[derive(Deserialize)]
+struct TextDocumentIdentifier {
+    uri: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeActionParams {
+    text_document: TextDocumentIdentifier,
+    range: Range,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidSaveParams {
+    text_document: TextDocumentIdentifier,
+}
+
+#[derive(Deserialize)]
+struct WorkspaceSymbolParams {
+    query: String,
+}
+
+#[derive(Deserialize)]
+struct SemanticSearchParams {
+    query: String,
+    limit: Option<usize>,
+    mode: Option<SearchModeKind>,
+    #[serde(default)]
+    filter: SearchFilter,
+}
+
+/// Arguments of [`FIND_SIMILAR_COMMAND`].
+#[derive(Serialize, Deserialize)]
+struct FindSimilarArguments {
+    uri: String,
+    range: Range,
+}
+
+#[derive(Deserialize)]
+struct ExecuteCommandParams {
+    command: String,
+    #[serde(default)]
+    arguments: Vec<Value>,
+}
+
+impl LspServer {
+    pub fn new(db_file_uri: impl Into<String>, options: LspOptions) -> Self {
+        LspServer {
+            db_file_uri: db_file_uri.into(),
+            options,
+            root: None,
+            initialized: false,
+            shut_down: false,
+            exited: false,
+        }
+    }
+
+    /// Loads the embedding model, then answers messages from standard input on standard output
+    /// until the editor sends `exit` or closes standard input.
+    pub async fn serve_stdio(&mut self) -> Result<()> {
+        embed::load_models(false)?;
+
+        let mut reader = BufReader::new(tokio::io::stdin());
+        let mut stdout = tokio::io::stdout();
+        while !self.exited {
+            let Some(message) = read_message(&mut reader).await? else {
+                break;
+            };
+            for reply in self.handle_message(&message).await {
+                write_message(&mut stdout, &reply).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a JSON-RPC message with the responses and notifications to send back.
+    async fn handle_message(&mut self, message: &str) -> Vec<Value> {
+        let request: Value = match serde_json::from_str(message) {
+            Ok(x) => x,
+            Err(e) => {
+                return vec![response(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, e.to_string())),
+                )];
+            }
+        };
+        let method = request["method"].as_str().unwrap_or_default();
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        match request.get("id").cloned() {
+            Some(id) => vec![response(id, self.handle_request(method, params).await)],
+            None => self.handle_notification(method, params).await,
+        }
+    }
+
+    async fn handle_request(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        if method == "initialize" {
+            return Ok(self.initialize(&params));
+        }
+        if !self.initialized {
+            return Err(RpcError::new(
+                SERVER_NOT_INITIALIZED,
+                "server not initialized",
+            ));
+        }
+        if self.shut_down {
+            return Err(RpcError::new(INVALID_REQUEST, "server shut down"));
+        }
+
+        match method {
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "workspace/symbol" => {
+                let params: WorkspaceSymbolParams = parse_params(params)?;
+                // Editors ask with an empty query as soon as the symbol picker opens.
+                if params.query.trim().is_empty() {
+                    return Ok(json!([]));
+                }
+                let options = self.search_options(None, SearchFilter::default());
+                let results = search::search(&self.db_file_uri, &params.query, &options).await?;
+                Ok(results.iter().filter_map(symbol_information).collect())
+            }
+            "semanticSearch" => {
+                let params: SemanticSearchParams = parse_params(params)?;
+                let mut options = self.search_options(params.limit, params.filter);
+                options.mode = params
+                    .mode
+                    .unwrap_or_default()
+                    .to_mode(DEFAULT_VECTOR_WEIGHT);
+                let results = search::search(&self.db_file_uri, &params.query, &options).await?;
+                Ok(results.iter().filter_map(result_item).collect())
+            }
+            "textDocument/codeAction" => {
+                let params: CodeActionParams = parse_params(params)?;
+                if params.range.start == params.range.end {
+                    return Ok(json!([]));
+                }
+                let arguments = FindSimilarArguments {
+                    uri: params.text_document.uri,
+                    range: params.range,
+                };
+                Ok(json!([{
+                    "title": "Find similar code",
+                    "command": {
+                        "title": "Find similar code",
+                        "command": FIND_SIMILAR_COMMAND,
+                        "arguments": [arguments],
+                    },
+                }]))
+            }
+            "workspace/executeCommand" => {
+                let params: ExecuteCommandParams = parse_params(params)?;
+                if params.command != FIND_SIMILAR_COMMAND {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        format!("unknown command {}", params.command),
+                    ));
+                }
+                let arguments: FindSimilarArguments =
+                    parse_params(params.arguments.into_iter().next().unwrap_or_default())?;
+                let path = uri_to_path(&arguments.uri)
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "expected a file URI"))?;
+                let (start_line, end_line) = arguments.range.rows();
+                let target = SimilarTo::Lines {
+                    file_path: self
+                        .table_path(&path)
+                        .unwrap_or(path)
+                        .to_string_lossy()
+                        .to_string(),
+                    start_line,
+                    end_line,
+                };
+                let options = self.search_options(None, SearchFilter::default());
+                let results = search::find_similar(&self.db_file_uri, &target, &options).await?;
+                Ok(results.iter().filter_map(result_item).collect())
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {method}"),
+            )),
+        }
+    }
+
+    async fn handle_notification(&mut self, method: &str, params: Value) -> Vec<Value> {
+        match method {
+            "exit" => {
+                self.exited = true;
+                vec![]
+            }
+            "textDocument/didSave" if self.initialized => {
+                let Ok(params) = parse_params::<DidSaveParams>(params) else {
+                    return vec![];
+                };
+                match self.reindex(&params.text_document.uri).await {
+                    Ok(Some(message)) => vec![log_message(LOG_INFO, &message)],
+                    Ok(None) => vec![],
+                    Err(e) => vec![log_message(
+                        LOG_ERROR,
+                        &format!("Failed to re-index {}: {e}", params.text_document.uri),
+                    )],
+                }
+            }
+            // Other notifications, such as `initialized` or `textDocument/didOpen`, need nothing.
+            _ => vec![],
+        }
+    }
+
+    fn initialize(&mut self, params: &Value) -> Value {
+        let workspace_root = params["workspaceFolders"][0]["uri"]
+            .as_str()
+            .or_else(|| params["rootUri"].as_str())
+            .and_then(uri_to_path)
+            .or_else(|| params["rootPath"].as_str().map(PathBuf::from));
+        self.root = self
+            .options
+            .root
+            .clone()
+            .or(workspace_root)
+            .and_then(|root| {
+                let canonical_root = fs::canonicalize(&root).ok()?;
+                Some((root, canonical_root))
+            });
+        self.initialized = true;
+
+        json!({
+            "capabilities": {
+                "textDocumentSync": {
+                    "openClose": true,
+                    "change": 0,
+                    "save": { "includeText": false },
+                },
+                "workspaceSymbolProvider": true,
+                "codeActionProvider": true,
+                "executeCommandProvider": { "commands": [FIND_SIMILAR_COMMAND] },
+            },
+            "serverInfo": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    fn search_options(&self, limit: Option<usize>, filter: SearchFilter) -> SearchOptions {
+        SearchOptions {
+            table_name: self.options.index.table_name.clone(),
+            limit: limit.unwrap_or(self.options.limit),
+            metric: self.options.metric,
+            filter,
+            ..Default::default()
+        }
+    }
+
+    /// Maps `path` to the same path below the root, as stored in the table, unless it is outside
+    /// the root or ignored.
+    fn table_path(&self, path: &Path) -> Option<PathBuf> {
+        let (root, canonical_root) = self.root.as_ref()?;
+        to_root_path(root, canonical_root, path)
+    }
+
+    /// Replaces the chunks of the saved file at `uri`, returning what was done, or `None` if the
+    /// file is not below the root.
+    async fn reindex(&self, uri: &str) -> Result<Option<String>> {
+        let (Some((root, _)), Some(path)) = (&self.root, uri_to_path(uri)) else {
+            return Ok(None);
+        };
+        let Some(path) = self.table_path(&path) else {
+            return Ok(None);
+        };
+        let options = WatchOptions {
+            split: self.options.split.clone(),
+            index: self.options.index.clone(),
+            ..Default::default()
+        };
+        let report = reindex_paths(root, &[path], &self.db_file_uri, &options).await?;
+        Ok(Some(format!(
+            "Re-indexed {}: {} chunks removed, {} inserted",
+            uri, report.chunks_deleted, report.chunks_inserted
+        )))
+    }
+}
+
+/// Reads the body of the next message, or returns `None` at the end of the input.
+async fn read_message(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        // Other headers, such as `Content-Type`, are ignored.
+        if let Some((_, value)) = line
+            .split_once(':')
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Content-Length {length} exceeds the limit of {MAX_MESSAGE_LENGTH} bytes"),
+        )
+        .into());
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn write_message(writer: &mut (impl AsyncWrite + Unpin), message: &Value) -> Result<()> {
+    let body = message.to_string();
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n{body}", body.len()).as_bytes())
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+fn log_message(kind: u8, message: &str) -> Value {
+    notification(
+        "window/logMessage",
+        json!({ "type": kind, "message": message }),
+    )
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+/// Returns the URI of a chunk path, resolving relative paths against the working directory.
+fn path_to_uri(path: &str) -> Option<String> {
+    let path = std::path::absolute(path).ok()?;
+    Some(Url::from_file_path(path).ok()?.to_string())
+}
+
+/// Returns the range of the lines of a result, up to the start of the row after them.
+fn result_range(result: &SearchResult) -> Range {
+    Range {
+        start: Position {
+            line: result.start_line,
+            character: 0,
+        },
+        end: Position {
+            line: result.end_line.max(result.start_line + 1),
+            character: 0,
+        },
+    }
+}
+
+fn result_item(result: &SearchResult) -> Option<Value> {
+    Some(json!({
+        "id": result.id,
+        "uri": path_to_uri(&result.file_path)?,
+        "range": result_range(result),
+        "score": result.score,
+        "rerankScore": result.rerank_score,
+        "language": result.language,
+        "symbolKind": result.symbol_kind,
+        "symbolName": result.symbol_name,
+        "text": result.text,
+    }))
+}
+
+fn symbol_information(result: &SearchResult) -> Option<Value> {
+    let name = match &result.symbol_name {
+        Some(name) => name.clone(),
+        None => format!("{}:{}", result.file_name, result.start_line + 1),
+    };
+    Some(json!({
+        "name": name,
+        "kind": lsp_symbol_kind(result.symbol_kind.as_deref()),
+        "location": {
+            "uri": path_to_uri(&result.file_path)?,
+            "range": result_range(result),
+        },
+        "containerName": result.file_name,
+    }))
+}
+
+/// Returns the LSP `SymbolKind` closest to the kind of definition a chunk belongs to.
+fn lsp_symbol_kind(kind: Option<&str>) -> u8 {
+    match kind {
+        Some("module") => 2,
+        Some("class" | "impl" | "type") => 5,
+        Some("method") => 6,
+        Some("enum") => 10,
+        Some("trait" | "interface") => 11,
+        Some("function") => 12,
+        Some("constant") => 14,
+        Some("struct") => 23,
+        // Chunks outside any definition are shown as files.
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn frame(message: &Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    #[tokio::test]
+    async fn test_read_and_write_message() -> anyhow::Result<()> {
+        let first = json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" });
+        let second = json!({ "jsonrpc": "2.0", "method": "exit", "params": { "é": "ü" } });
+        let input = format!("{}{}", frame(&first), frame(&second));
+        let mut reader = input.as_bytes();
+        assert_eq!(read_message(&mut reader).await?.unwrap(), first.to_string());
+        assert_eq!(
+            read_message(&mut reader).await?.unwrap(),
+            second.to_string()
+        );
+        assert_eq!(read_message(&mut reader).await?, None);
+
+        let mut output = Vec::new();
+        write_message(&mut output, &first).await?;
+        assert_eq!(String::from_utf8(output)?, frame(&first));
+
+        let input = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE_LENGTH + 1);
+        assert!(matches!(
+            read_message(&mut input.as_bytes()).await,
+            Err(crate::Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = Url::from_file_path(temp_dir.path()).unwrap().to_string();
+        let mut server = LspServer::new(
+            temp_dir.path().join("test.db").to_string_lossy(),
+            LspOptions::default(),
+        );
+
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "workspace/symbol" });
+        let replies = server.handle_message(&request.to_string()).await;
+        assert_eq!(replies[0]["error"]["code"], SERVER_NOT_INITIALIZED);
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "initialize",
+            "params": { "rootUri": root, "capabilities": {} },
+        });
+        let replies = server.handle_message(&request.to_string()).await;
+        let capabilities = &replies[0]["result"]["capabilities"];
+        assert_eq!(capabilities["workspaceSymbolProvider"], true);
+        assert_eq!(
+            capabilities["executeCommandProvider"]["commands"][0],
+            FIND_SIMILAR_COMMAND
+        );
+        assert!(server.root.is_some());
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "workspace/symbol",
+            "params": { "query": "" },
+        });
+        let replies = server.handle_message(&request.to_string()).await;
+        assert_eq!(replies[0]["result"], json!([]));
+
+        let uri = format!("{root}/lib.rs");
+        let range = json!({
+            "start": { "line": 2, "character": 0 },
+            "end": { "line": 5, "character": 3 },
+        });
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 4,
+            "method": "textDocument/codeAction",
+            "params": { "textDocument": { "uri": uri }, "range": range, "context": {} },
+        });
+        let replies = server.handle_message(&request.to_string()).await;
+        let command = &replies[0]["result"][0]["command"];
+        assert_eq!(command["command"], FIND_SIMILAR_COMMAND);
+        assert_eq!(
+            command["arguments"][0],
+            json!({ "uri": uri, "range": range })
+        );
+
+        let request = json!({ "jsonrpc": "2.0", "id": 5, "method": "textDocument/hover" });
+        let replies = server.handle_message(&request.to_string()).await;
+        assert_eq!(replies[0]["error"]["code"], METHOD_NOT_FOUND);
+
+        let request = json!({ "jsonrpc": "2.0", "id": 6, "method": "shutdown" });
+        let replies = server.handle_message(&request.to_string()).await;
+        assert_eq!(replies[0]["result"], Value::Null);
+        let request = json!({ "jsonrpc": "2.0", "method": "exit" });
+        assert!(server.handle_message(&request.to_string()).await.is_empty());
+        assert!(server.exited);
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_rows() {
+        let range = |start_line, end_line, end_character| Range {
+            start: Position {
+                line: start_line,
+                character: 4,
+            },
+            end: Position {
+                line: end_line,
+                character: end_character,
+            },
+        };
+        assert_eq!(range(2, 5, 3).rows(), (2, 6));
+        // A selection ending at the start of a line leaves that line out.
+        assert_eq!(range(2, 5, 0).rows(), (2, 5));
+        assert_eq!(range(2, 2, 0).rows(), (2, 3));
+    }
+}
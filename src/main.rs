@@ -0,0 +1,729 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use semantic_code_search::context::{ContextOptions, DEFAULT_TOKEN_BUDGET};
+use semantic_code_search::duplicates::{
+    DEFAULT_MAX_NEIGHBORS, DEFAULT_SIMILARITY_THRESHOLD, DuplicateOptions,
+};
+use semantic_code_search::filter::SearchFilter;
+use semantic_code_search::index::{
+    DEFAULT_REBUILD_THRESHOLD, DEFAULT_TABLE_NAME, DistanceMetric, IndexOptions,
+    VectorIndexOptions, VectorIndexType,
+};
+use semantic_code_search::lsp::{LspOptions, LspServer};
+use semantic_code_search::mcp::{McpOptions, McpServer};
+use semantic_code_search::search::{
+    DEFAULT_LIMIT, DEFAULT_MMR_LAMBDA, DEFAULT_RERANK_CANDIDATES, DEFAULT_VECTOR_WEIGHT,
+    MmrOptions, RerankOptions, SearchMode, SearchModeKind, SearchOptions, SimilarTo,
+};
+use semantic_code_search::split::{
+    Classification, DEFAULT_MAX_FILE_SIZE, DEFAULT_MAX_LINE_LENGTH, DEFAULT_ROW_GROUP_SIZE,
+    SplitOptions, SymbolKind, SymlinkPolicy,
+};
+use semantic_code_search::topics::{
+    DEFAULT_MAX_ITERATIONS, DEFAULT_SEED, DEFAULT_TOPIC_COUNT, TopicOptions,
+};
+use semantic_code_search::tui::{DEFAULT_CONTEXT_LINES, TuiOptions};
+use semantic_code_search::watch::WatchOptions;
+use semantic_code_search::{
+    Result, codebases, context, duplicates, embed, index, search, split, topics, tui, watch,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Split, embed and index source code for semantic search"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Split a directory, a git revision or an archive into chunks, written to a Parquet file.
+    Split {
+        input: String,
+        output: String,
+        /// Split this revision of the git repository at INPUT instead of its working directory.
+        #[arg(long, conflicts_with = "archive")]
+        revision: Option<String>,
+        /// Treat INPUT as a tar, tar.gz or zip archive.
+        #[arg(long)]
+        archive: bool,
+        #[command(flatten)]
+        split: SplitArgs,
+    },
+    /// Add embeddings to the chunks in a Parquet file.
+    Embed { input: String, output: String },
+    /// Index embedded chunks into a LanceDB database.
+    Index {
+        input: String,
+        db: String,
+        #[command(flatten)]
+        index: IndexArgs,
+    },
+    /// Update the index of a git repository from one revision to another.
+    IndexDiff {
+        repository_path: String,
+        from_revision: String,
+        to_revision: String,
+        db: String,
+        #[command(flatten)]
+        split: SplitArgs,
+        #[command(flatten)]
+        index: IndexArgs,
+    },
+    /// Keep the index of a directory up to date as its files change.
+    Watch {
+        root: String,
+        db: String,
+        /// Milliseconds a path must go without changes before it is re-indexed.
+        #[arg(long, default_value_t = 500)]
+        debounce_ms: u64,
+        #[command(flatten)]
+        split: SplitArgs,
+        #[command(flatten)]
+        index: IndexArgs,
+    },
+    /// Build or rebuild the vector index of a table.
+    CreateIndex {
+        db: String,
+        #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+        table: String,
+        #[command(flatten)]
+        vector_index: VectorIndexArgs,
+    },
+    /// Search a table for the chunks that best match a query, printed as JSON.
+    Search {
+        db: String,
+        query: String,
+        #[arg(long, value_enum, default_value_t = SearchModeKind::Vector)]
+        mode: SearchModeKind,
+        /// Weight of the vector ranking in hybrid search, from 0 to 1.
+        #[arg(long, default_value_t = DEFAULT_VECTOR_WEIGHT)]
+        vector_weight: f32,
+        #[command(flatten)]
+        search: SearchArgs,
+    },
+    /// Search a table interactively, previewing results and opening them in $EDITOR.
+    Tui {
+        db: String,
+        #[arg(long, value_enum, default_value_t = SearchModeKind::Vector)]
+        mode: SearchModeKind,
+        /// Weight of the vector ranking in hybrid search, from 0 to 1.
+        #[arg(long, default_value_t = DEFAULT_VECTOR_WEIGHT)]
+        vector_weight: f32,
+        /// Number of lines shown before and after a result in the preview.
+        #[arg(long, default_value_t = DEFAULT_CONTEXT_LINES)]
+        context_lines: usize,
+        #[command(flatten)]
+        search: SearchArgs,
+    },
+    /// Pack the best matches for a query into a context for a prompt, under a token budget.
+    Context {
+        db: String,
+        query: String,
+        #[arg(long, value_enum, default_value_t = SearchModeKind::Vector)]
+        mode: SearchModeKind,
+        /// Weight of the vector ranking in hybrid search, from 0 to 1.
+        #[arg(long, default_value_t = DEFAULT_VECTOR_WEIGHT)]
+        vector_weight: f32,
+        /// Maximum number of tokens of the context.
+        #[arg(long, default_value_t = DEFAULT_TOKEN_BUDGET)]
+        token_budget: usize,
+        /// Keep matches as they are rather than widening them to their enclosing definitions.
+        #[arg(long)]
+        no_expand: bool,
+        /// Markdown prints the context text alone; JSON adds its sections and token count.
+        #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+        format: ReportFormat,
+        #[command(flatten)]
+        search: SearchArgs,
+    },
+    /// Find the chunks most similar to a chunk or to lines of a file, printed as JSON.
+    Similar {
+        db: String,
+        #[command(flatten)]
+        target: SimilarToArgs,
+        /// Lines of --file, counted from 1, such as `10-25`.
+        #[arg(long, value_parser = parse_line_range, requires = "file")]
+        lines: Option<(u64, u64)>,
+        #[command(flatten)]
+        search: SearchArgs,
+    },
+    /// Report near-duplicate chunks and clusters of them.
+    Duplicates {
+        db: String,
+        #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+        table: String,
+        /// Lowest cosine similarity for two chunks to be duplicates.
+        #[arg(long, default_value_t = DEFAULT_SIMILARITY_THRESHOLD)]
+        threshold: f32,
+        /// Number of nearest chunks compared with every chunk.
+        #[arg(long, default_value_t = DEFAULT_MAX_NEIGHBORS)]
+        max_neighbors: usize,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+        format: ReportFormat,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Cluster the chunk embeddings into labelled topics, printed as JSON with a 2D projection.
+    Topics {
+        db: String,
+        #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+        table: String,
+        /// Number of topics to cluster the chunks into.
+        #[arg(long, default_value_t = DEFAULT_TOPIC_COUNT)]
+        topics: usize,
+        #[arg(long, default_value_t = DEFAULT_MAX_ITERATIONS)]
+        max_iterations: usize,
+        #[arg(long, default_value_t = DEFAULT_SEED)]
+        seed: u64,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Serve the search tools to Model Context Protocol clients over standard input and output.
+    Mcp {
+        db: String,
+        #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+        table: String,
+        /// Distance between embeddings; must match the vector index, if there is one.
+        #[arg(long, value_enum, default_value_t = DistanceMetric::Cosine)]
+        metric: DistanceMetric,
+        /// Load the cross-encoder model, so that tools can rerank their results.
+        #[arg(long)]
+        rerank: bool,
+    },
+    /// Serve semantic search to editors as a language server over standard input and output,
+    /// re-indexing files as they are saved.
+    Lsp {
+        db: String,
+        /// Directory the table was split from, given the same way; the workspace root by
+        /// default.
+        #[arg(long)]
+        root: Option<PathBuf>,
+        #[arg(long, default_value_t = DEFAULT_LIMIT)]
+        limit: usize,
+        #[command(flatten)]
+        split: SplitArgs,
+        #[command(flatten)]
+        index: IndexArgs,
+    },
+    /// List, describe or drop the codebases in a table.
+    Codebases {
+        db: String,
+        #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+        table: String,
+        #[command(subcommand)]
+        command: CodebasesCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum CodebasesCommand {
+    List,
+    Describe {
+        repository: String,
+        #[arg(long)]
+        branch: Option<String>,
+    },
+    Drop {
+        repository: String,
+        #[arg(long)]
+        branch: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct SplitArgs {
+    #[arg(long, default_value_t = DEFAULT_ROW_GROUP_SIZE)]
+    row_group_size: usize,
+    /// Number of worker threads; one per available CPU by default.
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Skip files larger than this many bytes; 0 disables the limit.
+    #[arg(long, default_value_t = DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+    /// Skip files with a line longer than this many bytes; 0 disables the limit.
+    #[arg(long, default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    max_line_length: usize,
+    /// Write the run report as JSON to this path.
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Record the last commit, author and time of every chunk.
+    #[arg(long)]
+    blame: bool,
+    /// Split the files inside archives found in directories.
+    #[arg(long)]
+    expand_archives: bool,
+    #[arg(long, value_enum, default_value_t = SymlinkPolicy::FollowFiles)]
+    symlinks: SymlinkPolicy,
+    /// Repository identifier stored with every chunk, instead of the detected one.
+    #[arg(long)]
+    repository: Option<String>,
+    /// Branch stored with every chunk, instead of the detected one.
+    #[arg(long)]
+    branch: Option<String>,
+}
+
+impl SplitArgs {
+    fn to_options(&self) -> SplitOptions {
+        SplitOptions {
+            row_group_size: self.row_group_size,
+            threads: self.threads,
+            max_file_size: Some(self.max_file_size).filter(|x| *x > 0),
+            max_line_length: Some(self.max_line_length).filter(|x| *x > 0),
+            report_path: self.report.clone(),
+            blame: self.blame,
+            expand_archives: self.expand_archives,
+            symlinks: self.symlinks,
+            repository: self.repository.clone(),
+            branch: self.branch.clone(),
+        }
+    }
+}
+
+#[derive(Args)]
+struct IndexArgs {
+    #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+    table: String,
+    /// Maintain a vector index, rebuilt once enough rows are missing from it.
+    #[arg(long)]
+    vector_index: bool,
+    /// Do not maintain a full-text index on the chunk text.
+    #[arg(long)]
+    no_full_text_index: bool,
+    /// Number of rows missing from an index that triggers a rebuild; at least 256.
+    #[arg(long, default_value_t = DEFAULT_REBUILD_THRESHOLD)]
+    rebuild_threshold: usize,
+    #[command(flatten)]
+    vector_index_options: VectorIndexArgs,
+}
+
+impl IndexArgs {
+    fn to_options(&self) -> IndexOptions {
+        IndexOptions {
+            table_name: self.table.clone(),
+            vector_index: self
+                .vector_index
+                .then(|| self.vector_index_options.to_options()),
+            full_text_index: !self.no_full_text_index,
+            rebuild_threshold: self.rebuild_threshold,
+        }
+    }
+}
+
+#[derive(Args)]
+struct VectorIndexArgs {
+    #[arg(long, value_enum, default_value_t = VectorIndexType::IvfPq)]
+    index_type: VectorIndexType,
+    #[arg(long, value_enum, default_value_t = DistanceMetric::Cosine)]
+    metric: DistanceMetric,
+    /// Number of IVF partitions; picked from the number of rows by default.
+    #[arg(long)]
+    partitions: Option<u32>,
+    /// Number of PQ sub-vectors; picked from the embedding dimension by default.
+    #[arg(long)]
+    sub_vectors: Option<u32>,
+}
+
+impl VectorIndexArgs {
+    fn to_options(&self) -> VectorIndexOptions {
+        VectorIndexOptions {
+            index_type: self.index_type,
+            metric: self.metric,
+            num_partitions: self.partitions,
+            num_sub_vectors: self.sub_vectors,
+        }
+    }
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+    table: String,
+    #[arg(long, default_value_t = DEFAULT_LIMIT)]
+    limit: usize,
+    /// Distance between embeddings; must match the vector index, if there is one.
+    #[arg(long, value_enum, default_value_t = DistanceMetric::Cosine)]
+    metric: DistanceMetric,
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// Reorder the results with a cross-encoder model.
+    #[arg(long)]
+    rerank: bool,
+    /// Number of first-stage results to rerank.
+    #[arg(long, default_value_t = DEFAULT_RERANK_CANDIDATES)]
+    rerank_candidates: usize,
+    /// Diversify the results with maximal marginal relevance.
+    #[arg(long)]
+    mmr: bool,
+    /// Weight of relevance against diversity, from 0 to 1.
+    #[arg(long, default_value_t = DEFAULT_MMR_LAMBDA)]
+    mmr_lambda: f32,
+    /// Return at most this many results from any one file.
+    #[arg(long)]
+    max_per_file: Option<usize>,
+}
+
+impl SearchArgs {
+    fn to_options(&self, mode: SearchMode) -> SearchOptions {
+        SearchOptions {
+            table_name: self.table.clone(),
+            limit: self.limit,
+            mode,
+            metric: self.metric,
+            filter: self.filter.to_filter(),
+            rerank: self.rerank.then_some(RerankOptions {
+                candidates: self.rerank_candidates,
+            }),
+            mmr: self.mmr.then_some(MmrOptions {
+                lambda: self.mmr_lambda,
+                ..Default::default()
+            }),
+            max_per_file: self.max_per_file,
+        }
+    }
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct SimilarToArgs {
+    /// ID of a chunk in the table.
+    #[arg(long)]
+    chunk_id: Option<String>,
+    /// Path of a file as stored in the table; requires --lines.
+    #[arg(long, requires = "lines")]
+    file: Option<String>,
+}
+
+/// Parses a range of lines counted from 1, such as `10-25`, into rows counted from 0 with the
+/// end excluded, like those of chunks.
+fn parse_line_range(value: &str) -> std::result::Result<(u64, u64), String> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| format!("expected START-END, found `{value}`"))?;
+    let start: u64 = start.trim().parse().map_err(|e| format!("{e}"))?;
+    let end: u64 = end.trim().parse().map_err(|e| format!("{e}"))?;
+    if start == 0 || end < start {
+        return Err(format!("invalid line range `{value}`"));
+    }
+    Ok((start - 1, end))
+}
+
+/// Conditions on the chunks to search; repeated options match any of their values.
+#[derive(Args)]
+struct FilterArgs {
+    #[arg(long = "language")]
+    languages: Vec<String>,
+    /// Glob matched against whole file paths, such as `**/src/**/*.rs`.
+    #[arg(long = "path-glob")]
+    path_globs: Vec<String>,
+    #[arg(long = "path-prefix")]
+    path_prefixes: Vec<String>,
+    #[arg(long = "file-name")]
+    file_names: Vec<String>,
+    #[arg(long = "repository")]
+    repositories: Vec<String>,
+    #[arg(long = "branch")]
+    branches: Vec<String>,
+    #[arg(long)]
+    min_lines: Option<u64>,
+    #[arg(long)]
+    max_lines: Option<u64>,
+    #[arg(long = "symbol-kind", value_enum)]
+    symbol_kinds: Vec<SymbolKind>,
+    #[arg(long = "classification", value_enum)]
+    classifications: Vec<Classification>,
+    /// Leaves out chunks of files with this classification, such as generated or vendored code.
+    #[arg(long = "exclude-classification", value_enum)]
+    excluded_classifications: Vec<Classification>,
+    /// Only chunks last changed after this time, in seconds since the Unix epoch; needs blame
+    /// information.
+    #[arg(long)]
+    modified_after: Option<i64>,
+    /// Only chunks last changed before this time, in seconds since the Unix epoch; needs blame
+    /// information.
+    #[arg(long)]
+    modified_before: Option<i64>,
+}
+
+impl FilterArgs {
+    fn to_filter(&self) -> SearchFilter {
+        let mut filter = SearchFilter::default();
+        for language in &self.languages {
+            filter = filter.with_language(language);
+        }
+        for glob in &self.path_globs {
+            filter = filter.with_path_glob(glob);
+        }
+        for prefix in &self.path_prefixes {
+            filter = filter.with_path_prefix(prefix);
+        }
+        for file_name in &self.file_names {
+            filter = filter.with_file_name(file_name);
+        }
+        for repository in &self.repositories {
+            filter = filter.with_repository(repository);
+        }
+        for branch in &self.branches {
+            filter = filter.with_branch(branch);
+        }
+        filter = match (self.min_lines, self.max_lines) {
+            (None, None) => filter,
+            (min, max) => filter.with_line_count(min.unwrap_or(0)..=max.unwrap_or(u64::MAX)),
+        };
+        for kind in &self.symbol_kinds {
+            filter = filter.with_symbol_kind(*kind);
+        }
+        for classification in &self.classifications {
+            filter = filter.with_classification(*classification);
+        }
+        for classification in &self.excluded_classifications {
+            filter = filter.exclude_classification(*classification);
+        }
+        if let Some(time) = self.modified_after {
+            filter = filter.with_modified_after(time);
+        }
+        if let Some(time) = self.modified_before {
+            filter = filter.with_modified_before(time);
+        }
+        filter
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Json,
+    Markdown,
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Split {
+            input,
+            output,
+            revision,
+            archive,
+            split: split_args,
+        } => {
+            let options = split_args.to_options();
+            let report = match (revision, archive) {
+                (Some(revision), _) => {
+                    split::find_and_split_git(input, revision, output, &options)?
+                }
+                (None, true) => split::find_and_split_archive(input, output, &options)?,
+                (None, false) => split::find_and_split_with_options(input, output, &options)?,
+            };
+            print_json(&report)
+        }
+        Command::Embed { input, output } => embed::create_embeddings_from_file(input, output),
+        Command::Index {
+            input,
+            db,
+            index: index_args,
+        } => index::index_with_options(input, db, &index_args.to_options()).await,
+        Command::IndexDiff {
+            repository_path,
+            from_revision,
+            to_revision,
+            db,
+            split: split_args,
+            index: index_args,
+        } => {
+            let report = index::index_git_diff(
+                repository_path,
+                from_revision,
+                to_revision,
+                db,
+                &split_args.to_options(),
+                &index_args.to_options(),
+            )
+            .await?;
+            print_json(&report)
+        }
+        Command::Watch {
+            root,
+            db,
+            debounce_ms,
+            split: split_args,
+            index: index_args,
+        } => {
+            let options = WatchOptions {
+                debounce: Duration::from_millis(debounce_ms),
+                split: split_args.to_options(),
+                index: index_args.to_options(),
+            };
+            watch::watch(root, db, &options, |report| match report {
+                Ok(report) => {
+                    if let Ok(json) = serde_json::to_string(&report) {
+                        println!("{json}");
+                    }
+                }
+                Err(e) => eprintln!("Failed to re-index: {e}"),
+            })
+            .await
+        }
+        Command::CreateIndex {
+            db,
+            table,
+            vector_index,
+        } => {
+            let status =
+                index::create_vector_index(&db, &table, &vector_index.to_options()).await?;
+            print_json(&status)
+        }
+        Command::Search {
+            db,
+            query,
+            mode,
+            vector_weight,
+            search: search_args,
+        } => {
+            let options = search_args.to_options(mode.to_mode(vector_weight));
+            print_json(&search::search(&db, &query, &options).await?)
+        }
+        Command::Tui {
+            db,
+            mode,
+            vector_weight,
+            context_lines,
+            search: search_args,
+        } => {
+            let options = TuiOptions {
+                search: search_args.to_options(mode.to_mode(vector_weight)),
+                context_lines,
+            };
+            tui::run(&db, &options).await
+        }
+        Command::Context {
+            db,
+            query,
+            mode,
+            vector_weight,
+            token_budget,
+            no_expand,
+            format,
+            search: search_args,
+        } => {
+            let options = ContextOptions {
+                search: search_args.to_options(mode.to_mode(vector_weight)),
+                token_budget,
+                expand_symbols: !no_expand,
+            };
+            let context = context::build_context(&db, &query, &options).await?;
+            match format {
+                ReportFormat::Json => print_json(&context),
+                ReportFormat::Markdown => {
+                    print!("{}", context.text);
+                    Ok(())
+                }
+            }
+        }
+        Command::Similar {
+            db,
+            target,
+            lines,
+            search: search_args,
+        } => {
+            let target = match (target.chunk_id, target.file, lines) {
+                (Some(id), _, _) => SimilarTo::Chunk { id },
+                (None, Some(file_path), Some((start_line, end_line))) => SimilarTo::Lines {
+                    file_path,
+                    start_line,
+                    end_line,
+                },
+                // Ruled out by the argument groups.
+                _ => unreachable!("either --chunk-id or --file and --lines is required"),
+            };
+            let options = search_args.to_options(SearchMode::Vector);
+            print_json(&search::find_similar(&db, &target, &options).await?)
+        }
+        Command::Duplicates {
+            db,
+            table,
+            threshold,
+            max_neighbors,
+            format,
+            filter,
+        } => {
+            let options = DuplicateOptions {
+                table_name: table,
+                threshold,
+                max_neighbors,
+                filter: filter.to_filter(),
+            };
+            let report = duplicates::find_duplicates(&db, &options).await?;
+            match format {
+                ReportFormat::Json => print_json(&report),
+                ReportFormat::Markdown => {
+                    print!("{}", report.to_markdown());
+                    Ok(())
+                }
+            }
+        }
+        Command::Topics {
+            db,
+            table,
+            topics,
+            max_iterations,
+            seed,
+            filter,
+        } => {
+            let options = TopicOptions {
+                table_name: table,
+                topics,
+                max_iterations,
+                seed,
+                filter: filter.to_filter(),
+            };
+            print_json(&topics::find_topics(&db, &options).await?)
+        }
+        Command::Mcp {
+            db,
+            table,
+            metric,
+            rerank,
+        } => {
+            let options = McpOptions {
+                table_name: table,
+                metric,
+                rerank,
+            };
+            McpServer::new(db, options).serve_stdio().await
+        }
+        Command::Lsp {
+            db,
+            root,
+            limit,
+            split: split_args,
+            index: index_args,
+        } => {
+            let options = LspOptions {
+                root,
+                limit,
+                metric: index_args.vector_index_options.metric,
+                split: split_args.to_options(),
+                index: index_args.to_options(),
+            };
+            LspServer::new(db, options).serve_stdio().await
+        }
+        Command::Codebases { db, table, command } => match command {
+            CodebasesCommand::List => print_json(&codebases::list_codebases(&db, &table).await?),
+            CodebasesCommand::Describe { repository, branch } => print_json(
+                &codebases::describe_codebase(&db, &table, &repository, branch.as_deref()).await?,
+            ),
+            CodebasesCommand::Drop { repository, branch } => print_json(
+                &codebases::drop_codebase(&db, &table, &repository, branch.as_deref()).await?,
+            ),
+        },
+    }
+}
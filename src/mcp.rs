@@ -0,0 +1,537 @@
+use crate::filter::SearchFilter;
+use crate::index::{DEFAULT_TABLE_NAME, DistanceMetric};
+use crate::jsonrpc::{
+    INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR, RpcError, parse_params,
+    response,
+};
+use crate::search::{
+    self, DEFAULT_LIMIT, DEFAULT_RERANK_CANDIDATES, DEFAULT_VECTOR_WEIGHT, RerankOptions,
+    SearchModeKind, SearchOptions, SimilarTo,
+};
+use crate::{Error, Result, embed};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Protocol versions the server speaks, latest first.
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+#[derive(Debug, Clone)]
+pub struct McpOptions {
+    /// Table the tools search.
+    pub table_name: String,
+    /// Distance between embeddings; must match the vector index, if there is one.
+    pub metric: DistanceMetric,
+    /// Whether to load the cross-encoder model, so that tools can rerank their results.
+    pub rerank: bool,
+}
+
+impl Default for McpOptions {
+    fn default() -> Self {
+        McpOptions {
+            table_name: DEFAULT_TABLE_NAME.to_string(),
+            metric: DistanceMetric::default(),
+            rerank: false,
+        }
+    }
+}
+
+/// A Model Context Protocol server exposing code search as tools, over the database at
+/// `db_file_uri`.
+///
+/// Tools report chunks with their lines counted from 1 and the last one included, as editors
+/// show them, unlike the rows stored in the table.
+pub struct McpServer {
+    db_file_uri: String,
+    options: McpOptions,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SearchCodeArguments {
+    query: String,
+    limit: Option<usize>,
+    mode: Option<SearchModeKind>,
+    #[serde(default)]
+    filter: SearchFilter,
+    #[serde(default)]
+    rerank: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FindSimilarArguments {
+    chunk_id: Option<String>,
+    file_path: Option<String>,
+    start_line: Option<u64>,
+    end_line: Option<u64>,
+    limit: Option<usize>,
+    #[serde(default)]
+    filter: SearchFilter,
+    #[serde(default)]
+    rerank: bool,
+}
+
+impl FindSimilarArguments {
+    /// Returns the chunk or lines to find similar chunks to, converting lines to rows.
+    fn target(&self) -> Result<SimilarTo, RpcError> {
+        match self {
+            FindSimilarArguments {
+                chunk_id: Some(id),
+                file_path: None,
+                ..
+            } => Ok(SimilarTo::Chunk { id: id.clone() }),
+            FindSimilarArguments {
+                chunk_id: None,
+                file_path: Some(file_path),
+                start_line: Some(start_line),
+                end_line: Some(end_line),
+                ..
+            } if *start_line >= 1 && end_line >= start_line => Ok(SimilarTo::Lines {
+                file_path: file_path.clone(),
+                start_line: start_line - 1,
+                end_line: *end_line,
+            }),
+            _ => Err(RpcError::new(
+                INVALID_PARAMS,
+                "expected either chunk_id, or file_path with start_line and end_line",
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GetChunkArguments {
+    id: String,
+}
+
+impl McpServer {
+    pub fn new(db_file_uri: impl Into<String>, options: McpOptions) -> Self {
+        McpServer {
+            db_file_uri: db_file_uri.into(),
+            options,
+        }
+    }
+
+    /// Loads the models, then answers newline-delimited JSON-RPC messages from standard input on
+    /// standard output until standard input is closed.
+    pub async fn serve_stdio(&self) -> Result<()> {
+        embed::load_models(self.options.rerank)?;
+
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let mut stdout = tokio::io::stdout();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle_message(&line).await {
+                stdout.write_all(response.as_bytes()).await?;
+                stdout.write_all(b"\n").await?;
+                stdout.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a JSON-RPC message, or returns `None` for notifications.
+    pub async fn handle_message(&self, message: &str) -> Option<String> {
+        let reply = match serde_json::from_str::<Value>(message) {
+            Ok(Value::Object(request)) => {
+                let id = request.get("id").cloned();
+                let method = request.get("method").and_then(Value::as_str);
+                let params = request.get("params").cloned().unwrap_or(Value::Null);
+                match (id, method) {
+                    // Notifications, such as `notifications/initialized`, need no answer.
+                    (None, _) => return None,
+                    (Some(id), Some(method)) => {
+                        response(id, self.handle_request(method, params).await)
+                    }
+                    (Some(id), None) => {
+                        response(id, Err(RpcError::new(INVALID_REQUEST, "missing method")))
+                    }
+                }
+            }
+            Ok(_) => response(
+                Value::Null,
+                Err(RpcError::new(INVALID_REQUEST, "expected a request object")),
+            ),
+            Err(e) => response(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+        };
+        Some(reply.to_string())
+    }
+
+    async fn handle_request(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "initialize" => {
+                let requested = params.get("protocolVersion").and_then(Value::as_str);
+                let version = PROTOCOL_VERSIONS
+                    .iter()
+                    .find(|x| Some(**x) == requested)
+                    .unwrap_or(&PROTOCOL_VERSIONS[0]);
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tools() })),
+            "tools/call" => {
+                let name = params.get("name").and_then(Value::as_str).unwrap_or("");
+                let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+                let output = match name {
+                    "search_code" => self.search_code(parse_params(arguments)?).await,
+                    "find_similar" => {
+                        let arguments: FindSimilarArguments = parse_params(arguments)?;
+                        let target = arguments.target()?;
+                        self.find_similar(&target, arguments).await
+                    }
+                    "get_chunk" => self.get_chunk(parse_params(arguments)?).await,
+                    _ => {
+                        return Err(RpcError::new(
+                            INVALID_PARAMS,
+                            format!("unknown tool {name}"),
+                        ));
+                    }
+                };
+                // Failures of the tools themselves are reported to the model, not the client.
+                Ok(match output {
+                    Ok(structured) => json!({
+                        "content": [{ "type": "text", "text": structured.to_string() }],
+                        "structuredContent": structured,
+                        "isError": false,
+                    }),
+                    Err(e) => json!({
+                        "content": [{ "type": "text", "text": e.to_string() }],
+                        "isError": true,
+                    }),
+                })
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {method}"),
+            )),
+        }
+    }
+
+    /// Returns the options of a search, which can only ask for reranking when the cross-encoder
+    /// model was loaded.
+    fn search_options(
+        &self,
+        limit: Option<usize>,
+        filter: SearchFilter,
+        rerank: bool,
+    ) -> Result<SearchOptions> {
+        if rerank && !self.options.rerank {
+            return Err(Error::RerankingDisabled);
+        }
+        Ok(SearchOptions {
+            table_name: self.options.table_name.clone(),
+            limit: limit.unwrap_or(DEFAULT_LIMIT),
+            metric: self.options.metric,
+            filter,
+            rerank: rerank.then_some(RerankOptions {
+                candidates: DEFAULT_RERANK_CANDIDATES,
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn search_code(&self, arguments: SearchCodeArguments) -> Result<Value> {
+        let mut options =
+            self.search_options(arguments.limit, arguments.filter, arguments.rerank)?;
+        options.mode = arguments
+            .mode
+            .unwrap_or_default()
+            .to_mode(DEFAULT_VECTOR_WEIGHT);
+        let results = search::search(&self.db_file_uri, &arguments.query, &options).await?;
+        results_content(results)
+    }
+
+    async fn find_similar(
+        &self,
+        target: &SimilarTo,
+        arguments: FindSimilarArguments,
+    ) -> Result<Value> {
+        let options = self.search_options(arguments.limit, arguments.filter, arguments.rerank)?;
+        let results = search::find_similar(&self.db_file_uri, target, &options).await?;
+        results_content(results)
+    }
+
+    async fn get_chunk(&self, arguments: GetChunkArguments) -> Result<Value> {
+        let chunk =
+            search::get_chunk(&self.db_file_uri, &self.options.table_name, &arguments.id).await?;
+        with_editor_lines(chunk)
+    }
+}
+
+fn results_content(results: Vec<search::SearchResult>) -> Result<Value> {
+    let results = results
+        .into_iter()
+        .map(with_editor_lines)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(json!({ "results": results }))
+}
+
+/// Serializes `value`, a chunk or a search result, with its lines counted from 1 and the last
+/// one included.
+fn with_editor_lines(value: impl Serialize) -> Result<Value> {
+    let mut value = serde_json::to_value(value)?;
+    if let (Some(start), Some(end)) = (value["start_line"].as_u64(), value["end_line"].as_u64()) {
+        value["start_line"] = (start + 1).into();
+        value["end_line"] = end.max(start + 1).into();
+    }
+    Ok(value)
+}
+
+fn tools() -> Value {
+    let filter = json!({
+        "type": "object",
+        "description": "Limits the chunks searched; every field holds values any of which may match, and chunks must match every field given.",
+        "properties": {
+            "languages": { "type": "array", "items": { "type": "string" } },
+            "path_globs": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Globs matched against whole file paths, such as **/src/**/*.rs.",
+            },
+            "path_prefixes": { "type": "array", "items": { "type": "string" } },
+            "file_names": { "type": "array", "items": { "type": "string" } },
+            "repositories": { "type": "array", "items": { "type": "string" } },
+            "branches": { "type": "array", "items": { "type": "string" } },
+            "min_lines": { "type": "integer", "minimum": 0 },
+            "max_lines": { "type": "integer", "minimum": 0 },
+            "symbol_kinds": {
+                "type": "array",
+                "items": {
+                    "type": "string",
+                    "enum": [
+                        "function", "method", "class", "struct", "enum", "trait", "interface",
+                        "impl", "module", "constant", "type",
+                    ],
+                },
+            },
+            "classifications": {
+                "type": "array",
+                "items": { "type": "string", "enum": ["source", "generated", "vendored"] },
+            },
+            "excluded_classifications": {
+                "type": "array",
+                "items": { "type": "string", "enum": ["source", "generated", "vendored"] },
+                "description": "Leaves out generated or vendored code, for instance.",
+            },
+            "modified_after": {
+                "type": "integer",
+                "description": "Only chunks last changed after this time, in seconds since the Unix epoch.",
+            },
+            "modified_before": {
+                "type": "integer",
+                "description": "Only chunks last changed before this time, in seconds since the Unix epoch.",
+            },
+        },
+        "additionalProperties": false,
+    });
+    let limit = json!({ "type": "integer", "minimum": 1, "default": DEFAULT_LIMIT });
+    let rerank = json!({
+        "type": "boolean",
+        "description": "Reorder the results with a cross-encoder; only available when the server was started with --rerank.",
+    });
+    let results = json!({
+        "type": "object",
+        "properties": {
+            "results": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string" },
+                        "file_path": { "type": "string" },
+                        "start_line": { "type": "integer" },
+                        "end_line": { "type": "integer" },
+                        "text": { "type": "string" },
+                        "score": { "type": "number" },
+                    },
+                    "required": ["id", "file_path", "start_line", "end_line", "text", "score"],
+                },
+            },
+        },
+        "required": ["results"],
+    });
+
+    json!([
+        {
+            "name": "search_code",
+            "description": "Search the indexed code for chunks matching a natural-language or keyword query, best first. Lines are counted from 1, last one included.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "limit": limit,
+                    "mode": {
+                        "type": "string",
+                        "enum": ["vector", "full_text", "hybrid"],
+                        "default": "vector",
+                        "description": "Match by meaning, by keywords, or both.",
+                    },
+                    "filter": filter,
+                    "rerank": rerank,
+                },
+                "required": ["query"],
+                "additionalProperties": false,
+            },
+            "outputSchema": results,
+        },
+        {
+            "name": "find_similar",
+            "description": "Find the chunks most similar to an indexed chunk, given its ID, or to lines of a file, given as indexed. Lines are counted from 1, last one included.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "chunk_id": { "type": "string" },
+                    "file_path": { "type": "string" },
+                    "start_line": { "type": "integer", "minimum": 1 },
+                    "end_line": { "type": "integer", "minimum": 1 },
+                    "limit": limit,
+                    "filter": filter,
+                    "rerank": rerank,
+                },
+                "additionalProperties": false,
+            },
+            "outputSchema": results,
+        },
+        {
+            "name": "get_chunk",
+            "description": "Read an indexed chunk, with its text and metadata, by ID. Lines are counted from 1, last one included.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "id": { "type": "string" } },
+                "required": ["id"],
+                "additionalProperties": false,
+            },
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn call(server: &McpServer, request: Value) -> Value {
+        let response = server.handle_message(&request.to_string()).await.unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_message() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let server = McpServer::new(
+            temp_dir.path().join("test.db").to_string_lossy(),
+            McpOptions::default(),
+        );
+
+        let response = call(
+            &server,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "protocolVersion": "2025-03-26", "capabilities": {} },
+            }),
+        )
+        .await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+        assert!(response["result"]["capabilities"]["tools"].is_object());
+
+        let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert_eq!(server.handle_message(&notification.to_string()).await, None);
+
+        let response = call(
+            &server,
+            json!({ "jsonrpc": "2.0", "id": "list", "method": "tools/list" }),
+        )
+        .await;
+        let names: Vec<&str> = response["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["search_code", "find_similar", "get_chunk"]);
+
+        // A chunk that cannot be read is a tool failure, reported in the result.
+        let response = call(
+            &server,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "tools/call",
+                "params": { "name": "get_chunk", "arguments": { "id": "missing" } },
+            }),
+        )
+        .await;
+        assert_eq!(response["result"]["isError"], true);
+
+        // So is asking for reranking when the cross-encoder model was not loaded.
+        let response = call(
+            &server,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 5,
+                "method": "tools/call",
+                "params": {
+                    "name": "search_code",
+                    "arguments": { "query": "parse", "rerank": true },
+                },
+            }),
+        )
+        .await;
+        assert_eq!(response["result"]["isError"], true);
+        assert!(
+            response["result"]["content"][0]["text"]
+                .as_str()
+                .unwrap()
+                .contains("--rerank")
+        );
+
+        // Malformed arguments are a protocol error.
+        let response = call(
+            &server,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "tools/call",
+                "params": { "name": "find_similar", "arguments": { "start_line": 3 } },
+            }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+        let response = call(
+            &server,
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "resources/list" }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let response: Value =
+            serde_json::from_str(&server.handle_message("{not json").await.unwrap())?;
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_editor_lines() -> anyhow::Result<()> {
+        let value = with_editor_lines(json!({ "start_line": 4, "end_line": 9 }))?;
+        assert_eq!(value, json!({ "start_line": 5, "end_line": 9 }));
+        // Empty chunks still span the line they start on.
+        let value = with_editor_lines(json!({ "start_line": 4, "end_line": 4 }))?;
+        assert_eq!(value, json!({ "start_line": 5, "end_line": 5 }));
+        Ok(())
+    }
+}
@@ -0,0 +1,1197 @@
+use crate::embed::{create_embeddings_from_string, rerank};
+use crate::filter::SearchFilter;
+use crate::index::{
+    DEFAULT_TABLE_NAME, DistanceMetric, EMBEDDING_COLUMN, open_existing_table, quote_literal,
+};
+use crate::{Error, Result};
+use clap::ValueEnum;
+use lancedb::Table;
+use lancedb::arrow::IntoPolars;
+use lancedb::index::scalar::FullTextSearchQuery;
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use polars::prelude::{DataFrame, DataType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+/// Number of results returned by default.
+pub const DEFAULT_LIMIT: usize = 10;
+
+/// Weight of the vector ranking in hybrid search by default.
+pub const DEFAULT_VECTOR_WEIGHT: f32 = 0.5;
+
+/// Number of first-stage results reranked by default.
+pub const DEFAULT_RERANK_CANDIDATES: usize = 50;
+
+/// Smoothing constant of reciprocal rank fusion, as in the original paper by Cormack et al.
+const RRF_K: f32 = 60.0;
+
+/// Number of candidates taken from each ranking, per requested result, in hybrid search.
+const HYBRID_CANDIDATES_PER_RESULT: usize = 4;
+
+/// Number of candidates considered per requested result when limiting results per file.
+const COLLAPSE_CANDIDATES_PER_RESULT: usize = 4;
+
+/// Number of candidates diversified by default.
+pub const DEFAULT_MMR_CANDIDATES: usize = 50;
+
+/// Weight of relevance against diversity by default.
+pub const DEFAULT_MMR_LAMBDA: f32 = 0.5;
+
+/// Columns returned for every result, besides the score.
+const RESULT_COLUMNS: &[&str] = &[
+    "id",
+    "file_path",
+    "file_name",
+    "start_line",
+    "end_line",
+    "text",
+    "language",
+    "symbol_kind",
+    "symbol_name",
+    "repository",
+    "branch",
+    "last_author",
+    "last_modified",
+];
+
+/// Columns of a [`Chunk`].
+const CHUNK_COLUMNS: &[&str] = &[
+    "id",
+    "file_path",
+    "file_name",
+    "start_line",
+    "end_line",
+    "text",
+    "language",
+    "classification",
+    "symbol_kind",
+    "symbol_name",
+    "repository",
+    "branch",
+    "commit",
+    "last_commit",
+    "last_author",
+    "last_modified",
+];
+
+const ROW_ID_COLUMN: &str = "_rowid";
+const DISTANCE_COLUMN: &str = "_distance";
+const FULL_TEXT_SCORE_COLUMN: &str = "_score";
+
+/// How queries are matched against chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SearchMode {
+    /// Nearest embeddings to the embedding of the query, which finds paraphrases.
+    #[default]
+    Vector,
+    /// BM25 ranking of the chunk text, which finds exact identifiers. Needs the full-text index.
+    FullText,
+    /// Both rankings, fused with reciprocal rank fusion.
+    Hybrid {
+        /// Weight of the vector ranking, from 0 to 1; the full-text ranking gets the rest.
+        vector_weight: f32,
+    },
+}
+
+/// Kind of [`SearchMode`], as picked on the command line and in requests, where the weight of
+/// the vector ranking in hybrid search is a separate setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchModeKind {
+    #[default]
+    Vector,
+    // Language server parameters are in camel case.
+    #[serde(alias = "fullText")]
+    FullText,
+    Hybrid,
+}
+
+impl SearchModeKind {
+    pub fn to_mode(self, vector_weight: f32) -> SearchMode {
+        match self {
+            SearchModeKind::Vector => SearchMode::Vector,
+            SearchModeKind::FullText => SearchMode::FullText,
+            SearchModeKind::Hybrid => SearchMode::Hybrid { vector_weight },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Table to search.
+    pub table_name: String,
+    /// Maximum number of results.
+    pub limit: usize,
+    pub mode: SearchMode,
+    /// Distance between embeddings; must match the vector index, if there is one.
+    pub metric: DistanceMetric,
+    /// Chunks to search among.
+    pub filter: SearchFilter,
+    /// Reorders the results with a cross-encoder, if set.
+    pub rerank: Option<RerankOptions>,
+    /// Diversifies the results with maximal marginal relevance, if set.
+    pub mmr: Option<MmrOptions>,
+    /// Maximum number of results from any one file.
+    pub max_per_file: Option<usize>,
+}
+
+/// Second search stage, which scores every pair of query and chunk text with a cross-encoder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RerankOptions {
+    /// Number of results of the first stage to rerank, of which the best `limit` are kept. The
+    /// more candidates, the better the recall and the slower the search.
+    pub candidates: usize,
+}
+
+impl Default for RerankOptions {
+    fn default() -> Self {
+        RerankOptions {
+            candidates: DEFAULT_RERANK_CANDIDATES,
+        }
+    }
+}
+
+/// Maximal marginal relevance, which picks results one at a time, trading their relevance against
+/// their similarity to the results already picked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MmrOptions {
+    /// Weight of relevance against diversity, from 0 to 1: 1 keeps the order by relevance, while
+    /// lower values push near-duplicates of earlier results further down.
+    pub lambda: f32,
+    /// Number of results of the first stage, or of reranking, to pick from.
+    pub candidates: usize,
+}
+
+impl Default for MmrOptions {
+    fn default() -> Self {
+        MmrOptions {
+            lambda: DEFAULT_MMR_LAMBDA,
+            candidates: DEFAULT_MMR_CANDIDATES,
+        }
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            table_name: DEFAULT_TABLE_NAME.to_string(),
+            limit: DEFAULT_LIMIT,
+            mode: SearchMode::default(),
+            metric: DistanceMetric::default(),
+            filter: SearchFilter::default(),
+            rerank: None,
+            mmr: None,
+            max_per_file: None,
+        }
+    }
+}
+
+/// A chunk matching a query.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchResult {
+    /// ID of the chunk, which stays the same across re-indexing as long as its lines do.
+    pub id: String,
+    pub file_path: String,
+    pub file_name: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub text: String,
+    pub language: String,
+    /// Kind of definition the chunk belongs to, such as `function`, if any.
+    pub symbol_kind: Option<String>,
+    pub symbol_name: Option<String>,
+    pub repository: Option<String>,
+    pub branch: Option<String>,
+    /// Author of the last commit changing the lines of the chunk, when blame information was
+    /// recorded.
+    pub last_author: Option<String>,
+    /// Author time of that commit, in seconds since the Unix epoch.
+    pub last_modified: Option<i64>,
+    /// First-stage relevance of the chunk, higher being better: one minus the distance in vector
+    /// search, the BM25 score in full-text search and the fused reciprocal rank in hybrid search.
+    pub score: f32,
+    /// Relevance given by the cross-encoder, higher being better, when results were reranked;
+    /// results are then sorted by it instead of `score`.
+    pub rerank_score: Option<f32>,
+}
+
+/// A first-stage result, with what later stages need to know about its row.
+#[derive(Debug, Clone)]
+struct Candidate {
+    row_id: u64,
+    /// Stored embedding of the chunk, read only to diversify results.
+    embedding: Option<Vec<f32>>,
+    result: SearchResult,
+}
+
+impl Candidate {
+    /// Score the candidate is ranked by at the current stage.
+    fn relevance(&self) -> f32 {
+        self.result.rerank_score.unwrap_or(self.result.score)
+    }
+}
+
+/// Searches the `options.table_name` table of the database at `db_file_uri` for the chunks that
+/// best match `query`, best first, among the chunks matching `options.filter`.
+pub async fn search(
+    db_file_uri: &str,
+    query: &str,
+    options: &SearchOptions,
+) -> Result<Vec<SearchResult>> {
+    let table = open_existing_table(db_file_uri, &options.table_name).await?;
+    let predicate = options.filter.to_sql()?;
+    let candidates = first_stage(
+        &table,
+        query,
+        options,
+        predicate.as_deref(),
+        candidate_count(options),
+    )
+    .await?;
+    later_stages(query, candidates, options).await
+}
+
+/// Number of first-stage candidates needed for the later stages of `options`.
+fn candidate_count(options: &SearchOptions) -> usize {
+    let mut candidates = options.limit;
+    if let Some(rerank) = &options.rerank {
+        candidates = candidates.max(rerank.candidates);
+    }
+    if let Some(mmr) = &options.mmr {
+        candidates = candidates.max(mmr.candidates);
+    }
+    if options.max_per_file.is_some() {
+        candidates = candidates.max(options.limit.saturating_mul(COLLAPSE_CANDIDATES_PER_RESULT));
+    }
+    candidates
+}
+
+/// Columns the first stage reads for the later stages of `options`.
+fn candidate_columns(options: &SearchOptions) -> Vec<&'static str> {
+    let mut columns = RESULT_COLUMNS.to_vec();
+    if options.mmr.is_some() {
+        columns.push(EMBEDDING_COLUMN);
+    }
+    columns
+}
+
+/// Reranks, diversifies and collapses `candidates` as set in `options`, then keeps the best
+/// `options.limit` of them.
+async fn later_stages(
+    query: &str,
+    mut candidates: Vec<Candidate>,
+    options: &SearchOptions,
+) -> Result<Vec<SearchResult>> {
+    if options.rerank.is_some() {
+        rerank_results(query, &mut candidates).await?;
+    }
+    Ok(select(
+        candidates,
+        options.limit,
+        options.mmr.as_ref(),
+        options.max_per_file,
+    ))
+}
+
+/// What to find similar chunks to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimilarTo {
+    /// A chunk in the table, by ID.
+    Chunk { id: String },
+    /// Rows `start_line..end_line` of a file, counted from 0 like those of chunks. The path must
+    /// be given as stored in the table, that is, as the file was found when splitting.
+    Lines {
+        file_path: String,
+        start_line: u64,
+        end_line: u64,
+    },
+}
+
+/// Searches the `options.table_name` table for the chunks most similar to `target`, best first,
+/// leaving out `target` itself.
+///
+/// The stored embedding of the target is used when the table holds it; otherwise, lines are read
+/// from disk and embedded, as long as the table holds chunks of the file. Other files are never
+/// read, so that callers serving remote clients do not expose them. Searches are always by
+/// vector, whatever `options.mode` says, and reranking scores candidates against the text of the
+/// target.
+pub async fn find_similar(
+    db_file_uri: &str,
+    target: &SimilarTo,
+    options: &SearchOptions,
+) -> Result<Vec<SearchResult>> {
+    let table = open_existing_table(db_file_uri, &options.table_name).await?;
+    let (text, embedding, exclusion) = match target {
+        SimilarTo::Chunk { id } => {
+            let predicate = format!("id = {}", quote_literal(id));
+            let (text, embedding) = read_stored_chunk(&table, &predicate)
+                .await?
+                .ok_or_else(|| Error::ChunkNotFound(id.clone()))?;
+            (text, embedding, format!("id <> {}", quote_literal(id)))
+        }
+        SimilarTo::Lines {
+            file_path,
+            start_line,
+            end_line,
+        } => {
+            let file = quote_literal(file_path);
+            let predicate = format!(
+                "file_path = {file} AND start_line = {start_line} AND end_line = {end_line}"
+            );
+            let (text, embedding) = match read_stored_chunk(&table, &predicate).await? {
+                Some(stored) => stored,
+                None => {
+                    if table
+                        .count_rows(Some(format!("file_path = {file}")))
+                        .await?
+                        == 0
+                    {
+                        return Err(Error::FileNotIndexed(file_path.clone()));
+                    }
+                    let contents = fs::read_to_string(file_path)?;
+                    let text = contents
+                        .lines()
+                        .skip(*start_line as usize)
+                        .take(end_line.saturating_sub(*start_line) as usize)
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    let embedding = embed_text(text.clone()).await?;
+                    (text, embedding)
+                }
+            };
+            // Chunks overlapping the lines are part of the target.
+            let exclusion = format!(
+                "NOT (file_path = {file} AND start_line < {end_line} AND end_line > {start_line})"
+            );
+            (text, embedding, exclusion)
+        }
+    };
+
+    let predicate = match options.filter.to_sql()? {
+        Some(filter) => format!("({filter}) AND {exclusion}"),
+        None => exclusion,
+    };
+    let candidates = vector_search(
+        &table,
+        embedding,
+        options,
+        &candidate_columns(options),
+        Some(&predicate),
+        candidate_count(options),
+    )
+    .await?;
+    later_stages(&text, candidates, options).await
+}
+
+/// A chunk as stored in the table, without its embedding.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Chunk {
+    pub id: String,
+    pub file_path: String,
+    pub file_name: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub text: String,
+    pub language: String,
+    pub classification: String,
+    pub symbol_kind: Option<String>,
+    pub symbol_name: Option<String>,
+    pub repository: Option<String>,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    /// Last commit changing the lines of the chunk, when blame information was recorded.
+    pub last_commit: Option<String>,
+    pub last_author: Option<String>,
+    /// Author time of `last_commit`, in seconds since the Unix epoch.
+    pub last_modified: Option<i64>,
+}
+
+/// Reads the chunk with ID `id` from the `table_name` table of the database at `db_file_uri`.
+pub async fn get_chunk(db_file_uri: &str, table_name: &str, id: &str) -> Result<Chunk> {
+    let table = open_existing_table(db_file_uri, table_name).await?;
+    let dataframe = table
+        .query()
+        .select(Select::columns(CHUNK_COLUMNS))
+        .only_if(format!("id = {}", quote_literal(id)))
+        .limit(1)
+        .execute()
+        .await?
+        .into_polars()
+        .await?;
+    if dataframe.height() == 0 {
+        return Err(Error::ChunkNotFound(id.to_string()));
+    }
+
+    let string = |column: &str| -> Result<Option<String>> {
+        Ok(dataframe.column(column)?.str()?.get(0).map(str::to_string))
+    };
+    Ok(Chunk {
+        id: id.to_string(),
+        file_path: string("file_path")?.unwrap_or_default(),
+        file_name: string("file_name")?.unwrap_or_default(),
+        start_line: dataframe
+            .column("start_line")?
+            .u64()?
+            .get(0)
+            .unwrap_or_default(),
+        end_line: dataframe
+            .column("end_line")?
+            .u64()?
+            .get(0)
+            .unwrap_or_default(),
+        text: string("text")?.unwrap_or_default(),
+        language: string("language")?.unwrap_or_default(),
+        classification: string("classification")?.unwrap_or_default(),
+        symbol_kind: string("symbol_kind")?,
+        symbol_name: string("symbol_name")?,
+        repository: string("repository")?,
+        branch: string("branch")?,
+        commit: string("commit")?,
+        last_commit: string("last_commit")?,
+        last_author: string("last_author")?,
+        last_modified: dataframe.column("last_modified")?.i64()?.get(0),
+    })
+}
+
+/// Reads the text and embedding of the first chunk matching `predicate`, if any.
+async fn read_stored_chunk(table: &Table, predicate: &str) -> Result<Option<(String, Vec<f32>)>> {
+    let dataframe = table
+        .query()
+        .select(Select::columns(&["text", EMBEDDING_COLUMN]))
+        .only_if(predicate)
+        .limit(1)
+        .execute()
+        .await?
+        .into_polars()
+        .await?;
+    if dataframe.height() == 0 {
+        return Ok(None);
+    }
+
+    let text = dataframe
+        .column("text")?
+        .str()?
+        .get(0)
+        .unwrap_or_default()
+        .to_string();
+    Ok(read_embeddings(&dataframe)?
+        .into_iter()
+        .next()
+        .flatten()
+        .map(|x| (text, x)))
+}
+
+/// Returns the best `limit` candidates for `query` in the search mode of `options`.
+async fn first_stage(
+    table: &Table,
+    query: &str,
+    options: &SearchOptions,
+    predicate: Option<&str>,
+    limit: usize,
+) -> Result<Vec<Candidate>> {
+    let columns = candidate_columns(options);
+    match options.mode {
+        SearchMode::Vector => {
+            let embedding = embed_text(query.to_string()).await?;
+            vector_search(table, embedding, options, &columns, predicate, limit).await
+        }
+        SearchMode::FullText => full_text_search(table, query, &columns, predicate, limit).await,
+        SearchMode::Hybrid { vector_weight } => {
+            let candidates = limit.saturating_mul(HYBRID_CANDIDATES_PER_RESULT);
+            let embedding = embed_text(query.to_string()).await?;
+            let vector =
+                vector_search(table, embedding, options, &columns, predicate, candidates).await?;
+            let full_text = full_text_search(table, query, &columns, predicate, candidates).await?;
+            Ok(reciprocal_rank_fusion(
+                vector,
+                full_text,
+                vector_weight,
+                limit,
+            ))
+        }
+    }
+}
+
+/// Runs model inference `f` on a thread meant for blocking work, so that it does not stall the
+/// async workers that other searches and requests are served from.
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(std::io::Error::from)?
+}
+
+async fn embed_text(text: String) -> Result<Vec<f32>> {
+    run_blocking(move || create_embeddings_from_string(text)).await
+}
+
+/// Scores `candidates` against `query` with the cross-encoder and sorts them by that score.
+async fn rerank_results(query: &str, candidates: &mut [Candidate]) -> Result<()> {
+    let query = query.to_string();
+    let documents: Vec<String> = candidates.iter().map(|x| x.result.text.clone()).collect();
+    let scores = run_blocking(move || {
+        let documents: Vec<&str> = documents.iter().map(String::as_str).collect();
+        rerank(&query, &documents)
+    })
+    .await?;
+    for (candidate, score) in candidates.iter_mut().zip(scores) {
+        candidate.result.rerank_score = Some(score);
+    }
+    // The sort is stable, so first-stage order breaks ties.
+    candidates.sort_by(|x, y| y.relevance().total_cmp(&x.relevance()));
+    Ok(())
+}
+
+async fn vector_search(
+    table: &Table,
+    embedding: Vec<f32>,
+    options: &SearchOptions,
+    columns: &[&str],
+    predicate: Option<&str>,
+    limit: usize,
+) -> Result<Vec<Candidate>> {
+    let mut query = table
+        .vector_search(embedding)?
+        .column(EMBEDDING_COLUMN)
+        .distance_type(options.metric.into())
+        .select(Select::columns(columns))
+        .with_row_id()
+        .limit(limit);
+    // Vector queries filter before picking the nearest rows unless told otherwise.
+    if let Some(predicate) = predicate {
+        query = query.only_if(predicate);
+    }
+    let dataframe = query.execute().await?.into_polars().await?;
+    if dataframe.height() == 0 {
+        return Ok(vec![]);
+    }
+
+    let scores = dataframe
+        .column(DISTANCE_COLUMN)?
+        .f32()?
+        .iter()
+        .map(|x| 1.0 - x.unwrap_or(f32::INFINITY));
+    read_candidates(&dataframe, scores)
+}
+
+async fn full_text_search(
+    table: &Table,
+    query: &str,
+    columns: &[&str],
+    predicate: Option<&str>,
+    limit: usize,
+) -> Result<Vec<Candidate>> {
+    let mut query = table
+        .query()
+        .full_text_search(FullTextSearchQuery::new(query.to_string()))
+        .select(Select::columns(columns))
+        .with_row_id()
+        .limit(limit);
+    if let Some(predicate) = predicate {
+        query = query.only_if(predicate);
+    }
+    let dataframe = query.execute().await?.into_polars().await?;
+    if dataframe.height() == 0 {
+        return Ok(vec![]);
+    }
+
+    let scores = dataframe
+        .column(FULL_TEXT_SCORE_COLUMN)?
+        .f32()?
+        .iter()
+        .map(|x| x.unwrap_or_default());
+    read_candidates(&dataframe, scores)
+}
+
+/// Reads the candidates in `dataframe` with the given scores, along with their embeddings if the
+/// embedding column was selected.
+fn read_candidates(
+    dataframe: &DataFrame,
+    scores: impl Iterator<Item = f32>,
+) -> Result<Vec<Candidate>> {
+    let row_ids = dataframe.column(ROW_ID_COLUMN)?.u64()?;
+    let mut embeddings = match dataframe.get_column_index(EMBEDDING_COLUMN) {
+        Some(_) => read_embeddings(dataframe)?.into_iter(),
+        None => Vec::new().into_iter(),
+    };
+    let ids = dataframe.column("id")?.str()?;
+    let file_paths = dataframe.column("file_path")?.str()?;
+    let file_names = dataframe.column("file_name")?.str()?;
+    let start_lines = dataframe.column("start_line")?.u64()?;
+    let end_lines = dataframe.column("end_line")?.u64()?;
+    let texts = dataframe.column("text")?.str()?;
+    let languages = dataframe.column("language")?.str()?;
+    let symbol_kinds = dataframe.column("symbol_kind")?.str()?;
+    let symbol_names = dataframe.column("symbol_name")?.str()?;
+    let repositories = dataframe.column("repository")?.str()?;
+    let branches = dataframe.column("branch")?.str()?;
+    let last_authors = dataframe.column("last_author")?.str()?;
+    let last_modified = dataframe.column("last_modified")?.i64()?;
+
+    Ok(scores
+        .enumerate()
+        .map(|(i, score)| Candidate {
+            row_id: row_ids.get(i).unwrap_or_default(),
+            embedding: embeddings.next().flatten(),
+            result: SearchResult {
+                id: ids.get(i).unwrap_or_default().to_string(),
+                file_path: file_paths.get(i).unwrap_or_default().to_string(),
+                file_name: file_names.get(i).unwrap_or_default().to_string(),
+                start_line: start_lines.get(i).unwrap_or_default(),
+                end_line: end_lines.get(i).unwrap_or_default(),
+                text: texts.get(i).unwrap_or_default().to_string(),
+                language: languages.get(i).unwrap_or_default().to_string(),
+                symbol_kind: symbol_kinds.get(i).map(str::to_string),
+                symbol_name: symbol_names.get(i).map(str::to_string),
+                repository: repositories.get(i).map(str::to_string),
+                branch: branches.get(i).map(str::to_string),
+                last_author: last_authors.get(i).map(str::to_string),
+                last_modified: last_modified.get(i),
+                score,
+                rerank_score: None,
+            },
+        })
+        .collect())
+}
+
+/// Reads the embedding column of `dataframe`, stored either as a list or as a fixed-size list.
+pub(crate) fn read_embeddings(dataframe: &DataFrame) -> Result<Vec<Option<Vec<f32>>>> {
+    let embeddings = dataframe
+        .column(EMBEDDING_COLUMN)?
+        .cast(&DataType::List(Box::new(DataType::Float32)))?;
+    let mut result = Vec::with_capacity(embeddings.len());
+    for embedding in embeddings.list()? {
+        result.push(match embedding {
+            Some(x) => Some(x.f32()?.iter().map(|y| y.unwrap_or_default()).collect()),
+            None => None,
+        });
+    }
+    Ok(result)
+}
+
+/// Fuses two rankings of the same rows, scoring every row with the weighted sum of
+/// `1 / (RRF_K + rank)` over the rankings it appears in.
+fn reciprocal_rank_fusion(
+    vector: Vec<Candidate>,
+    full_text: Vec<Candidate>,
+    vector_weight: f32,
+    limit: usize,
+) -> Vec<Candidate> {
+    let vector_weight = vector_weight.clamp(0.0, 1.0);
+    let mut fused: HashMap<u64, Candidate> = HashMap::new();
+    for (weight, ranking) in [(vector_weight, vector), (1.0 - vector_weight, full_text)] {
+        for (rank, mut candidate) in ranking.into_iter().enumerate() {
+            // Ranks are counted from 1.
+            let score = weight / (RRF_K + rank as f32 + 1.0);
+            candidate.result.score = 0.0;
+            fused
+                .entry(candidate.row_id)
+                .or_insert(candidate)
+                .result
+                .score += score;
+        }
+    }
+
+    let mut candidates: Vec<Candidate> = fused.into_values().collect();
+    candidates.sort_by(|x, y| {
+        y.result
+            .score
+            .total_cmp(&x.result.score)
+            .then_with(|| x.result.file_path.cmp(&y.result.file_path))
+            .then_with(|| x.result.start_line.cmp(&y.result.start_line))
+    });
+    candidates.truncate(limit);
+    candidates
+}
+
+/// Picks up to `limit` of `candidates`, which are sorted by relevance, taking at most
+/// `max_per_file` from any file.
+///
+/// Without `mmr`, the most relevant candidates are picked. With it, every pick maximizes
+/// `lambda * relevance - (1 - lambda) * similarity`, where relevance is the candidate score
+/// scaled to `[0, 1]` and similarity is the highest cosine similarity between the embedding of
+/// the candidate and those of the candidates already picked, so that near-duplicates of a pick
+/// give way to other relevant chunks.
+fn select(
+    candidates: Vec<Candidate>,
+    limit: usize,
+    mmr: Option<&MmrOptions>,
+    max_per_file: Option<usize>,
+) -> Vec<SearchResult> {
+    let (min, max) = candidates
+        .iter()
+        .map(Candidate::relevance)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), x| {
+            (min.min(x), max.max(x))
+        });
+    let normalize = |x: f32| {
+        if max > min {
+            (x - min) / (max - min)
+        } else {
+            1.0
+        }
+    };
+
+    let mut remaining = candidates;
+    let mut selected: Vec<Candidate> = Vec::new();
+    let mut picks_per_file: HashMap<String, usize> = HashMap::new();
+    while selected.len() < limit {
+        if let Some(max_per_file) = max_per_file {
+            remaining.retain(|x| {
+                picks_per_file
+                    .get(&x.result.file_path)
+                    .is_none_or(|picks| *picks < max_per_file)
+            });
+        }
+        if remaining.is_empty() {
+            break;
+        }
+
+        let index = match mmr {
+            None => 0,
+            Some(mmr) => {
+                let lambda = mmr.lambda.clamp(0.0, 1.0);
+                let marginal_relevance = |candidate: &Candidate| {
+                    let similarity = selected
+                        .iter()
+                        .filter_map(|x| {
+                            Some(cosine_similarity(
+                                candidate.embedding.as_ref()?,
+                                x.embedding.as_ref()?,
+                            ))
+                        })
+                        .fold(0.0, f32::max);
+                    lambda * normalize(candidate.relevance()) - (1.0 - lambda) * similarity
+                };
+                // The first of equally good candidates is the most relevant one.
+                let mut best = 0;
+                let mut best_value = f32::NEG_INFINITY;
+                for (i, candidate) in remaining.iter().enumerate() {
+                    let value = marginal_relevance(candidate);
+                    if value > best_value {
+                        best = i;
+                        best_value = value;
+                    }
+                }
+                best
+            }
+        };
+        let candidate = remaining.remove(index);
+        *picks_per_file
+            .entry(candidate.result.file_path.clone())
+            .or_default() += 1;
+        selected.push(candidate);
+    }
+    selected.into_iter().map(|x| x.result).collect()
+}
+
+fn cosine_similarity(x: &[f32], y: &[f32]) -> f32 {
+    let dot: f32 = x.iter().zip(y).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|a| a * a).sum::<f32>().sqrt();
+    let norms = norm(x) * norm(y);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::create_embeddings_from_file;
+    use crate::git::tests::commit_files;
+    use crate::index::index;
+    use crate::index::tests::index_directory;
+    use crate::split::{Classification, SplitOptions, SymbolKind, find_and_split_with_options};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn candidate(row_id: u64, file_path: &str, score: f32, embedding: &[f32]) -> Candidate {
+        let result = SearchResult {
+            id: row_id.to_string(),
+            file_path: file_path.to_string(),
+            file_name: file_path.to_string(),
+            start_line: 1,
+            end_line: 2,
+            text: String::new(),
+            language: "rust".to_string(),
+            symbol_kind: None,
+            symbol_name: None,
+            repository: None,
+            branch: None,
+            last_author: None,
+            last_modified: None,
+            score,
+            rerank_score: None,
+        };
+        Candidate {
+            row_id,
+            embedding: Some(embedding.to_vec()),
+            result,
+        }
+    }
+
+    fn paths(results: impl IntoIterator<Item = SearchResult>) -> Vec<String> {
+        results.into_iter().map(|x| x.file_path).collect()
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let vector = vec![
+            candidate(1, "a.rs", 0.9, &[]),
+            candidate(2, "b.rs", 0.8, &[]),
+        ];
+        let full_text = vec![
+            candidate(3, "c.rs", 7.0, &[]),
+            candidate(2, "b.rs", 5.0, &[]),
+        ];
+        let paths = |candidates: Vec<Candidate>| paths(candidates.into_iter().map(|x| x.result));
+
+        // Appearing in both rankings beats coming first in one.
+        assert_eq!(
+            paths(reciprocal_rank_fusion(
+                vector.clone(),
+                full_text.clone(),
+                0.5,
+                10
+            )),
+            vec!["b.rs", "a.rs", "c.rs"]
+        );
+        // The weight decides between results ranked first in a single ranking.
+        assert_eq!(
+            paths(reciprocal_rank_fusion(
+                vector.clone(),
+                full_text.clone(),
+                0.2,
+                2
+            )),
+            vec!["b.rs", "c.rs"]
+        );
+        assert_eq!(
+            paths(reciprocal_rank_fusion(vector, full_text, 1.0, 10))[..2],
+            ["a.rs", "b.rs"]
+        );
+    }
+
+    #[test]
+    fn test_search_mode_kind() -> anyhow::Result<()> {
+        for (json, kind) in [
+            ("\"vector\"", SearchModeKind::Vector),
+            ("\"full_text\"", SearchModeKind::FullText),
+            ("\"fullText\"", SearchModeKind::FullText),
+            ("\"hybrid\"", SearchModeKind::Hybrid),
+        ] {
+            assert_eq!(serde_json::from_str::<SearchModeKind>(json)?, kind);
+        }
+        assert_eq!(
+            SearchModeKind::from_str("full-text", false),
+            Ok(SearchModeKind::FullText)
+        );
+        assert_eq!(
+            SearchModeKind::Hybrid.to_mode(0.3),
+            SearchMode::Hybrid { vector_weight: 0.3 }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_select() {
+        let candidates = vec![
+            candidate(1, "a.rs", 0.9, &[1.0, 0.0]),
+            candidate(2, "copy_of_a.rs", 0.89, &[1.0, 0.01]),
+            candidate(3, "a.rs", 0.85, &[0.7, 0.7]),
+            candidate(4, "b.rs", 0.8, &[0.0, 1.0]),
+        ];
+
+        assert_eq!(
+            paths(select(candidates.clone(), 3, None, None)),
+            vec!["a.rs", "copy_of_a.rs", "a.rs"]
+        );
+        assert_eq!(
+            paths(select(candidates.clone(), 3, None, Some(1))),
+            vec!["a.rs", "copy_of_a.rs", "b.rs"]
+        );
+        // The near-duplicate of the first pick gives way to different chunks.
+        let mmr = MmrOptions::default();
+        assert_eq!(
+            paths(select(candidates.clone(), 2, Some(&mmr), None)),
+            vec!["a.rs", "b.rs"]
+        );
+        // Relevance alone keeps the original order.
+        let mmr = MmrOptions {
+            lambda: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(select(candidates, 2, Some(&mmr), None)),
+            vec!["a.rs", "copy_of_a.rs"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(&root)?;
+        fs::write(
+            root.join("reader.rs"),
+            r#"
+            fn open_reader(file: File) -> ParquetRecordBatchReaderBuilder<File> {
+                ParquetRecordBatchReaderBuilder::try_new(file).unwrap()
+            }
+            "#,
+        )?;
+        fs::write(
+            root.join("math.py"),
+            r#"
+def add_numbers(first, second):
+    return first + second
+"#,
+        )?;
+        fs::write(
+            root.join("greeting.js"),
+            r#"
+function greet(name) {
+    console.log("Hello, " + name);
+}
+"#,
+        )?;
+        fs::write(
+            root.join(".gitattributes"),
+            "greeting.js linguist-vendored\n",
+        )?;
+
+        let db_path_str = index_directory(temp_dir.path(), &root).await?;
+
+        let search_with = |mode: SearchMode, query: &'static str| {
+            let db_path_str = db_path_str.clone();
+            async move {
+                let options = SearchOptions {
+                    mode,
+                    limit: 2,
+                    ..Default::default()
+                };
+                search(&db_path_str, query, &options).await
+            }
+        };
+
+        let results = search_with(SearchMode::FullText, "ParquetRecordBatchReaderBuilder").await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file_name, "reader.rs");
+        assert!(results[0].score > 0.0);
+
+        let results = search_with(SearchMode::Vector, "sum of two numbers").await?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].file_name, "math.py");
+        assert!(results[0].score >= results[1].score);
+
+        let results = search_with(
+            SearchMode::Hybrid {
+                vector_weight: DEFAULT_VECTOR_WEIGHT,
+            },
+            "ParquetRecordBatchReaderBuilder",
+        )
+        .await?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].file_name, "reader.rs");
+
+        // Filtering happens before the nearest chunks are picked, so the only JavaScript chunk
+        // is found even though it is not among the nearest ones overall.
+        let options = SearchOptions {
+            limit: 1,
+            filter: SearchFilter::default()
+                .with_language("javascript")
+                .with_path_glob("**/*.js")
+                .with_symbol_kind(SymbolKind::Function),
+            ..Default::default()
+        };
+        let results = search(&db_path_str, "sum of two numbers", &options).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file_name, "greeting.js");
+
+        let options = SearchOptions {
+            filter: SearchFilter::default().with_classification(Classification::Vendored),
+            ..Default::default()
+        };
+        let results = search(&db_path_str, "sum of two numbers", &options).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file_name, "greeting.js");
+        let options = SearchOptions {
+            filter: SearchFilter::default().exclude_classification(Classification::Vendored),
+            ..Default::default()
+        };
+        let results = search(&db_path_str, "sum of two numbers", &options).await?;
+        assert!(!results.is_empty());
+        assert!(results.iter().all(|x| x.file_name != "greeting.js"));
+
+        let options = SearchOptions {
+            filter: SearchFilter::default().with_file_name("missing.rs"),
+            ..Default::default()
+        };
+        assert!(
+            search(&db_path_str, "sum of two numbers", &options)
+                .await?
+                .is_empty()
+        );
+
+        let options = SearchOptions {
+            limit: 2,
+            rerank: Some(RerankOptions::default()),
+            ..Default::default()
+        };
+        let results = search(&db_path_str, "add two numbers", &options).await?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].file_name, "math.py");
+        assert!(results[0].rerank_score >= results[1].rerank_score);
+        assert!(results.iter().all(|x| x.rerank_score.is_some()));
+
+        let options = SearchOptions {
+            limit: 3,
+            mmr: Some(MmrOptions::default()),
+            max_per_file: Some(1),
+            ..Default::default()
+        };
+        let results = search(&db_path_str, "add two numbers", &options).await?;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].file_name, "math.py");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_modified() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repository_path = temp_dir.path().join("repository");
+        let repository = git2::Repository::init(&repository_path)?;
+        // The first commit is made at 1_700_000_000 and the second a minute later.
+        commit_files(
+            &repository,
+            &[(
+                "old.py",
+                "def add_numbers(first, second):\n    return first + second\n",
+            )],
+            "First commit",
+        );
+        commit_files(
+            &repository,
+            &[(
+                "new.py",
+                "def sum_numbers(numbers):\n    return sum(numbers)\n",
+            )],
+            "Second commit",
+        );
+
+        let split_file_uri = temp_dir.path().join("split.parquet");
+        let embed_file_uri = temp_dir.path().join("embed.parquet");
+        let db_path_str = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+        find_and_split_with_options(
+            repository_path.to_string_lossy().to_string(),
+            split_file_uri.to_string_lossy().to_string(),
+            &SplitOptions {
+                blame: true,
+                ..Default::default()
+            },
+        )?;
+        create_embeddings_from_file(
+            split_file_uri.to_string_lossy().to_string(),
+            embed_file_uri.to_string_lossy().to_string(),
+        )?;
+        index(
+            embed_file_uri.to_string_lossy().to_string(),
+            db_path_str.clone(),
+        )
+        .await?;
+
+        let options = SearchOptions {
+            filter: SearchFilter::default().with_modified_after(1_700_000_000),
+            ..Default::default()
+        };
+        let results = search(&db_path_str, "add two numbers", &options).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file_name, "new.py");
+        assert_eq!(results[0].last_author.as_deref(), Some("Test"));
+        assert_eq!(results[0].last_modified, Some(1_700_000_060));
+
+        let options = SearchOptions {
+            filter: SearchFilter::default().with_modified_before(1_700_000_060),
+            ..Default::default()
+        };
+        let results = search(&db_path_str, "add two numbers", &options).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file_name, "old.py");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_similar() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(&root)?;
+        let add = "def add(first, second):\n    return first + second\n";
+        fs::write(root.join("add.py"), add)?;
+        fs::write(root.join("copy.py"), add.replace("add", "plus"))?;
+        fs::write(
+            root.join("greeting.js"),
+            "function greet(name) {\n    console.log(\"Hello, \" + name);\n}\n",
+        )?;
+
+        let db_path_str = index_directory(temp_dir.path(), &root).await?;
+
+        let options = SearchOptions {
+            limit: 1,
+            ..Default::default()
+        };
+        let add_path = root.join("add.py").to_string_lossy().to_string();
+        let source = search(&db_path_str, "add two numbers", &options).await?;
+        assert_eq!(source[0].file_path, add_path);
+
+        // By ID, the source chunk is left out.
+        let target = SimilarTo::Chunk {
+            id: source[0].id.clone(),
+        };
+        let results = find_similar(&db_path_str, &target, &options).await?;
+        assert_eq!(results[0].file_name, "copy.py");
+
+        let chunk = get_chunk(&db_path_str, DEFAULT_TABLE_NAME, &source[0].id).await?;
+        assert_eq!(chunk.file_path, source[0].file_path);
+        assert_eq!(chunk.text, source[0].text);
+        assert_eq!(chunk.language, "python");
+
+        // Lines that do not make up a stored chunk are embedded from disk.
+        let target = SimilarTo::Lines {
+            file_path: add_path,
+            start_line: 0,
+            end_line: 1,
+        };
+        let results = find_similar(&db_path_str, &target, &options).await?;
+        assert_eq!(results[0].file_name, "copy.py");
+
+        // Files without chunks in the table are not read.
+        let secret_path = temp_dir.path().join("secret.py");
+        fs::write(&secret_path, add)?;
+        let target = SimilarTo::Lines {
+            file_path: secret_path.to_string_lossy().to_string(),
+            start_line: 0,
+            end_line: 1,
+        };
+        assert!(matches!(
+            find_similar(&db_path_str, &target, &options).await,
+            Err(Error::FileNotIndexed(_))
+        ));
+
+        let target = SimilarTo::Chunk {
+            id: "missing".to_string(),
+        };
+        assert!(matches!(
+            find_similar(&db_path_str, &target, &options).await,
+            Err(Error::ChunkNotFound(_))
+        ));
+        assert!(matches!(
+            get_chunk(&db_path_str, DEFAULT_TABLE_NAME, "missing").await,
+            Err(Error::ChunkNotFound(_))
+        ));
+        Ok(())
+    }
+}
@@ -1,12 +1,130 @@
-use crate::languages::{Language, get_languages};
+use crate::Result;
+pub use crate::archive::ARCHIVE_PATH_SEPARATOR;
+use crate::archive::{ArchiveSource, is_archive};
+pub use crate::classify::Classification;
+use crate::classify::Classifier;
+use crate::git::{Blamer, GitTreeSource, discover_codebase};
+use crate::languages::{Language, get_language_for_path};
+pub use crate::symbols::SymbolKind;
+use crate::symbols::{chunk_symbol, find_symbols};
+use arrow::array::{ArrayRef, Int64Builder, RecordBatch, StringBuilder, UInt64Builder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use chardetng::EncodingDetector;
+use clap::ValueEnum;
 use code_splitter::Chunk;
-use polars::prelude::*;
-use std::fs::ReadDir;
-use std::io::{BufRead, Read, Seek};
+use encoding_rs::Encoding;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
+use std::sync::Arc;
+use std::{fmt, fs, io};
+
+/// Number of chunks buffered in memory before a Parquet row group is flushed.
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 8192;
+
+/// Number of hexadecimal digits in chunk IDs.
+const CHUNK_ID_LENGTH: usize = 32;
+
+/// Files larger than this many bytes are skipped by default.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Files with a line longer than this many bytes are skipped by default, which filters out
+/// minified bundles and generated blobs.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 1000;
+
+/// Number of files handed to the worker pool at once.
+const FILES_PER_BATCH: usize = 256;
+
+/// Number of leading bytes inspected when deciding whether a file is binary.
+const BINARY_SNIFF_LENGTH: usize = 8000;
+
+/// Version control metadata directories, which are never walked.
+const IGNORED_DIRECTORIES: &[&str] = &[".git", ".hg", ".svn"];
+
+/// How the directory walker treats symbolic links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Symlinks are reported as skipped, so every file is split at most once.
+    Skip,
+    /// Symlinks to files are followed, as they were before symlink policies existed, and symlinks
+    /// to directories are reported as skipped, so that no directory is walked twice.
+    #[default]
+    FollowFiles,
+    /// Symlinks are followed, except those pointing to a directory that contains them.
+    Follow,
+    /// Like [`SymlinkPolicy::Follow`], but only for symlinks that resolve to a path inside the
+    /// root directory.
+    FollowWithinRoot,
+}
+
+impl SymlinkPolicy {
+    /// Whether symlinks to directories are walked, which needs loops to be detected.
+    fn follows_directories(self) -> bool {
+        matches!(
+            self,
+            SymlinkPolicy::Follow | SymlinkPolicy::FollowWithinRoot
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SplitOptions {
+    /// Maximum number of chunks per Parquet row group.
+    pub row_group_size: usize,
+    /// Number of worker threads used to split files; `None` uses one per available CPU.
+    pub threads: Option<usize>,
+    /// Files larger than this many bytes are skipped.
+    pub max_file_size: Option<u64>,
+    /// Files with a line longer than this many bytes, once decoded, are skipped.
+    pub max_line_length: Option<usize>,
+    /// Where to write the run report as JSON, if anywhere.
+    pub report_path: Option<PathBuf>,
+    /// Whether to record the last commit, author and time of every chunk, from `git blame`.
+    ///
+    /// Chunks outside a git repository, or with no committed lines, get no history.
+    pub blame: bool,
+    /// Whether to split the files inside tar, tar.gz and zip archives found while walking a
+    /// directory, as if they were directories themselves.
+    pub expand_archives: bool,
+    /// How to treat symlinks found while walking a directory.
+    pub symlinks: SymlinkPolicy,
+    /// Repository identifier stored with every chunk.
+    ///
+    /// When `None`, it is the URL of the `origin` remote of the repository being split, or its
+    /// path when there is no such remote; outside a git repository, it is the input path.
+    pub repository: Option<String>,
+    /// Branch stored with every chunk.
+    ///
+    /// When `None`, it is the branch being split, if any: the checked-out branch for directories
+    /// or the branch `revision` names for git revisions.
+    pub branch: Option<String>,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        SplitOptions {
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            threads: None,
+            max_file_size: Some(DEFAULT_MAX_FILE_SIZE),
+            max_line_length: Some(DEFAULT_MAX_LINE_LENGTH),
+            report_path: None,
+            blame: false,
+            expand_archives: false,
+            symlinks: SymlinkPolicy::default(),
+            repository: None,
+            branch: None,
+        }
+    }
+}
 
 struct FileContent {
+    text: String,
     lines: Vec<String>,
     chunks: Vec<Chunk>,
 }
@@ -19,21 +137,463 @@ pub struct ChunkMetadata {
     end_line: u64,
     text: Option<String>,
     size: u64,
+    classification: Classification,
+    language: String,
+    symbol_kind: Option<SymbolKind>,
+    symbol_name: Option<String>,
+    repository: Option<String>,
+    branch: Option<String>,
+    commit: Option<String>,
+    last_commit: Option<String>,
+    last_author: Option<String>,
+    last_modified: Option<i64>,
+}
+
+/// Why a file was left out of a split run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SkipReason {
+    /// No supported language matches the file extension.
+    UnsupportedLanguage,
+    /// The language grammar failed to split the file.
+    ParseError { message: String },
+    /// The file content could not be decoded as text in any detected encoding.
+    NonUtf8,
+    /// The file content looks like binary data.
+    Binary,
+    /// The file or directory could not be read due to its permissions.
+    PermissionDenied,
+    /// The file exceeds the configured maximum file size.
+    TooLarge { size: u64, limit: u64 },
+    /// A line, counted from 1, exceeds the configured maximum line length.
+    LineTooLong {
+        line: usize,
+        length: usize,
+        limit: usize,
+    },
+    /// The path is a symlink, and symlinks are not followed.
+    Symlink,
+    /// The path is a symlink to a directory, and only symlinks to files are followed.
+    SymlinkToDirectory,
+    /// The path is a symlink to a file or directory outside the root.
+    SymlinkOutsideRoot { target: String },
+    /// The path is a symlink to one of the directories containing it.
+    SymlinkLoop { target: String },
+    /// The path is a FIFO, a socket or a device file.
+    SpecialFile,
+    /// The file is an archive nested in more archives than are expanded.
+    ArchiveTooDeep { limit: usize },
+    /// Any other I/O failure.
+    Io { message: String },
+}
+
+impl From<io::Error> for SkipReason {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => SkipReason::PermissionDenied,
+            io::ErrorKind::InvalidData => SkipReason::NonUtf8,
+            _ => SkipReason::Io {
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::UnsupportedLanguage => write!(f, "unsupported language"),
+            SkipReason::ParseError { message } => write!(f, "parse error: {message}"),
+            SkipReason::NonUtf8 => write!(f, "content could not be decoded as text"),
+            SkipReason::Binary => write!(f, "content looks like binary data"),
+            SkipReason::PermissionDenied => write!(f, "permission denied"),
+            SkipReason::TooLarge { size, limit } => {
+                write!(f, "file size {size} exceeds the limit of {limit} bytes")
+            }
+            SkipReason::LineTooLong {
+                line,
+                length,
+                limit,
+            } => write!(
+                f,
+                "line {line} is {length} bytes long, exceeding the limit of {limit} bytes"
+            ),
+            SkipReason::Symlink => write!(f, "symlinks are not followed"),
+            SkipReason::SymlinkToDirectory => {
+                write!(f, "symlinks to directories are not followed")
+            }
+            SkipReason::SymlinkOutsideRoot { target } => {
+                write!(f, "symlink target {target} is outside the root")
+            }
+            SkipReason::SymlinkLoop { target } => {
+                write!(f, "symlink to {target} creates a loop")
+            }
+            SkipReason::SpecialFile => write!(f, "not a regular file or directory"),
+            SkipReason::ArchiveTooDeep { limit } => {
+                write!(f, "archive is nested more than {limit} archives deep")
+            }
+            SkipReason::Io { message } => write!(f, "I/O error: {message}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+impl SkippedFile {
+    pub(crate) fn new(path: &Path, reason: SkipReason) -> Self {
+        SkippedFile {
+            path: path.to_string_lossy().to_string(),
+            reason,
+        }
+    }
+}
+
+/// Summary of a split run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SplitReport {
+    /// Number of files that were split into chunks.
+    pub files_split: u64,
+    /// Number of chunks written to the output.
+    pub chunks_written: u64,
+    /// Files and directories that were left out, in walk order.
+    pub skipped: Vec<SkippedFile>,
+}
+
+impl SplitReport {
+    pub fn write_json(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = fs::File::create(path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+/// A file to split, either on disk or already read into memory.
+pub(crate) enum SourceFile {
+    Disk(PathBuf),
+    Memory { path: PathBuf, contents: Vec<u8> },
+}
+
+impl SourceFile {
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            SourceFile::Disk(path) => path,
+            SourceFile::Memory { path, .. } => path,
+        }
+    }
+
+    fn read(&self, options: &SplitOptions) -> Result<Cow<'_, [u8]>, SkipReason> {
+        let size = match self {
+            SourceFile::Disk(path) => fs::metadata(path)?.len(),
+            SourceFile::Memory { contents, .. } => contents.len() as u64,
+        };
+        if let Some(limit) = options.max_file_size {
+            if size > limit {
+                return Err(SkipReason::TooLarge { size, limit });
+            }
+        }
+
+        match self {
+            SourceFile::Disk(path) => Ok(Cow::Owned(fs::read(path)?)),
+            SourceFile::Memory { contents, .. } => Ok(Cow::Borrowed(contents)),
+        }
+    }
+}
+
+/// Whether `path` lies in a directory that is never walked.
+pub(crate) fn is_ignored(path: &Path) -> bool {
+    path.components()
+        .any(|x| IGNORED_DIRECTORIES.iter().any(|y| x.as_os_str() == *y))
+}
+
+/// Where a [`CodeFileSplitter`] takes the files to split from.
+pub(crate) trait FileSource: Iterator<Item = Result<SourceFile, SkippedFile>> {
+    /// Classifier holding the `.gitattributes` rules for every file returned so far.
+    fn classifier(&self) -> &Classifier;
+}
+
+/// Walks a directory tree in a deterministic order: the files of a directory come first, sorted
+/// by name, followed by its subdirectories, also sorted by name.
+///
+/// The `.gitattributes` file of each directory is loaded into the classifier before any of the
+/// directory's files are returned.
+///
+/// When archives are expanded, their entries take the place of the archive itself, read as the
+/// walk reaches it. Symbolic links are handled according to the [`SymlinkPolicy`]; FIFOs, sockets
+/// and device files are skipped.
+struct DirectoryWalker {
+    directories: Vec<PendingDirectory>,
+    files: VecDeque<Result<SourceFile, SkippedFile>>,
+    /// Archive whose entries are being returned.
+    archive: Option<ArchiveSource>,
+    classifier: Classifier,
+    expand_archives: bool,
+    max_file_size: Option<u64>,
+    symlinks: SymlinkPolicy,
+    canonical_root: PathBuf,
+}
+
+/// A directory waiting to be walked.
+struct PendingDirectory {
+    path: PathBuf,
+    // Canonical paths of the directory and its ancestors up to the root, only tracked while
+    // following symlinks, to detect loops.
+    ancestors: Vec<PathBuf>,
+}
+
+impl DirectoryWalker {
+    fn new(root: PathBuf) -> Self {
+        DirectoryWalker {
+            classifier: Classifier::new(&root),
+            canonical_root: root.clone(),
+            directories: vec![PendingDirectory {
+                path: root,
+                ancestors: vec![],
+            }],
+            files: VecDeque::new(),
+            archive: None,
+            expand_archives: false,
+            max_file_size: None,
+            symlinks: SymlinkPolicy::default(),
+        }
+    }
+
+    fn with_archives(mut self, max_file_size: Option<u64>) -> Self {
+        self.expand_archives = true;
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Sets how symlinks are handled, with `root` bounding [`SymlinkPolicy::FollowWithinRoot`].
+    fn with_symlinks(mut self, symlinks: SymlinkPolicy, root: &Path) -> Self {
+        self.symlinks = symlinks;
+        if symlinks.follows_directories() {
+            self.canonical_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+            for directory in &mut self.directories {
+                if let Ok(path) = fs::canonicalize(&directory.path) {
+                    directory.ancestors = vec![path];
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns the next file in the queue, opening the archives in it when they are expanded.
+    fn next_file(&mut self) -> Option<Result<SourceFile, SkippedFile>> {
+        loop {
+            if let Some(archive) = &mut self.archive {
+                match archive.next() {
+                    Some(file) => return Some(file),
+                    None => self.archive = None,
+                }
+            }
+            let path = match self.files.pop_front()? {
+                Ok(SourceFile::Disk(path)) if self.expand_archives && is_archive(&path) => path,
+                file => return Some(file),
+            };
+            match ArchiveSource::open(&path, self.max_file_size) {
+                Ok(archive) => self.archive = Some(archive),
+                Err(e) => {
+                    let reason = SkipReason::Io {
+                        message: e.to_string(),
+                    };
+                    return Some(Err(SkippedFile::new(&path, reason)));
+                }
+            }
+        }
+    }
+
+    /// Returns the type of the entry at `path`, resolving symlinks according to the policy, and
+    /// the canonical path of directories when symlinks are followed.
+    fn resolve(
+        &self,
+        path: &Path,
+        file_type: fs::FileType,
+        ancestors: &[PathBuf],
+    ) -> Result<(fs::FileType, Option<PathBuf>), SkipReason> {
+        if !file_type.is_symlink() {
+            // Directories are tracked so that symlinks back to them are detected.
+            let canonical_path = match self.symlinks.follows_directories() && file_type.is_dir() {
+                true => Some(fs::canonicalize(path)?),
+                false => None,
+            };
+            return Ok((file_type, canonical_path));
+        }
+        if self.symlinks == SymlinkPolicy::Skip {
+            return Err(SkipReason::Symlink);
+        }
+
+        // Dangling symlinks and symlinks pointing to themselves fail to resolve here.
+        let target = fs::canonicalize(path)?;
+        if self.symlinks == SymlinkPolicy::FollowFiles {
+            let file_type = fs::metadata(&target)?.file_type();
+            if file_type.is_dir() {
+                return Err(SkipReason::SymlinkToDirectory);
+            }
+            return Ok((file_type, None));
+        }
+        if self.symlinks == SymlinkPolicy::FollowWithinRoot
+            && !target.starts_with(&self.canonical_root)
+        {
+            return Err(SkipReason::SymlinkOutsideRoot {
+                target: target.to_string_lossy().to_string(),
+            });
+        }
+        if ancestors.contains(&target) {
+            return Err(SkipReason::SymlinkLoop {
+                target: target.to_string_lossy().to_string(),
+            });
+        }
+        Ok((fs::metadata(&target)?.file_type(), Some(target)))
+    }
+}
+
+impl Iterator for DirectoryWalker {
+    type Item = Result<SourceFile, SkippedFile>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(file) = self.next_file() {
+                return Some(file);
+            }
+
+            let PendingDirectory {
+                path: directory,
+                ancestors,
+            } = self.directories.pop()?;
+            let read_dir = match fs::read_dir(&directory) {
+                Ok(read_dir) => read_dir,
+                Err(e) => return Some(Err(SkippedFile::new(&directory, e.into()))),
+            };
+            let mut entries = Vec::new();
+            for entry in read_dir {
+                match entry {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => self
+                        .files
+                        .push_back(Err(SkippedFile::new(&directory, e.into()))),
+                }
+            }
+            entries.sort_by_key(|x| x.file_name());
+            if entries.iter().any(|x| x.file_name() == ".gitattributes") {
+                self.classifier.load_gitattributes(&directory);
+            }
+
+            let mut subdirectories = Vec::new();
+            for entry in entries {
+                let path = entry.path();
+                // Unlike `metadata`, `file_type` does not follow symlinks.
+                let resolved = entry
+                    .file_type()
+                    .map_err(SkipReason::from)
+                    .and_then(|x| self.resolve(&path, x, &ancestors));
+                match resolved {
+                    Ok((file_type, canonical_path)) if file_type.is_dir() => {
+                        if !is_ignored(Path::new(&entry.file_name())) {
+                            let mut ancestors = ancestors.clone();
+                            ancestors.extend(canonical_path);
+                            subdirectories.push(PendingDirectory { path, ancestors });
+                        }
+                    }
+                    Ok((file_type, _)) if file_type.is_file() => {
+                        self.files.push_back(Ok(SourceFile::Disk(path)))
+                    }
+                    Ok(_) => self
+                        .files
+                        .push_back(Err(SkippedFile::new(&path, SkipReason::SpecialFile))),
+                    Err(reason) => self.files.push_back(Err(SkippedFile::new(&path, reason))),
+                }
+            }
+            // The stack is popped from the end, so push in reverse to visit in sorted order.
+            self.directories.extend(subdirectories.into_iter().rev());
+        }
+    }
+}
+
+impl FileSource for DirectoryWalker {
+    fn classifier(&self) -> &Classifier {
+        &self.classifier
+    }
+}
+
+/// Returns a given list of files, and the files under a given list of directories, in order.
+///
+/// The given paths are resolved the way a [`DirectoryWalker`] of the root resolves its entries,
+/// so symbolic links follow the [`SymlinkPolicy`] and special files are skipped. The
+/// `.gitattributes` files between the root and every returned file are loaded up front.
+struct FileListSource {
+    files: VecDeque<Result<SourceFile, SkippedFile>>,
+    classifier: Classifier,
+}
+
+impl FileListSource {
+    fn new(root: &Path, paths: &[PathBuf], symlinks: SymlinkPolicy) -> Self {
+        let walker = DirectoryWalker::new(root.to_path_buf()).with_symlinks(symlinks, root);
+        let mut files = VecDeque::new();
+        for path in paths {
+            // Like the walker, look at the path itself rather than at what a symlink points to.
+            let resolved = fs::symlink_metadata(path)
+                .map_err(SkipReason::from)
+                .and_then(|x| walker.resolve(path, x.file_type(), &[]));
+            match resolved {
+                Ok((file_type, _)) if file_type.is_dir() => {
+                    files.extend(DirectoryWalker::new(path.clone()).with_symlinks(symlinks, root))
+                }
+                Ok((file_type, _)) if file_type.is_file() => {
+                    files.push_back(Ok(SourceFile::Disk(path.clone())))
+                }
+                Ok(_) => files.push_back(Err(SkippedFile::new(path, SkipReason::SpecialFile))),
+                Err(reason) => files.push_back(Err(SkippedFile::new(path, reason))),
+            }
+        }
+
+        // Sorted paths put every directory before its subdirectories.
+        let directories: BTreeSet<&Path> = files
+            .iter()
+            .filter_map(|x| x.as_ref().ok())
+            .flat_map(|x| x.path().ancestors().skip(1))
+            .filter(|x| x.starts_with(root))
+            .collect();
+        let mut classifier = Classifier::new(root);
+        for directory in directories {
+            classifier.load_gitattributes(directory);
+        }
+        FileListSource { files, classifier }
+    }
+}
+
+impl Iterator for FileListSource {
+    type Item = Result<SourceFile, SkippedFile>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.files.pop_front()
+    }
+}
+
+impl FileSource for FileListSource {
+    fn classifier(&self) -> &Classifier {
+        &self.classifier
+    }
 }
 
 struct CodeFileSplitter {
-    directories: Vec<PathBuf>,
-    entries: Option<ReadDir>,
-    chunks: Vec<ChunkMetadata>,
+    source: Box<dyn FileSource>,
+    chunks: VecDeque<ChunkMetadata>,
+    pool: Option<ThreadPool>,
+    options: SplitOptions,
+    report: SplitReport,
+    repository: Option<String>,
+    branch: Option<String>,
+    commit: Option<String>,
+    blamer: Option<Blamer>,
 }
 
 impl From<String> for CodeFileSplitter {
     fn from(path: String) -> Self {
-        CodeFileSplitter {
-            directories: vec![PathBuf::from(path)],
-            entries: None,
-            chunks: vec![],
-        }
+        CodeFileSplitter::from_source(Box::new(DirectoryWalker::new(PathBuf::from(path))))
     }
 }
 
@@ -41,160 +601,633 @@ impl Iterator for CodeFileSplitter {
     type Item = ChunkMetadata;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while !self.chunks.is_empty() || !self.directories.is_empty() || self.entries.is_some() {
-            // Process chunks.
-            if !self.chunks.is_empty() {
-                return Some(self.chunks.remove(0));
-            }
-
-            // Process directory entries.
-            while let Some(read_dir) = &mut self.entries {
-                match read_dir.next() {
-                    Some(Ok(entry)) => {
-                        let path = entry.path();
-                        if let Ok(metadata) = entry.metadata() {
-                            if metadata.is_dir() {
-                                self.directories.push(path.clone());
-                                continue;
-                            } else {
-                                let maybe_chunks = CodeFileSplitter::process_file(&path);
-                                if let Some(mut chunks) = maybe_chunks {
-                                    self.chunks.append(&mut chunks);
-                                }
-                            }
-                        }
-                    }
-                    None => {
-                        // All done in this directory.
-                        self.entries = None;
-                        break;
-                    }
-                    _ => {}
+        loop {
+            if let Some(chunk) = self.chunks.pop_front() {
+                return Some(chunk);
+            }
+
+            let mut files = Vec::new();
+            for entry in self.source.by_ref() {
+                match entry {
+                    Ok(file) => files.push(file),
+                    Err(skipped) => self.report.skipped.push(skipped),
+                }
+                if files.len() == FILES_PER_BATCH {
+                    break;
                 }
             }
+            if files.is_empty() {
+                return None;
+            }
 
-            // Process directories.
-            while let Some(directory) = self.directories.pop() {
-                if let Ok(entries) = fs::read_dir(&directory) {
-                    self.entries = Some(entries);
+            // Indexed parallel iterators keep the input order, so the output is deterministic.
+            let options = &self.options;
+            let classifier = self.source.classifier();
+            let blamer = self.blamer.as_ref();
+            let split = || {
+                files
+                    .par_iter()
+                    .map(|x| CodeFileSplitter::process_file(x, options, classifier, blamer))
+                    .collect::<Vec<_>>()
+            };
+            let processed = match &self.pool {
+                Some(pool) => pool.install(split),
+                None => split(),
+            };
+            for (file, result) in files.iter().zip(processed) {
+                match result {
+                    Ok(chunks) => {
+                        self.report.files_split += 1;
+                        self.chunks
+                            .extend(chunks.into_iter().map(|x| ChunkMetadata {
+                                repository: self.repository.clone(),
+                                branch: self.branch.clone(),
+                                commit: self.commit.clone(),
+                                ..x
+                            }));
+                    }
+                    Err(reason) => self
+                        .report
+                        .skipped
+                        .push(SkippedFile::new(file.path(), reason)),
                 }
             }
         }
-        None
     }
 }
 
 impl CodeFileSplitter {
-    fn split_file(path: &Path, language: &Language) -> Result<FileContent, code_splitter::Error> {
-        let file = fs::File::open(path)?;
+    fn from_source(source: Box<dyn FileSource>) -> Self {
+        CodeFileSplitter {
+            source,
+            chunks: VecDeque::new(),
+            pool: None,
+            options: SplitOptions::default(),
+            report: SplitReport::default(),
+            repository: None,
+            branch: None,
+            commit: None,
+            blamer: None,
+        }
+    }
+
+    fn new(path: String, options: &SplitOptions) -> Result<Self> {
+        let mut walker = DirectoryWalker::new(PathBuf::from(&path))
+            .with_symlinks(options.symlinks, Path::new(&path));
+        if options.expand_archives {
+            walker = walker.with_archives(options.max_file_size);
+        }
+        let mut splitter = CodeFileSplitter::from_source(Box::new(walker)).with_options(options)?;
+        if options.blame {
+            splitter.blamer = Blamer::discover(Path::new(&path));
+        }
+        Ok(splitter.with_discovered_codebase(Path::new(&path)))
+    }
+
+    /// Creates a splitter over the files of `revision` in the git repository at
+    /// `repository_path`, read from the object database without checking them out.
+    ///
+    /// When `paths` is set, only files with those paths relative to the repository root are split.
+    fn from_git(
+        repository_path: &Path,
+        revision: &str,
+        paths: Option<&HashSet<PathBuf>>,
+        options: &SplitOptions,
+    ) -> Result<Self> {
+        let mut source = GitTreeSource::open(repository_path, revision)?;
+        if let Some(paths) = paths {
+            source.retain_paths(paths);
+        }
+        let repository = source.repository_id().to_string();
+        let branch = source.branch().map(str::to_string);
+        let commit = source.commit().to_string();
+
+        let mut splitter = CodeFileSplitter::from_source(Box::new(source)).with_options(options)?;
+        if options.blame {
+            splitter.blamer = Some(Blamer::at_commit(repository_path, &commit)?);
+        }
+        splitter.commit = Some(commit);
+        Ok(splitter.with_codebase(repository, branch))
+    }
 
-        let mut reader = io::BufReader::new(file);
+    /// Creates a splitter over the given files and directories below `root`.
+    fn from_paths(root: &Path, paths: &[PathBuf], options: &SplitOptions) -> Result<Self> {
+        let source = FileListSource::new(root, paths, options.symlinks);
+        let mut splitter = CodeFileSplitter::from_source(Box::new(source)).with_options(options)?;
+        if options.blame {
+            splitter.blamer = Blamer::discover(root);
+        }
+        Ok(splitter.with_discovered_codebase(root))
+    }
 
-        let lines: Vec<String> = reader.by_ref().lines().collect::<Result<_, _>>()?;
+    /// Creates a splitter over the files inside the archive at `archive_path`, which are read
+    /// into memory a few at a time.
+    fn from_archive(archive_path: &Path, options: &SplitOptions) -> Result<Self> {
+        let source = ArchiveSource::open(archive_path, options.max_file_size)?;
+        let splitter = CodeFileSplitter::from_source(Box::new(source)).with_options(options)?;
+        Ok(splitter.with_codebase(canonical_path(archive_path), None))
+    }
 
-        let mut contents = String::new();
-        reader.rewind()?;
-        reader.read_to_string(&mut contents)?;
-        let chunks = language.splitter.split(contents.as_bytes())?;
+    fn with_options(mut self, options: &SplitOptions) -> Result<Self> {
+        if let Some(threads) = options.threads {
+            self.pool = Some(ThreadPoolBuilder::new().num_threads(threads).build()?);
+        }
+        self.options = options.clone();
+        Ok(self)
+    }
 
-        Ok(FileContent { lines, chunks })
+    /// Sets the repository and branch of every chunk, unless the options override them.
+    fn with_codebase(mut self, repository: String, branch: Option<String>) -> Self {
+        self.repository = Some(self.options.repository.clone().unwrap_or(repository));
+        self.branch = self.options.branch.clone().or(branch);
+        self
     }
 
-    fn process_file(path: &Path) -> Option<Vec<ChunkMetadata>> {
-        let maybe_extension = path.extension().unwrap().to_str().map(|x| x.to_string());
+    /// Like [`CodeFileSplitter::with_codebase`], with the repository containing `path`, or
+    /// `path` itself outside a git repository.
+    fn with_discovered_codebase(self, path: &Path) -> Self {
+        let (repository, branch) = codebase_of(path, &self.options);
+        self.with_codebase(repository, branch)
+    }
 
-        if let Some(extension) = maybe_extension {
-            let maybe_processed_content = get_languages()
-                .iter()
-                .filter(|x| x.extensions.contains(&extension))
-                .map(|y| CodeFileSplitter::split_file(path, y))
-                .next()?
-                .map_err(|e| eprintln!("Failed to process file: {e}"));
-
-            if maybe_processed_content.is_ok() {
-                let processed_content = maybe_processed_content.unwrap();
-                let mut chunks = Vec::new();
-                for chunk in processed_content.chunks {
-                    chunks.push(ChunkMetadata {
-                        file_path: path.to_str().unwrap_or_default().to_string(),
-                        file_name: path
-                            .file_name()
-                            .unwrap()
-                            .to_str()
-                            .unwrap_or_default()
-                            .to_string(),
-                        start_line: chunk.range.start_point.row as u64,
-                        end_line: chunk.range.end_point.row as u64,
-                        text: Some(
-                            processed_content.lines
-                                [chunk.range.start_point.row..chunk.range.end_point.row]
-                                .join("\n"),
-                        )
-                        .filter(|x| !x.is_empty()),
-                        size: chunk.size as u64,
-                    });
-                }
-                return Some(chunks);
+    /// Returns the report for the files walked so far, leaving an empty one in its place.
+    fn take_report(&mut self) -> SplitReport {
+        std::mem::take(&mut self.report)
+    }
+
+    fn split_file(
+        file: &SourceFile,
+        language: &Language,
+        options: &SplitOptions,
+    ) -> Result<FileContent, SkipReason> {
+        let contents = decode_text(&file.read(options)?)?;
+        let lines: Vec<String> = contents.lines().map(str::to_string).collect();
+        if let Some(limit) = options.max_line_length {
+            if let Some((index, line)) = lines.iter().enumerate().find(|(_, x)| x.len() > limit) {
+                return Err(SkipReason::LineTooLong {
+                    line: index + 1,
+                    length: line.len(),
+                    limit,
+                });
             }
         }
-        None
+        let chunks =
+            language
+                .splitter
+                .split(contents.as_bytes())
+                .map_err(|e| SkipReason::ParseError {
+                    message: e.to_string(),
+                })?;
+
+        Ok(FileContent {
+            text: contents,
+            lines,
+            chunks,
+        })
+    }
+
+    fn process_file(
+        file: &SourceFile,
+        options: &SplitOptions,
+        classifier: &Classifier,
+        blamer: Option<&Blamer>,
+    ) -> Result<Vec<ChunkMetadata>, SkipReason> {
+        let path = file.path();
+        let language = get_language_for_path(path).ok_or(SkipReason::UnsupportedLanguage)?;
+        let processed_content = CodeFileSplitter::split_file(file, language, options)?;
+        let classification = classifier.classify(path, &processed_content.lines);
+        let blame = blamer.and_then(|x| x.blame(path, processed_content.text.as_bytes()));
+        let symbols = find_symbols(language, &processed_content.text);
+
+        let file_path = path.to_string_lossy().to_string();
+        let file_name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut chunks = Vec::new();
+        for chunk in processed_content.chunks {
+            // Chunk text spans rows `start_point.row..end_point.row`, which are lines counted
+            // from 1 up to `end_point.row` inclusive.
+            let last_change = blame.as_ref().and_then(|x| {
+                x.last_change(
+                    chunk.range.start_point.row + 1,
+                    chunk
+                        .range
+                        .end_point
+                        .row
+                        .max(chunk.range.start_point.row + 1),
+                )
+            });
+            let symbol = chunk_symbol(
+                &symbols,
+                chunk.range.start_point.row,
+                chunk.range.end_point.row,
+            );
+            chunks.push(ChunkMetadata {
+                file_path: file_path.clone(),
+                file_name: file_name.clone(),
+                start_line: chunk.range.start_point.row as u64,
+                end_line: chunk.range.end_point.row as u64,
+                text: Some(
+                    processed_content.lines[chunk.range.start_point.row..chunk.range.end_point.row]
+                        .join("\n"),
+                )
+                .filter(|x| !x.is_empty()),
+                size: chunk.size as u64,
+                classification,
+                language: language.name.clone(),
+                symbol_kind: symbol.map(|x| x.kind),
+                symbol_name: symbol.and_then(|x| x.name.clone()),
+                repository: None,
+                branch: None,
+                commit: None,
+                last_commit: last_change.map(|x| x.commit.clone()),
+                last_author: last_change.map(|x| x.author.clone()),
+                last_modified: last_change.map(|x| x.time),
+            });
+        }
+        Ok(chunks)
     }
 }
 
+/// Decodes raw file content into UTF-8 text.
 ///
+/// A byte order mark takes precedence, followed by the NUL byte patterns of UTF-16 text without
+/// one; otherwise content with NUL bytes near the start is treated as binary, valid UTF-8 is used
+/// as is, and anything else is transcoded from the encoding that `chardetng` considers most
+/// likely, such as Latin-1 or Shift JIS.
+fn decode_text(bytes: &[u8]) -> Result<String, SkipReason> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return if had_errors {
+            Err(SkipReason::NonUtf8)
+        } else {
+            Ok(text.into_owned())
+        };
+    }
+
+    if let Some(encoding) = detect_utf16(bytes) {
+        let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+        if !had_errors && !text.contains('\0') {
+            return Ok(text.into_owned());
+        }
+    }
+
+    if bytes[..bytes.len().min(BINARY_SNIFF_LENGTH)].contains(&0) {
+        return Err(SkipReason::Binary);
+    }
+
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Ok(text.to_string());
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, false);
+    let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+    if had_errors {
+        return Err(SkipReason::NonUtf8);
+    }
+    Ok(text.into_owned())
+}
+
+/// Returns the UTF-16 encoding `bytes` look like without a byte order mark: an even length, and
+/// NUL bytes in the high byte of at least half the code units near the start, but never in the
+/// low byte, as in text that is mostly ASCII.
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let sample = &bytes[..bytes.len().min(BINARY_SNIFF_LENGTH)];
+    let units = sample.len() / 2;
+    let nul_bytes = |offset: usize| {
+        sample
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|x| **x == 0)
+            .count()
+    };
+    match (nul_bytes(0), nul_bytes(1)) {
+        (0, odd) if odd * 2 >= units => Some(encoding_rs::UTF_16LE),
+        (even, 0) if even * 2 >= units => Some(encoding_rs::UTF_16BE),
+        _ => None,
+    }
+}
+
+/// Returns the repository and branch recorded with the chunks of the files under `path`: those
+/// of `options` if set, or else those of the git repository containing `path`, or `path` itself
+/// outside a git repository.
+pub(crate) fn codebase_of(path: &Path, options: &SplitOptions) -> (String, Option<String>) {
+    let (repository, branch) =
+        discover_codebase(path).unwrap_or_else(|| (canonical_path(path), None));
+    (
+        options.repository.clone().unwrap_or(repository),
+        options.branch.clone().or(branch),
+    )
+}
+
+fn canonical_path(path: &Path) -> String {
+    path.canonicalize()
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Returns the ID of `chunk`, which stays the same as long as its codebase, file path and lines
+/// do, so that chunks can be referred to across re-indexing.
+fn chunk_id(chunk: &ChunkMetadata) -> String {
+    let mut hasher = blake3::Hasher::new();
+    // Every field is encoded unambiguously, as IDs are persisted: optional ones with a presence
+    // byte, strings with their length first and numbers as little-endian bytes.
+    for field in [
+        chunk.repository.as_deref(),
+        chunk.branch.as_deref(),
+        Some(chunk.file_path.as_str()),
+    ] {
+        match field {
+            Some(value) => {
+                hasher.update(&[1]);
+                hasher.update(&(value.len() as u64).to_le_bytes());
+                hasher.update(value.as_bytes());
+            }
+            None => {
+                hasher.update(&[0]);
+            }
+        }
+    }
+    hasher.update(&chunk.start_line.to_le_bytes());
+    hasher.update(&chunk.end_line.to_le_bytes());
+    hasher.finalize().to_hex()[..CHUNK_ID_LENGTH].to_string()
+}
+
+fn chunk_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("file_path", DataType::Utf8, false),
+        Field::new("file_name", DataType::Utf8, false),
+        Field::new("start_line", DataType::UInt64, false),
+        Field::new("end_line", DataType::UInt64, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("classification", DataType::Utf8, false),
+        Field::new("language", DataType::Utf8, false),
+        Field::new("symbol_kind", DataType::Utf8, true),
+        Field::new("symbol_name", DataType::Utf8, true),
+        Field::new("repository", DataType::Utf8, true),
+        Field::new("branch", DataType::Utf8, true),
+        Field::new("commit", DataType::Utf8, true),
+        Field::new("last_commit", DataType::Utf8, true),
+        Field::new("last_author", DataType::Utf8, true),
+        Field::new("last_modified", DataType::Int64, true),
+    ]))
+}
+
+/// Writes chunks to a Parquet file, one bounded row group at a time.
+struct ChunkWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    schema: SchemaRef,
+    row_group_size: usize,
+    rows: usize,
+    written: u64,
+    ids: StringBuilder,
+    file_paths: StringBuilder,
+    file_names: StringBuilder,
+    start_lines: UInt64Builder,
+    end_lines: UInt64Builder,
+    texts: StringBuilder,
+    sizes: UInt64Builder,
+    classifications: StringBuilder,
+    languages: StringBuilder,
+    symbol_kinds: StringBuilder,
+    symbol_names: StringBuilder,
+    repositories: StringBuilder,
+    branches: StringBuilder,
+    commits: StringBuilder,
+    last_commits: StringBuilder,
+    last_authors: StringBuilder,
+    last_modified: Int64Builder,
+}
+
+impl<W: Write + Send> ChunkWriter<W> {
+    fn try_new(output: W, row_group_size: usize) -> Result<Self> {
+        let row_group_size = row_group_size.max(1);
+        let schema = chunk_schema();
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(row_group_size)
+            .build();
+        let writer = ArrowWriter::try_new(output, schema.clone(), Some(properties))?;
+
+        Ok(ChunkWriter {
+            writer,
+            schema,
+            row_group_size,
+            rows: 0,
+            written: 0,
+            ids: StringBuilder::new(),
+            file_paths: StringBuilder::new(),
+            file_names: StringBuilder::new(),
+            start_lines: UInt64Builder::new(),
+            end_lines: UInt64Builder::new(),
+            texts: StringBuilder::new(),
+            sizes: UInt64Builder::new(),
+            classifications: StringBuilder::new(),
+            languages: StringBuilder::new(),
+            symbol_kinds: StringBuilder::new(),
+            symbol_names: StringBuilder::new(),
+            repositories: StringBuilder::new(),
+            branches: StringBuilder::new(),
+            commits: StringBuilder::new(),
+            last_commits: StringBuilder::new(),
+            last_authors: StringBuilder::new(),
+            last_modified: Int64Builder::new(),
+        })
+    }
+
+    fn push(&mut self, chunk: ChunkMetadata) -> Result<()> {
+        let Some(text) = chunk.text else {
+            return Ok(());
+        };
+
+        self.ids.append_value(chunk_id(&chunk));
+        self.file_paths.append_value(chunk.file_path);
+        self.file_names.append_value(chunk.file_name);
+        self.start_lines.append_value(chunk.start_line);
+        self.end_lines.append_value(chunk.end_line);
+        self.texts.append_value(text);
+        self.sizes.append_value(chunk.size);
+        self.classifications
+            .append_value(chunk.classification.as_str());
+        self.languages.append_value(chunk.language);
+        self.symbol_kinds
+            .append_option(chunk.symbol_kind.map(|x| x.as_str()));
+        self.symbol_names.append_option(chunk.symbol_name);
+        self.repositories.append_option(chunk.repository);
+        self.branches.append_option(chunk.branch);
+        self.commits.append_option(chunk.commit);
+        self.last_commits.append_option(chunk.last_commit);
+        self.last_authors.append_option(chunk.last_author);
+        self.last_modified.append_option(chunk.last_modified);
+        self.rows += 1;
+        self.written += 1;
+
+        if self.rows >= self.row_group_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.ids.finish()),
+            Arc::new(self.file_paths.finish()),
+            Arc::new(self.file_names.finish()),
+            Arc::new(self.start_lines.finish()),
+            Arc::new(self.end_lines.finish()),
+            Arc::new(self.texts.finish()),
+            Arc::new(self.sizes.finish()),
+            Arc::new(self.classifications.finish()),
+            Arc::new(self.languages.finish()),
+            Arc::new(self.symbol_kinds.finish()),
+            Arc::new(self.symbol_names.finish()),
+            Arc::new(self.repositories.finish()),
+            Arc::new(self.branches.finish()),
+            Arc::new(self.commits.finish()),
+            Arc::new(self.last_commits.finish()),
+            Arc::new(self.last_authors.finish()),
+            Arc::new(self.last_modified.finish()),
+        ];
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.rows = 0;
+
+        // Close the row group right away so that only one is ever held in memory.
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Flushes any buffered chunks, closes the file and returns the number of chunks written.
+    fn close(mut self) -> Result<u64> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(self.written)
+    }
+}
+
+/// Splits every supported source file under `input_dir_path` into chunks and writes them to a
+/// Parquet file at `output_file_uri`, using the default [`SplitOptions`].
+pub fn find_and_split(input_dir_path: String, output_file_uri: String) -> Result<SplitReport> {
+    find_and_split_with_options(input_dir_path, output_file_uri, &SplitOptions::default())
+}
+
+/// Splits every supported source file under `input_dir_path` into chunks and writes them to a
+/// Parquet file at `output_file_uri`.
 ///
-/// # Arguments
-///
-/// * `input_dir_path`:
-/// * `output_file_uri`:
-///
-/// returns: Result<(), String>
+/// Chunks are streamed to the output as the splitter yields them, in row groups of at most
+/// `options.row_group_size` rows, so memory usage does not grow with the size of the codebase.
+/// Files are split in parallel, but chunks are always written in the order described by the
+/// directory walker, so the same input produces the same output across runs.
 ///
-/// # Examples
+/// Files that cannot be split do not fail the run; they are listed in the returned
+/// [`SplitReport`], which is also written to `options.report_path` when set.
+pub fn find_and_split_with_options(
+    input_dir_path: String,
+    output_file_uri: String,
+    options: &SplitOptions,
+) -> Result<SplitReport> {
+    let splitter = CodeFileSplitter::new(input_dir_path, options)?;
+    write_chunks(splitter, output_file_uri, options)
+}
+
+/// Splits every supported source file in `revision` of the local git repository at
+/// `repository_path` and writes the chunks to a Parquet file at `output_file_uri`.
 ///
-/// ```
+/// `revision` can be anything `git rev-parse` understands, such as a commit SHA, a branch or a
+/// tag. Files are read straight from the object database, so the working directory is left
+/// untouched and does not even need to exist. Chunk paths are relative to the repository root,
+/// and every chunk records the resolved commit SHA and the repository identifier: the URL of the
+/// `origin` remote, or the repository path when there is no such remote.
+pub fn find_and_split_git(
+    repository_path: String,
+    revision: String,
+    output_file_uri: String,
+    options: &SplitOptions,
+) -> Result<SplitReport> {
+    let splitter =
+        CodeFileSplitter::from_git(Path::new(&repository_path), &revision, None, options)?;
+    write_chunks(splitter, output_file_uri, options)
+}
+
+/// Splits every supported source file inside the tar, tar.gz or zip archive at `archive_path`
+/// and writes the chunks to a Parquet file at `output_file_uri`.
 ///
-/// ```
-pub fn find_and_split(input_dir_path: String, output_file_uri: String) -> Result<(), String> {
-    let splitter = CodeFileSplitter::from(input_dir_path);
-    let mut output_file = fs::File::create(output_file_uri).map_err(|e| e.to_string())?;
-
-    let mut file_paths: Vec<String> = Vec::new();
-    let mut file_names: Vec<String> = Vec::new();
-    let mut start_lines: Vec<u64> = Vec::new();
-    let mut end_lines: Vec<u64> = Vec::new();
-    let mut texts: Vec<String> = Vec::new();
-    let mut sizes: Vec<u64> = Vec::new();
-
-    for chunk in splitter {
-        if chunk.text.is_some() {
-            file_paths.push(chunk.file_path);
-            file_names.push(chunk.file_name);
-            start_lines.push(chunk.start_line);
-            end_lines.push(chunk.end_line);
-            texts.push(chunk.text.unwrap());
-            sizes.push(chunk.size);
-        }
-    }
-    let mut dataframe = df!(
-        "file_path" => file_paths,
-        "file_name" => file_names,
-        "start_line" => start_lines,
-        "end_line" => end_lines,
-        "text" => texts,
-        "size" => sizes,
-    )
-    .map_err(|x| x.to_string())?;
+/// Archives nested inside the archive are expanded too. Nothing is extracted to disk: entries are
+/// split in memory, and chunk paths join the archive path and the path inside it with
+/// [`ARCHIVE_PATH_SEPARATOR`], as in `drop.tar.gz!/src/lib.rs`.
+pub fn find_and_split_archive(
+    archive_path: String,
+    output_file_uri: String,
+    options: &SplitOptions,
+) -> Result<SplitReport> {
+    let splitter = CodeFileSplitter::from_archive(Path::new(&archive_path), options)?;
+    write_chunks(splitter, output_file_uri, options)
+}
+
+/// Like [`find_and_split_git`], but only splits the files with the given `paths`.
+pub(crate) fn split_git_paths(
+    repository_path: &Path,
+    revision: &str,
+    paths: &HashSet<PathBuf>,
+    output_file_uri: String,
+    options: &SplitOptions,
+) -> Result<SplitReport> {
+    let splitter = CodeFileSplitter::from_git(repository_path, revision, Some(paths), options)?;
+    write_chunks(splitter, output_file_uri, options)
+}
 
-    ParquetWriter::new(&mut output_file)
-        .finish(&mut dataframe)
-        .map_err(|e| e.to_string())?;
-    Ok(())
+/// Splits the given files, and the files under the given directories, all of them below `root`,
+/// and writes the chunks to a Parquet file at `output_file_uri`.
+pub(crate) fn split_paths(
+    root: &Path,
+    paths: &[PathBuf],
+    output_file_uri: String,
+    options: &SplitOptions,
+) -> Result<SplitReport> {
+    let splitter = CodeFileSplitter::from_paths(root, paths, options)?;
+    write_chunks(splitter, output_file_uri, options)
+}
+
+fn write_chunks(
+    mut splitter: CodeFileSplitter,
+    output_file_uri: String,
+    options: &SplitOptions,
+) -> Result<SplitReport> {
+    let output_file = fs::File::create(output_file_uri)?;
+
+    let mut writer = ChunkWriter::try_new(output_file, options.row_group_size)?;
+    for chunk in splitter.by_ref() {
+        writer.push(chunk)?;
+    }
+
+    let mut report = splitter.take_report();
+    report.chunks_written = writer.close()?;
+    if let Some(report_path) = &options.report_path {
+        report.write_json(report_path)?;
+    }
+    Ok(report)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::archive::tests::{create_tar_gz, create_zip};
+    use crate::git::tests::commit_files;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use polars::prelude::*;
     use std::fs::File;
     use std::io::Write;
     use std::path::{Path, PathBuf};
@@ -220,8 +1253,12 @@ mod tests {
             "#,
         );
 
-        let language = get_languages().iter().find(|x| x.name.eq("rust")).unwrap();
-        let result = CodeFileSplitter::split_file(&temp_file_path, language);
+        let language = get_language_for_path(Path::new("main.rs")).unwrap();
+        let result = CodeFileSplitter::split_file(
+            &SourceFile::Disk(temp_file_path),
+            language,
+            &SplitOptions::default(),
+        );
 
         // Assert that the split succeeded and returned the correct structure
         assert!(result.is_ok());
@@ -250,10 +1287,15 @@ mod tests {
             "#,
         );
 
-        let result = CodeFileSplitter::process_file(&temp_file_path);
+        let result = CodeFileSplitter::process_file(
+            &SourceFile::Disk(temp_file_path.clone()),
+            &SplitOptions::default(),
+            &Classifier::new(temp_dir.path()),
+            None,
+        );
 
         // Assert processing results
-        assert!(result.is_some(), "Processing result should not be None");
+        assert!(result.is_ok(), "Processing result should not be an error");
         let chunks = result.unwrap();
         // Example: Check that chunks have metadata
         assert!(!chunks.is_empty(), "Chunks should not be empty");
@@ -379,15 +1421,70 @@ mod tests {
         assert_eq!(
             dataframe.get_column_names(),
             &[
+                "id",
                 "file_path",
                 "file_name",
                 "start_line",
                 "end_line",
                 "text",
-                "size"
+                "size",
+                "classification",
+                "language",
+                "symbol_kind",
+                "symbol_name",
+                "repository",
+                "branch",
+                "commit",
+                "last_commit",
+                "last_author",
+                "last_modified"
             ]
         );
-        assert_eq!(dataframe.shape(), (4, 6));
+        assert_eq!(dataframe.shape(), (4, 17));
+        let ids: std::collections::HashSet<&str> = dataframe
+            .column("id")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(ids.len(), 4);
+        // Outside a git repository, the repository is the input directory.
+        assert!(
+            dataframe
+                .column("repository")
+                .unwrap()
+                .str()
+                .unwrap()
+                .iter()
+                .all(|x| x == Some(canonical_path(root_temp_dir.path()).as_str()))
+        );
+        assert!(
+            dataframe
+                .column("language")
+                .unwrap()
+                .str()
+                .unwrap()
+                .iter()
+                .all(|x| x == Some("rust"))
+        );
+        // Chunks inside `bar` belong to it, even when they do not start it.
+        let symbols: Vec<(Option<&str>, Option<&str>)> = dataframe
+            .column("symbol_kind")
+            .unwrap()
+            .str()
+            .unwrap()
+            .iter()
+            .zip(dataframe.column("symbol_name").unwrap().str().unwrap())
+            .collect();
+        assert!(
+            symbols
+                .iter()
+                .filter(|(_, name)| *name == Some("bar"))
+                .count()
+                > 1
+        );
+        assert!(symbols.contains(&(Some("function"), Some("foo"))));
         assert!(
             dataframe
                 .column("file_path")
@@ -421,4 +1518,740 @@ mod tests {
                 )
         );
     }
+
+    #[test]
+    fn test_find_and_split_row_groups() {
+        let root_temp_dir = tempfile::tempdir().expect("Failed to create root temp directory");
+        for name in ["first.rs", "second.rs", "third.rs"] {
+            create_temp_file(
+                root_temp_dir.path(),
+                name,
+                r#"
+            fn foo() {
+                println!("Row groups");
+            }
+            "#,
+            );
+        }
+        let output_file_uri = root_temp_dir.path().join("output_file.parquet");
+
+        let options = SplitOptions {
+            row_group_size: 2,
+            ..Default::default()
+        };
+        find_and_split_with_options(
+            root_temp_dir.path().to_str().unwrap().to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &options,
+        )
+        .expect("Failed to split files");
+
+        let reader = SerializedFileReader::new(File::open(&output_file_uri).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 3);
+        assert_eq!(metadata.num_row_groups(), 2);
+        assert!(
+            metadata
+                .row_groups()
+                .iter()
+                .all(|x| x.num_rows() <= options.row_group_size as i64)
+        );
+    }
+
+    #[test]
+    fn test_code_file_splitter_is_deterministic() {
+        let root_temp_dir = tempfile::tempdir().expect("Failed to create root temp directory");
+        // Version control directories are never walked.
+        for directory in ["b", "a", "a/c", ".git"] {
+            let directory_path = root_temp_dir.path().join(directory);
+            fs::create_dir_all(&directory_path).expect("Failed to create test directory");
+            for name in ["z.rs", "m.rs", "a.rs"] {
+                create_temp_file(
+                    &directory_path,
+                    name,
+                    r#"
+            fn foo() {
+                println!("Deterministic");
+            }
+            "#,
+                );
+            }
+        }
+
+        let options = SplitOptions {
+            threads: Some(4),
+            ..Default::default()
+        };
+        let collect_paths = || {
+            CodeFileSplitter::new(root_temp_dir.path().to_str().unwrap().to_string(), &options)
+                .unwrap()
+                .map(|x| x.file_path)
+                .collect::<Vec<_>>()
+        };
+
+        let expected: Vec<String> = [
+            "a/a.rs", "a/m.rs", "a/z.rs", "a/c/a.rs", "a/c/m.rs", "a/c/z.rs", "b/a.rs", "b/m.rs",
+            "b/z.rs",
+        ]
+        .iter()
+        .map(|x| root_temp_dir.path().join(x).to_string_lossy().to_string())
+        .collect();
+        assert_eq!(collect_paths(), expected);
+        assert_eq!(collect_paths(), expected);
+    }
+
+    #[test]
+    fn test_find_and_split_report() {
+        let root_temp_dir = tempfile::tempdir().expect("Failed to create root temp directory");
+        let input_dir = root_temp_dir.path().join("input");
+        fs::create_dir(&input_dir).expect("Failed to create input directory");
+
+        let supported_file_path = create_temp_file(
+            &input_dir,
+            "supported.rs",
+            r#"
+            fn foo() {
+                println!("Supported");
+            }
+            "#,
+        );
+        let unsupported_file_path = create_temp_file(&input_dir, "README.md", "# Readme");
+        let binary_file_path = input_dir.join("blob.rs");
+        fs::write(&binary_file_path, b"\x7fELF\x02\x01\x01\x00\x00\x00").unwrap();
+        let large_file_path = create_temp_file(&input_dir, "large.py", &"x = 1\n".repeat(1000));
+
+        let output_file_uri = root_temp_dir.path().join("output_file.parquet");
+        let report_path = root_temp_dir.path().join("report.json");
+        let options = SplitOptions {
+            max_file_size: Some(1024),
+            report_path: Some(report_path.clone()),
+            ..Default::default()
+        };
+
+        let report = find_and_split_with_options(
+            input_dir.to_str().unwrap().to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &options,
+        )
+        .expect("Failed to split files");
+
+        assert_eq!(report.files_split, 1);
+        assert!(report.chunks_written > 0);
+        assert!(
+            !report
+                .skipped
+                .iter()
+                .any(|x| x.path == supported_file_path.to_string_lossy())
+        );
+        assert_eq!(
+            report.skipped,
+            vec![
+                SkippedFile::new(&unsupported_file_path, SkipReason::UnsupportedLanguage),
+                SkippedFile::new(&binary_file_path, SkipReason::Binary),
+                SkippedFile::new(
+                    &large_file_path,
+                    SkipReason::TooLarge {
+                        size: fs::metadata(&large_file_path).unwrap().len(),
+                        limit: 1024
+                    }
+                ),
+            ]
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_reader(File::open(report_path).unwrap()).unwrap();
+        assert_eq!(json["files_split"], 1);
+        assert_eq!(json["skipped"][0]["reason"]["kind"], "unsupported_language");
+        assert_eq!(json["skipped"][1]["reason"]["kind"], "binary");
+    }
+
+    #[test]
+    fn test_chunk_id() {
+        let chunk = ChunkMetadata {
+            file_path: "src/lib.rs".to_string(),
+            file_name: "lib.rs".to_string(),
+            start_line: 0,
+            end_line: 10,
+            text: None,
+            size: 0,
+            classification: Classification::Source,
+            language: "rust".to_string(),
+            symbol_kind: None,
+            symbol_name: None,
+            repository: Some("repo".to_string()),
+            branch: None,
+            commit: None,
+            last_commit: None,
+            last_author: None,
+            last_modified: None,
+        };
+        let id = chunk_id(&chunk);
+        assert_eq!(id.len(), CHUNK_ID_LENGTH);
+        assert_eq!(chunk_id(&chunk.clone()), id);
+
+        // Fields cannot run into one another, and a missing branch is not an empty one.
+        let other = |edit: fn(&mut ChunkMetadata)| {
+            let mut other = chunk.clone();
+            edit(&mut other);
+            chunk_id(&other)
+        };
+        assert_ne!(other(|x| x.branch = Some(String::new())), id);
+        assert_ne!(
+            other(|x| {
+                x.repository = Some("rep".to_string());
+                x.branch = Some("o".to_string());
+            }),
+            other(|x| {
+                x.repository = Some("re".to_string());
+                x.branch = Some("po".to_string());
+            })
+        );
+        assert_ne!(other(|x| x.end_line = 11), id);
+    }
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(
+            decode_text("fn foo() {}".as_bytes()).unwrap(),
+            "fn foo() {}"
+        );
+        assert_eq!(
+            decode_text(b"// caf\xe9 cr\xe8me br\xfbl\xe9e\nfn foo() {}").unwrap(),
+            "// café crème brûlée\nfn foo() {}"
+        );
+
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend("fn foo() {}".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(decode_text(&utf16).unwrap(), "fn foo() {}");
+
+        // Without a byte order mark, UTF-16 is told from binary data by where its NUL bytes are.
+        let text = "// café\nfn foo() {}";
+        let utf16le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(decode_text(&utf16le).unwrap(), text);
+        let utf16be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(decode_text(&utf16be).unwrap(), text);
+
+        assert_eq!(
+            decode_text(b"\x00asm\x01\x00\x00\x00"),
+            Err(SkipReason::Binary)
+        );
+    }
+
+    #[test]
+    fn test_process_file_with_long_lines() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let minified = format!("function a(){{{}}}", "var b=1;".repeat(200));
+        let temp_file_path = create_temp_file(temp_dir.path(), "bundle.min.js", &minified);
+
+        let classifier = Classifier::new(temp_dir.path());
+        let source_file = SourceFile::Disk(temp_file_path);
+        let result = CodeFileSplitter::process_file(
+            &source_file,
+            &SplitOptions::default(),
+            &classifier,
+            None,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            SkipReason::LineTooLong {
+                line: 1,
+                length: minified.len(),
+                limit: DEFAULT_MAX_LINE_LENGTH
+            }
+        );
+
+        let options = SplitOptions {
+            max_line_length: None,
+            ..Default::default()
+        };
+        assert!(CodeFileSplitter::process_file(&source_file, &options, &classifier, None).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_walker_symlinks() {
+        use std::os::unix::fs::symlink;
+        use std::os::unix::net::UnixListener;
+
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let root = temp_dir.path().join("root");
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        create_temp_file(&root.join("src"), "lib.rs", "fn lib() {}");
+        create_temp_file(&outside, "other.rs", "fn other() {}");
+        symlink(&root, root.join("src/loop")).unwrap();
+        symlink(root.join("src/lib.rs"), root.join("link.rs")).unwrap();
+        symlink(&outside, root.join("outside")).unwrap();
+        symlink(root.join("missing.rs"), root.join("dangling.rs")).unwrap();
+        let _listener = UnixListener::bind(root.join("socket")).unwrap();
+
+        let walk = |symlinks: SymlinkPolicy| {
+            let mut files = Vec::new();
+            let mut skipped = Vec::new();
+            for entry in DirectoryWalker::new(root.clone()).with_symlinks(symlinks, &root) {
+                match entry {
+                    Ok(file) => files.push(file.path().strip_prefix(&root).unwrap().to_owned()),
+                    Err(x) => skipped.push((
+                        Path::new(&x.path).strip_prefix(&root).unwrap().to_owned(),
+                        x.reason,
+                    )),
+                }
+            }
+            (files, skipped)
+        };
+
+        let (files, skipped) = walk(SymlinkPolicy::Skip);
+        assert_eq!(files, vec![PathBuf::from("src/lib.rs")]);
+        assert_eq!(
+            skipped,
+            vec![
+                (PathBuf::from("dangling.rs"), SkipReason::Symlink),
+                (PathBuf::from("link.rs"), SkipReason::Symlink),
+                (PathBuf::from("outside"), SkipReason::Symlink),
+                (PathBuf::from("socket"), SkipReason::SpecialFile),
+                (PathBuf::from("src/loop"), SkipReason::Symlink),
+            ]
+        );
+
+        let (files, skipped) = walk(SymlinkPolicy::FollowFiles);
+        assert_eq!(
+            files,
+            vec![PathBuf::from("link.rs"), PathBuf::from("src/lib.rs")]
+        );
+        assert_eq!(skipped.len(), 4);
+        assert!(
+            matches!(skipped[0], (ref x, SkipReason::Io { .. }) if x == Path::new("dangling.rs"))
+        );
+        assert_eq!(
+            skipped[1..],
+            [
+                (PathBuf::from("outside"), SkipReason::SymlinkToDirectory),
+                (PathBuf::from("socket"), SkipReason::SpecialFile),
+                (PathBuf::from("src/loop"), SkipReason::SymlinkToDirectory),
+            ]
+        );
+
+        let (files, skipped) = walk(SymlinkPolicy::Follow);
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("link.rs"),
+                PathBuf::from("outside/other.rs"),
+                PathBuf::from("src/lib.rs"),
+            ]
+        );
+        assert_eq!(skipped.len(), 3);
+        assert!(
+            matches!(skipped[0], (ref x, SkipReason::Io { .. }) if x == Path::new("dangling.rs"))
+        );
+        assert_eq!(
+            skipped[1],
+            (PathBuf::from("socket"), SkipReason::SpecialFile)
+        );
+        assert!(
+            matches!(skipped[2], (ref x, SkipReason::SymlinkLoop { .. }) if x == Path::new("src/loop"))
+        );
+
+        let (files, skipped) = walk(SymlinkPolicy::FollowWithinRoot);
+        assert_eq!(
+            files,
+            vec![PathBuf::from("link.rs"), PathBuf::from("src/lib.rs")]
+        );
+        assert_eq!(skipped.len(), 4);
+        assert!(
+            matches!(skipped[1], (ref x, SkipReason::SymlinkOutsideRoot { .. }) if x == Path::new("outside"))
+        );
+        assert!(
+            matches!(skipped[3], (ref x, SkipReason::SymlinkLoop { .. }) if x == Path::new("src/loop"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_list_source_symlinks() {
+        use std::os::unix::fs::symlink;
+        use std::os::unix::net::UnixListener;
+
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let root = temp_dir.path().join("root");
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        create_temp_file(&root.join("src"), "lib.rs", "fn lib() {}");
+        create_temp_file(&outside, "other.rs", "fn other() {}");
+        symlink(root.join("src/lib.rs"), root.join("link.rs")).unwrap();
+        symlink(&outside, root.join("outside")).unwrap();
+        let _listener = UnixListener::bind(root.join("socket")).unwrap();
+        let paths: Vec<_> = ["link.rs", "outside", "socket", "src"]
+            .iter()
+            .map(|x| root.join(x))
+            .collect();
+
+        let list = |symlinks: SymlinkPolicy| {
+            let mut files = Vec::new();
+            let mut skipped = Vec::new();
+            for entry in FileListSource::new(&root, &paths, symlinks) {
+                match entry {
+                    Ok(file) => files.push(file.path().strip_prefix(&root).unwrap().to_owned()),
+                    Err(x) => skipped.push((
+                        Path::new(&x.path).strip_prefix(&root).unwrap().to_owned(),
+                        x.reason,
+                    )),
+                }
+            }
+            (files, skipped)
+        };
+
+        let (files, skipped) = list(SymlinkPolicy::Skip);
+        assert_eq!(files, vec![PathBuf::from("src/lib.rs")]);
+        assert_eq!(
+            skipped,
+            vec![
+                (PathBuf::from("link.rs"), SkipReason::Symlink),
+                (PathBuf::from("outside"), SkipReason::Symlink),
+                (PathBuf::from("socket"), SkipReason::SpecialFile),
+            ]
+        );
+
+        let (files, skipped) = list(SymlinkPolicy::FollowFiles);
+        assert_eq!(
+            files,
+            vec![PathBuf::from("link.rs"), PathBuf::from("src/lib.rs")]
+        );
+        assert_eq!(
+            skipped,
+            vec![
+                (PathBuf::from("outside"), SkipReason::SymlinkToDirectory),
+                (PathBuf::from("socket"), SkipReason::SpecialFile),
+            ]
+        );
+
+        let (files, _) = list(SymlinkPolicy::Follow);
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("link.rs"),
+                PathBuf::from("outside/other.rs"),
+                PathBuf::from("src/lib.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_file_splitter_classification() {
+        let root_temp_dir = tempfile::tempdir().expect("Failed to create root temp directory");
+        let content = r#"
+            fn foo() {
+                println!("Classification");
+            }
+            "#;
+        for directory in ["src", "vendor/serde", "proto"] {
+            let directory_path = root_temp_dir.path().join(directory);
+            fs::create_dir_all(&directory_path).expect("Failed to create test directory");
+            create_temp_file(&directory_path, "lib.rs", content);
+        }
+        create_temp_file(
+            &root_temp_dir.path().join("src"),
+            "generated.rs",
+            &format!("// @generated by build.rs{content}"),
+        );
+        create_temp_file(
+            root_temp_dir.path(),
+            ".gitattributes",
+            "proto/** linguist-generated",
+        );
+
+        let splitter = CodeFileSplitter::from(root_temp_dir.path().to_str().unwrap().to_string());
+        let mut classifications: Vec<_> = splitter
+            .map(|x| {
+                let path = PathBuf::from(x.file_path);
+                let relative_path = path.strip_prefix(root_temp_dir.path()).unwrap();
+                (
+                    relative_path.to_string_lossy().to_string(),
+                    x.classification,
+                )
+            })
+            .collect();
+        classifications.dedup();
+
+        assert_eq!(
+            classifications,
+            vec![
+                ("proto/lib.rs".to_string(), Classification::Generated),
+                ("src/generated.rs".to_string(), Classification::Generated),
+                ("src/lib.rs".to_string(), Classification::Source),
+                ("vendor/serde/lib.rs".to_string(), Classification::Vendored),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_and_split_git() {
+        let root_temp_dir = tempfile::tempdir().expect("Failed to create root temp directory");
+        let repository_path = root_temp_dir.path().join("repository");
+        let repository = git2::Repository::init(&repository_path).unwrap();
+        repository
+            .remote("origin", "https://example.com/org/repo.git")
+            .unwrap();
+        let first_commit = commit_files(
+            &repository,
+            &[(
+                "src/lib.rs",
+                r#"
+            fn first() {
+                println!("First");
+            }
+            "#,
+            )],
+            "First commit",
+        );
+        repository
+            .branch(
+                "release",
+                &repository.find_commit(first_commit).unwrap(),
+                false,
+            )
+            .unwrap();
+        commit_files(
+            &repository,
+            &[(
+                "src/lib.rs",
+                r#"
+            fn second() {
+                println!("Second");
+            }
+            "#,
+            )],
+            "Second commit",
+        );
+        // Files only in the working directory must not be picked up.
+        create_temp_file(
+            &repository_path.join("src"),
+            "untracked.rs",
+            "fn untracked() {}",
+        );
+
+        let output_file_uri = root_temp_dir.path().join("output_file.parquet");
+        let report = find_and_split_git(
+            repository_path.to_str().unwrap().to_string(),
+            "release".to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &SplitOptions::default(),
+        )
+        .expect("Failed to split repository");
+        assert_eq!(report.files_split, 1);
+
+        let dataframe = LazyFrame::scan_parquet(output_file_uri, Default::default())
+            .unwrap()
+            .collect()
+            .unwrap();
+        assert!(!dataframe.is_empty());
+        let column_values = |name: &str| -> Vec<String> {
+            dataframe
+                .column(name)
+                .unwrap()
+                .str()
+                .unwrap()
+                .iter()
+                .map(|x| x.unwrap().to_string())
+                .collect()
+        };
+        assert!(column_values("file_path").iter().all(|x| x == "src/lib.rs"));
+        assert!(
+            column_values("text")
+                .iter()
+                .any(|x| x.contains("fn first()"))
+        );
+        assert!(
+            column_values("commit")
+                .iter()
+                .all(|x| *x == first_commit.to_string())
+        );
+        assert!(
+            column_values("repository")
+                .iter()
+                .all(|x| x == "https://example.com/org/repo.git")
+        );
+        assert!(column_values("branch").iter().all(|x| x == "release"));
+    }
+
+    #[test]
+    fn test_find_and_split_blame() {
+        let root_temp_dir = tempfile::tempdir().expect("Failed to create root temp directory");
+        let repository_path = root_temp_dir.path().join("repository");
+        let repository = git2::Repository::init(&repository_path).unwrap();
+        let content = |name: &str| {
+            format!(
+                r#"
+            fn {name}() {{
+                println!("{name}");
+            }}
+            "#
+            )
+        };
+        let first_commit = commit_files(
+            &repository,
+            &[("src/first.rs", content("first").as_str())],
+            "First commit",
+        );
+        let second_commit = commit_files(
+            &repository,
+            &[("src/second.rs", content("second").as_str())],
+            "Second commit",
+        );
+        create_temp_file(
+            &repository_path.join("src"),
+            "untracked.rs",
+            &content("untracked"),
+        );
+
+        let options = SplitOptions {
+            blame: true,
+            ..Default::default()
+        };
+        let read_history = |output_file_uri: &Path| {
+            let dataframe = LazyFrame::scan_parquet(output_file_uri, Default::default())
+                .unwrap()
+                .collect()
+                .unwrap();
+            let mut history: Vec<_> = dataframe
+                .column("file_name")
+                .unwrap()
+                .str()
+                .unwrap()
+                .iter()
+                .zip(
+                    dataframe
+                        .column("last_commit")
+                        .unwrap()
+                        .str()
+                        .unwrap()
+                        .iter(),
+                )
+                .zip(
+                    dataframe
+                        .column("last_modified")
+                        .unwrap()
+                        .i64()
+                        .unwrap()
+                        .iter(),
+                )
+                .map(|((x, y), z)| (x.unwrap().to_string(), y.map(str::to_string), z))
+                .collect();
+            history.dedup();
+            history
+        };
+
+        let output_file_uri = root_temp_dir.path().join("directory.parquet");
+        find_and_split_with_options(
+            repository_path.to_str().unwrap().to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &options,
+        )
+        .expect("Failed to split directory");
+        assert_eq!(
+            read_history(&output_file_uri),
+            vec![
+                (
+                    "first.rs".to_string(),
+                    Some(first_commit.to_string()),
+                    Some(1_700_000_000)
+                ),
+                (
+                    "second.rs".to_string(),
+                    Some(second_commit.to_string()),
+                    Some(1_700_000_060)
+                ),
+                ("untracked.rs".to_string(), None, None),
+            ]
+        );
+
+        let output_file_uri = root_temp_dir.path().join("git.parquet");
+        find_and_split_git(
+            repository_path.to_str().unwrap().to_string(),
+            first_commit.to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &options,
+        )
+        .expect("Failed to split repository");
+        assert_eq!(
+            read_history(&output_file_uri),
+            vec![(
+                "first.rs".to_string(),
+                Some(first_commit.to_string()),
+                Some(1_700_000_000)
+            )]
+        );
+    }
+
+    #[test]
+    fn test_find_and_split_archive() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let nested_zip = create_zip(&[(
+            "nested/lib.rs",
+            b"fn nested() {\n    println!(\"Nested\");\n}\n",
+        )]);
+        let archive = create_tar_gz(&[
+            ("drop/main.py", b"def main():\n    print('main')\n"),
+            ("drop/inner.zip", &nested_zip),
+            ("drop/README.md", b"# Readme\n"),
+        ]);
+        let archive_path = temp_dir.path().join("drop.tar.gz");
+        fs::write(&archive_path, &archive).unwrap();
+
+        let output_file_uri = temp_dir.path().join("output_file.parquet");
+        let report = find_and_split_archive(
+            archive_path.to_str().unwrap().to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &SplitOptions::default(),
+        )
+        .expect("Failed to split archive");
+        assert_eq!(report.files_split, 2);
+        assert_eq!(report.skipped.len(), 1);
+
+        let dataframe = LazyFrame::scan_parquet(&output_file_uri, Default::default())
+            .unwrap()
+            .collect()
+            .unwrap();
+        let mut file_paths: Vec<String> = dataframe
+            .column("file_path")
+            .unwrap()
+            .str()
+            .unwrap()
+            .iter()
+            .map(|x| x.unwrap().to_string())
+            .collect();
+        file_paths.dedup();
+        let archive_path = archive_path.to_str().unwrap();
+        assert_eq!(
+            file_paths,
+            vec![
+                format!("{archive_path}!/drop/main.py"),
+                format!("{archive_path}!/drop/inner.zip!/nested/lib.rs"),
+            ]
+        );
+
+        // Archives found while walking a directory are only expanded on request.
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        fs::write(input_dir.join("drop.tar.gz"), &archive).unwrap();
+        let report = find_and_split_with_options(
+            input_dir.to_str().unwrap().to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &SplitOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.files_split, 0);
+        let report = find_and_split_with_options(
+            input_dir.to_str().unwrap().to_string(),
+            output_file_uri.to_str().unwrap().to_string(),
+            &SplitOptions {
+                expand_archives: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(report.files_split, 2);
+    }
 }
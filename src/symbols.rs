@@ -0,0 +1,231 @@
+use crate::languages::Language;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Node, Parser};
+
+/// Kind of definition a chunk holds, so that searches can be limited to, say, functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolKind {
+    Function,
+    /// A function defined in a class, an impl block, a trait or an interface.
+    Method,
+    Class,
+    Struct,
+    Enum,
+    Trait,
+    Interface,
+    Impl,
+    Module,
+    Constant,
+    /// A type alias.
+    Type,
+}
+
+impl SymbolKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Method => "method",
+            SymbolKind::Class => "class",
+            SymbolKind::Struct => "struct",
+            SymbolKind::Enum => "enum",
+            SymbolKind::Trait => "trait",
+            SymbolKind::Interface => "interface",
+            SymbolKind::Impl => "impl",
+            SymbolKind::Module => "module",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Type => "type",
+        }
+    }
+
+    /// Whether functions defined directly in a symbol of this kind are methods.
+    fn has_methods(&self) -> bool {
+        matches!(
+            self,
+            SymbolKind::Class
+                | SymbolKind::Struct
+                | SymbolKind::Trait
+                | SymbolKind::Interface
+                | SymbolKind::Impl
+        )
+    }
+}
+
+/// A definition in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Symbol {
+    pub kind: SymbolKind,
+    /// Name of the definition, or the implemented type for impl blocks.
+    pub name: Option<String>,
+    /// First row of the definition, counted from 0.
+    pub start_row: usize,
+    /// Last row of the definition, counted from 0.
+    pub end_row: usize,
+}
+
+/// Finds the definitions in `text`, outer definitions before the ones nested in them.
+///
+/// Text that fails to parse has no definitions.
+pub(crate) fn find_symbols(language: &Language, text: &str) -> Vec<Symbol> {
+    let mut parser = Parser::new();
+    if parser.set_language(&language.grammar).is_err() {
+        return vec![];
+    }
+    let Some(tree) = parser.parse(text, None) else {
+        return vec![];
+    };
+
+    let mut symbols = Vec::new();
+    // Walked with an explicit stack, as deeply nested expressions would overflow a recursive walk.
+    let mut pending: Vec<(Node, bool)> = vec![(tree.root_node(), false)];
+    while let Some((node, in_container)) = pending.pop() {
+        let mut children_in_container = in_container;
+        if let Some(kind) = language.symbol_kind(node.kind()) {
+            let kind = match kind {
+                SymbolKind::Function if in_container => SymbolKind::Method,
+                _ => kind,
+            };
+            let name = node
+                .child_by_field_name("name")
+                .or_else(|| node.child_by_field_name("type"))
+                .and_then(|x| x.utf8_text(text.as_bytes()).ok())
+                .map(str::to_string);
+            symbols.push(Symbol {
+                kind,
+                name,
+                start_row: node.start_position().row,
+                end_row: node.end_position().row,
+            });
+            children_in_container = kind.has_methods();
+        }
+
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.named_children(&mut cursor).collect();
+        // Pushed in reverse so that children are visited in source order.
+        pending.extend(
+            children
+                .into_iter()
+                .rev()
+                .map(|x| (x, children_in_container)),
+        );
+    }
+    symbols
+}
+
+/// Returns the definition a chunk spanning rows `start_row..end_row` belongs to: the first one
+/// starting in the chunk, or else the innermost one enclosing it.
+pub(crate) fn chunk_symbol(
+    symbols: &[Symbol],
+    start_row: usize,
+    end_row: usize,
+) -> Option<&Symbol> {
+    symbols
+        .iter()
+        .find(|x| x.start_row >= start_row && x.start_row < end_row)
+        .or_else(|| {
+            symbols
+                .iter()
+                .rev()
+                .find(|x| x.start_row < start_row && x.end_row + 1 >= end_row)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages::get_language_for_path;
+    use std::path::Path;
+
+    fn symbols(file_name: &str, text: &str) -> Vec<(SymbolKind, Option<String>)> {
+        let language = get_language_for_path(Path::new(file_name)).unwrap();
+        find_symbols(language, text)
+            .into_iter()
+            .map(|x| (x.kind, x.name))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_symbols() {
+        assert_eq!(
+            symbols(
+                "lib.rs",
+                r#"
+struct Point { x: f32 }
+
+impl Point {
+    fn norm(&self) -> f32 {
+        fn square(x: f32) -> f32 { x * x }
+        square(self.x)
+    }
+}
+
+const ORIGIN: Point = Point { x: 0.0 };
+"#
+            ),
+            vec![
+                (SymbolKind::Struct, Some("Point".to_string())),
+                (SymbolKind::Impl, Some("Point".to_string())),
+                (SymbolKind::Method, Some("norm".to_string())),
+                (SymbolKind::Function, Some("square".to_string())),
+                (SymbolKind::Constant, Some("ORIGIN".to_string())),
+            ]
+        );
+        assert_eq!(
+            symbols(
+                "shapes.py",
+                r#"
+class Circle:
+    def area(self):
+        return 3.14 * self.r ** 2
+
+def unit():
+    return Circle()
+"#
+            ),
+            vec![
+                (SymbolKind::Class, Some("Circle".to_string())),
+                (SymbolKind::Method, Some("area".to_string())),
+                (SymbolKind::Function, Some("unit".to_string())),
+            ]
+        );
+        assert_eq!(
+            symbols(
+                "shape.ts",
+                "interface Shape { area(): number }\nfunction describe(s: Shape) {}\n"
+            ),
+            vec![
+                (SymbolKind::Interface, Some("Shape".to_string())),
+                (SymbolKind::Function, Some("describe".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk_symbol() {
+        let symbol = |kind, start_row, end_row| Symbol {
+            kind,
+            name: None,
+            start_row,
+            end_row,
+        };
+        let symbols = vec![
+            symbol(SymbolKind::Impl, 0, 20),
+            symbol(SymbolKind::Method, 2, 8),
+            symbol(SymbolKind::Method, 10, 19),
+        ];
+
+        // The first definition starting in the chunk wins over enclosing ones.
+        assert_eq!(
+            chunk_symbol(&symbols, 0, 9).map(|x| x.kind),
+            Some(SymbolKind::Impl)
+        );
+        assert_eq!(chunk_symbol(&symbols, 9, 15).map(|x| x.start_row), Some(10));
+        // A chunk in the middle of a definition belongs to the innermost one.
+        assert_eq!(
+            chunk_symbol(&symbols, 12, 16).map(|x| x.start_row),
+            Some(10)
+        );
+        assert_eq!(chunk_symbol(&symbols, 30, 40), None);
+    }
+}
@@ -0,0 +1,617 @@
+use crate::Result;
+use crate::filter::SearchFilter;
+use crate::index::{DEFAULT_TABLE_NAME, EMBEDDING_COLUMN, open_existing_table};
+use crate::locations::{ChunkLocation, LOCATION_COLUMNS, read_locations};
+use crate::search::read_embeddings;
+use lancedb::arrow::IntoPolars;
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Number of topics the chunks are clustered into by default.
+pub const DEFAULT_TOPIC_COUNT: usize = 10;
+
+/// Number of k-means iterations run at most by default.
+pub const DEFAULT_MAX_ITERATIONS: usize = 100;
+
+/// Seed of the random k-means initialization by default.
+pub const DEFAULT_SEED: u64 = 0;
+
+/// Number of symbols representing a topic.
+const REPRESENTATIVE_SYMBOLS: usize = 5;
+
+/// Number of path prefixes reported for a topic.
+const FREQUENT_PREFIXES: usize = 3;
+
+/// Number of power iterations run at most for every projection axis.
+const PROJECTION_ITERATIONS: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct TopicOptions {
+    /// Table holding the chunks to cluster.
+    pub table_name: String,
+    /// Number of topics to cluster the chunks into. Fewer topics are found when there are fewer
+    /// distinct embeddings.
+    pub topics: usize,
+    /// Number of k-means iterations run at most; clustering stops earlier once no chunk changes
+    /// topic.
+    pub max_iterations: usize,
+    /// Seed of the k-means++ initialization, so that runs over the same chunks agree.
+    pub seed: u64,
+    /// Chunks to cluster.
+    pub filter: SearchFilter,
+}
+
+impl Default for TopicOptions {
+    fn default() -> Self {
+        TopicOptions {
+            table_name: DEFAULT_TABLE_NAME.to_string(),
+            topics: DEFAULT_TOPIC_COUNT,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            seed: DEFAULT_SEED,
+            filter: SearchFilter::default(),
+        }
+    }
+}
+
+/// A directory prefix shared by chunks of a topic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PathPrefix {
+    /// Directory path relative to the repository of the chunks, ending with a `/`.
+    pub prefix: String,
+    /// Number of chunks of the topic under the prefix.
+    pub chunks: u64,
+}
+
+/// Chunks whose embeddings cluster together.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Topic {
+    /// Index of the topic, with larger topics first.
+    pub id: usize,
+    /// Short description built from the most frequent prefix and the representative symbols.
+    pub label: String,
+    /// Number of chunks in the topic.
+    pub chunks: u64,
+    /// Names of the definitions in the chunks closest to the centre of the topic, closest first.
+    pub symbols: Vec<String>,
+    /// Directory prefixes holding the most chunks of the topic, most chunks first.
+    pub path_prefixes: Vec<PathPrefix>,
+    /// Mean cosine similarity between the chunks and the centre of the topic.
+    pub cohesion: f32,
+    /// Projection of the centre of the topic, on the same axes as the chunks.
+    pub x: f32,
+    pub y: f32,
+}
+
+/// A chunk placed on the plane spanned by the two principal components of the embeddings.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProjectedChunk {
+    #[serde(flatten)]
+    pub location: ChunkLocation,
+    pub symbol_name: Option<String>,
+    /// Index of the topic of the chunk.
+    pub topic: usize,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Topics found among the chunks of a table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopicReport {
+    /// Number of chunks clustered; chunks without an embedding are left out.
+    pub chunks_clustered: u64,
+    /// Topics, largest first.
+    pub topics: Vec<Topic>,
+    /// Every clustered chunk, for plotting.
+    pub projection: Vec<ProjectedChunk>,
+}
+
+/// Clusters the embeddings of the chunks in the `options.table_name` table of the database at
+/// `db_file_uri` into topics, with spherical k-means, and projects them onto their two principal
+/// components.
+pub async fn find_topics(db_file_uri: &str, options: &TopicOptions) -> Result<TopicReport> {
+    let table = open_existing_table(db_file_uri, &options.table_name).await?;
+    let mut columns = LOCATION_COLUMNS.to_vec();
+    columns.extend(["symbol_name", EMBEDDING_COLUMN]);
+    let mut query = table.query().select(Select::columns(&columns));
+    if let Some(predicate) = options.filter.to_sql()? {
+        query = query.only_if(predicate);
+    }
+    let dataframe = query.execute().await?.into_polars().await?;
+    if dataframe.height() == 0 {
+        return Ok(build_report(vec![], vec![], options));
+    }
+
+    let symbol_names = dataframe.column("symbol_name")?.str()?;
+    let mut chunks = Vec::new();
+    let mut embeddings = Vec::new();
+    for (i, (location, embedding)) in read_locations(&dataframe)?
+        .into_iter()
+        .zip(read_embeddings(&dataframe)?)
+        .enumerate()
+    {
+        let Some(embedding) = embedding.and_then(normalize) else {
+            continue;
+        };
+        chunks.push((location, symbol_names.get(i).map(str::to_string)));
+        embeddings.push(embedding);
+    }
+    Ok(build_report(chunks, embeddings, options))
+}
+
+/// Builds the report for `chunks` and their unit-length `embeddings`.
+fn build_report(
+    chunks: Vec<(ChunkLocation, Option<String>)>,
+    embeddings: Vec<Vec<f32>>,
+    options: &TopicOptions,
+) -> TopicReport {
+    let (centroids, assignments) = cluster(
+        &embeddings,
+        options.topics,
+        options.max_iterations,
+        options.seed,
+    );
+
+    let mut members: Vec<Vec<usize>> = vec![vec![]; centroids.len()];
+    for (i, &topic) in assignments.iter().enumerate() {
+        members[topic].push(i);
+    }
+    // Topics are renumbered by decreasing size; the sort is stable, so ties keep their order.
+    let mut order: Vec<usize> = (0..centroids.len()).collect();
+    order.sort_by(|x, y| members[*y].len().cmp(&members[*x].len()));
+    let mut topic_ids = vec![0; centroids.len()];
+    for (id, &topic) in order.iter().enumerate() {
+        topic_ids[topic] = id;
+    }
+
+    let axes = principal_axes(&embeddings);
+    let project = |vector: &[f32]| match &axes {
+        Some((mean, first, second)) => {
+            let centered: Vec<f32> = vector.iter().zip(mean).map(|(x, m)| x - m).collect();
+            (dot(&centered, first), dot(&centered, second))
+        }
+        None => (0.0, 0.0),
+    };
+
+    let topics = order
+        .iter()
+        .enumerate()
+        .map(|(id, &topic)| {
+            let centroid = &centroids[topic];
+            let mut similarities: Vec<(usize, f32)> = members[topic]
+                .iter()
+                .map(|&i| (i, dot(&embeddings[i], centroid)))
+                .collect();
+            similarities.sort_by(|x, y| y.1.total_cmp(&x.1));
+
+            let mut symbols: Vec<String> = Vec::new();
+            for name in similarities
+                .iter()
+                .filter_map(|(i, _)| chunks[*i].1.as_ref())
+            {
+                if symbols.len() == REPRESENTATIVE_SYMBOLS {
+                    break;
+                }
+                if !symbols.contains(name) {
+                    symbols.push(name.clone());
+                }
+            }
+            let path_prefixes =
+                frequent_prefixes(members[topic].iter().map(|&i| chunks[i].0.relative_path()));
+            let (x, y) = project(centroid);
+            Topic {
+                id,
+                label: label(&path_prefixes, &symbols),
+                chunks: members[topic].len() as u64,
+                symbols,
+                path_prefixes,
+                cohesion: similarities.iter().map(|x| x.1).sum::<f32>()
+                    / similarities.len().max(1) as f32,
+                x,
+                y,
+            }
+        })
+        .collect();
+
+    let projection = chunks
+        .into_iter()
+        .zip(&embeddings)
+        .zip(&assignments)
+        .map(|(((location, symbol_name), embedding), &topic)| {
+            let (x, y) = project(embedding);
+            ProjectedChunk {
+                location,
+                symbol_name,
+                topic: topic_ids[topic],
+                x,
+                y,
+            }
+        })
+        .collect();
+
+    TopicReport {
+        chunks_clustered: embeddings.len() as u64,
+        topics,
+        projection,
+    }
+}
+
+fn label(path_prefixes: &[PathPrefix], symbols: &[String]) -> String {
+    let prefix = path_prefixes.first().map(|x| x.prefix.as_str());
+    let symbols = symbols
+        .iter()
+        .take(3)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    match (prefix, symbols.is_empty()) {
+        (Some(prefix), false) => format!("{prefix}: {symbols}"),
+        (Some(prefix), true) => prefix.to_string(),
+        (None, _) => symbols,
+    }
+}
+
+/// Returns the directory prefixes holding the most of `file_paths`, leaving out prefixes holding
+/// no more paths than a longer prefix already picked. Paths should be relative to their
+/// repository, or the repository root would be the top prefix of every topic.
+fn frequent_prefixes<'a>(file_paths: impl Iterator<Item = &'a str>) -> Vec<PathPrefix> {
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    for file_path in file_paths {
+        for (i, _) in file_path.match_indices('/') {
+            *counts.entry(&file_path[..=i]).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<(&str, u64)> = counts.into_iter().collect();
+    // Deeper prefixes first among equally frequent ones, as they say more about the topic.
+    counts.sort_by(|x, y| {
+        y.1.cmp(&x.1)
+            .then(y.0.len().cmp(&x.0.len()))
+            .then(x.0.cmp(y.0))
+    });
+
+    let mut prefixes: Vec<PathPrefix> = Vec::new();
+    for (prefix, chunks) in counts {
+        if prefixes
+            .iter()
+            .any(|x| x.chunks == chunks && x.prefix.starts_with(prefix))
+        {
+            continue;
+        }
+        prefixes.push(PathPrefix {
+            prefix: prefix.to_string(),
+            chunks,
+        });
+        if prefixes.len() == FREQUENT_PREFIXES {
+            break;
+        }
+    }
+    prefixes
+}
+
+/// Clusters unit-length `vectors` into at most `k` clusters with spherical k-means, seeded with
+/// k-means++. Returns the unit-length centroids and the cluster of every vector.
+fn cluster(
+    vectors: &[Vec<f32>],
+    k: usize,
+    max_iterations: usize,
+    seed: u64,
+) -> (Vec<Vec<f32>>, Vec<usize>) {
+    if vectors.is_empty() || k == 0 {
+        return (vec![], vec![]);
+    }
+
+    let mut random = SplitMix64(seed);
+    let mut centroids = vec![vectors[random.below(vectors.len())].clone()];
+    // Cosine distance from every vector to its nearest centroid.
+    let mut distances: Vec<f32> = vectors
+        .iter()
+        .map(|x| (1.0 - dot(x, &centroids[0])).max(0.0))
+        .collect();
+    while centroids.len() < k {
+        let total: f32 = distances.iter().map(|x| x * x).sum();
+        if total <= f32::EPSILON {
+            // Every vector coincides with a centroid.
+            break;
+        }
+        let mut target = random.unit() * total;
+        let mut picked = vectors.len() - 1;
+        for (i, distance) in distances.iter().enumerate() {
+            target -= distance * distance;
+            if target <= 0.0 && *distance > 0.0 {
+                picked = i;
+                break;
+            }
+        }
+        let centroid = vectors[picked].clone();
+        for (distance, vector) in distances.iter_mut().zip(vectors) {
+            *distance = distance.min((1.0 - dot(vector, &centroid)).max(0.0));
+        }
+        centroids.push(centroid);
+    }
+
+    let mut assignments = vec![usize::MAX; vectors.len()];
+    for _ in 0..max_iterations.max(1) {
+        let mut changed = false;
+        for (assignment, vector) in assignments.iter_mut().zip(vectors) {
+            let nearest = nearest_centroid(vector, &centroids);
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![vec![0.0f32; vectors[0].len()]; centroids.len()];
+        for (&assignment, vector) in assignments.iter().zip(vectors) {
+            for (sum, x) in sums[assignment].iter_mut().zip(vector) {
+                *sum += x;
+            }
+        }
+        for (centroid, sum) in centroids.iter_mut().zip(sums) {
+            // A cluster left empty keeps its centroid.
+            if let Some(sum) = normalize(sum) {
+                *centroid = sum;
+            }
+        }
+    }
+
+    // Clusters left empty are dropped.
+    let mut sizes = vec![0; centroids.len()];
+    for &assignment in &assignments {
+        sizes[assignment] += 1;
+    }
+    let mut new_indices = vec![0; centroids.len()];
+    let mut kept = Vec::new();
+    for (i, centroid) in centroids.into_iter().enumerate() {
+        if sizes[i] > 0 {
+            new_indices[i] = kept.len();
+            kept.push(centroid);
+        }
+    }
+    let assignments = assignments.into_iter().map(|x| new_indices[x]).collect();
+    (kept, assignments)
+}
+
+fn nearest_centroid(vector: &[f32], centroids: &[Vec<f32>]) -> usize {
+    centroids
+        .iter()
+        .map(|x| dot(vector, x))
+        .enumerate()
+        .max_by(|x, y| x.1.total_cmp(&y.1).then(y.0.cmp(&x.0)))
+        .map_or(0, |x| x.0)
+}
+
+/// Returns the mean of `vectors` and their two principal axes, found with power iteration, or
+/// `None` if there are no vectors.
+fn principal_axes(vectors: &[Vec<f32>]) -> Option<(Vec<f32>, Vec<f32>, Vec<f32>)> {
+    let dimension = vectors.first()?.len();
+    let mut mean = vec![0.0f32; dimension];
+    for vector in vectors {
+        for (m, x) in mean.iter_mut().zip(vector) {
+            *m += x;
+        }
+    }
+    for m in &mut mean {
+        *m /= vectors.len() as f32;
+    }
+    let centered: Vec<Vec<f32>> = vectors
+        .iter()
+        .map(|x| x.iter().zip(&mean).map(|(a, m)| a - m).collect())
+        .collect();
+
+    let first = principal_axis(&centered, None);
+    let second = principal_axis(&centered, Some(&first));
+    Some((mean, first, second))
+}
+
+/// Returns the unit-length direction of greatest variance of the `centered` vectors, orthogonal
+/// to `orthogonal_to` if given. The zero vector is returned when there is no variance left.
+fn principal_axis(centered: &[Vec<f32>], orthogonal_to: Option<&[f32]>) -> Vec<f32> {
+    let dimension = centered[0].len();
+    // Directions along which the vectors vary less than this are rounding noise.
+    let min_variance = 1e-5 * centered.iter().map(|x| dot(x, x)).sum::<f32>();
+    // A fixed start keeps projections reproducible.
+    let Some(mut axis) = normalize((0..dimension).map(|i| 1.0 / (i + 1) as f32).collect()) else {
+        return vec![];
+    };
+    for _ in 0..PROJECTION_ITERATIONS {
+        // Multiplies by the covariance matrix, up to a factor, without building it.
+        let mut next = vec![0.0f32; dimension];
+        for vector in centered {
+            let weight = dot(vector, &axis);
+            for (n, x) in next.iter_mut().zip(vector) {
+                *n += weight * x;
+            }
+        }
+        if let Some(orthogonal_to) = orthogonal_to {
+            let overlap = dot(&next, orthogonal_to);
+            for (n, x) in next.iter_mut().zip(orthogonal_to) {
+                *n -= overlap * x;
+            }
+        }
+        if dot(&next, &next).sqrt() <= min_variance {
+            return vec![0.0; dimension];
+        }
+        let Some(next) = normalize(next) else {
+            return vec![0.0; dimension];
+        };
+        let converged = dot(&next, &axis).abs() > 1.0 - 1e-6;
+        axis = next;
+        if converged {
+            break;
+        }
+    }
+    axis
+}
+
+fn dot(x: &[f32], y: &[f32]) -> f32 {
+    x.iter().zip(y).map(|(a, b)| a * b).sum()
+}
+
+/// Scales `vector` to unit length, or returns `None` for the zero vector.
+fn normalize(mut vector: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = dot(&vector, &vector).sqrt();
+    if norm <= f32::EPSILON {
+        return None;
+    }
+    for x in &mut vector {
+        *x /= norm;
+    }
+    Some(vector)
+}
+
+/// The SplitMix64 generator, which is enough to seed k-means reproducibly.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::tests::index_directory;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_cluster() {
+        let vectors: Vec<Vec<f32>> = [
+            [1.0, 0.1, 0.0],
+            [0.0, 0.1, 1.0],
+            [1.0, 0.0, 0.1],
+            [0.1, 0.0, 1.0],
+            [1.0, 0.1, 0.1],
+        ]
+        .into_iter()
+        .map(|x| normalize(x.to_vec()).unwrap())
+        .collect();
+
+        let (centroids, assignments) = cluster(&vectors, 2, 10, 7);
+        assert_eq!(centroids.len(), 2);
+        assert_eq!(assignments[0], assignments[2]);
+        assert_eq!(assignments[0], assignments[4]);
+        assert_eq!(assignments[1], assignments[3]);
+        assert_ne!(assignments[0], assignments[1]);
+
+        // Identical vectors make a single cluster, however many are asked for.
+        let same: Vec<Vec<f32>> = std::iter::repeat_n(vectors[0].clone(), 3).collect();
+        let (centroids, assignments) = cluster(&same, 3, 10, 7);
+        assert_eq!(centroids.len(), 1);
+        assert_eq!(assignments, vec![0, 0, 0]);
+        assert_eq!(cluster(&[], 3, 10, 7), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_frequent_prefixes() {
+        let prefixes = frequent_prefixes(
+            [
+                "src/index/table.rs",
+                "src/index/write.rs",
+                "src/index/write.rs",
+                "src/search.rs",
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            prefixes,
+            vec![
+                PathPrefix {
+                    prefix: "src/".to_string(),
+                    chunks: 4
+                },
+                PathPrefix {
+                    prefix: "src/index/".to_string(),
+                    chunks: 3
+                },
+            ]
+        );
+        assert_eq!(
+            label(&prefixes, &["open_table".to_string()]),
+            "src/: open_table"
+        );
+    }
+
+    #[test]
+    fn test_principal_axes() {
+        // Points along a line project onto the first axis only.
+        let vectors = vec![vec![0.0, 0.0], vec![1.0, 1.0], vec![2.0, 2.0]];
+        let (mean, first, second) = principal_axes(&vectors).unwrap();
+        assert_eq!(mean, vec![1.0, 1.0]);
+        assert!((first[0].abs() - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+        assert!((first[0] - first[1]).abs() < 1e-4);
+        assert_eq!(second, vec![0.0, 0.0]);
+        assert_eq!(principal_axes(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_find_topics() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(root.join("db"))?;
+        fs::create_dir_all(root.join("ui"))?;
+        fs::write(
+            root.join("db/users.py"),
+            "def insert_user(connection, user):\n    connection.execute(\"INSERT INTO users VALUES (?)\", user)\n",
+        )?;
+        fs::write(
+            root.join("db/orders.py"),
+            "def delete_order(connection, order):\n    connection.execute(\"DELETE FROM orders WHERE id = ?\", order)\n",
+        )?;
+        fs::write(
+            root.join("ui/button.js"),
+            "function renderButton(label) {\n    return `<button class=\"primary\">${label}</button>`;\n}\n",
+        )?;
+
+        let db_path_str = index_directory(temp_dir.path(), &root).await?;
+
+        let options = TopicOptions {
+            topics: 2,
+            ..Default::default()
+        };
+        let report = find_topics(&db_path_str, &options).await?;
+        assert_eq!(report.chunks_clustered, 3);
+        assert_eq!(report.projection.len(), 3);
+        assert_eq!(report.topics.len(), 2);
+        assert_eq!(
+            report.topics.iter().map(|x| x.chunks).sum::<u64>(),
+            3,
+            "every chunk belongs to one topic"
+        );
+        assert!(report.topics[0].chunks >= report.topics[1].chunks);
+        assert!(
+            report
+                .topics
+                .iter()
+                .all(|x| !x.symbols.is_empty() && !x.path_prefixes.is_empty())
+        );
+
+        let options = TopicOptions {
+            filter: SearchFilter::default().with_language("python"),
+            ..Default::default()
+        };
+        let report = find_topics(&db_path_str, &options).await?;
+        assert_eq!(report.chunks_clustered, 2);
+        assert!(report.topics.len() <= 2);
+        assert_eq!(report.topics[0].path_prefixes[0].prefix, "db/");
+        assert!(report.topics[0].label.starts_with("db/: "));
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+use crate::highlight::{Highlight, highlight_lines};
+use crate::languages::get_language_for_path;
+use crate::search::{self, SearchOptions, SearchResult};
+use crate::{Result, embed};
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+/// Number of lines shown around a chunk in the preview by default.
+pub const DEFAULT_CONTEXT_LINES: usize = 5;
+
+/// Time the query must go without changes before it is searched.
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Time to wait for a key before checking whether the query is due to be searched.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Number of columns tabs are expanded to in the preview.
+const TAB_WIDTH: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct TuiOptions {
+    /// Options for every search; the query comes from the query box.
+    pub search: SearchOptions,
+    /// Number of lines shown before and after the chunk in the preview.
+    pub context_lines: usize,
+}
+
+impl Default for TuiOptions {
+    fn default() -> Self {
+        TuiOptions {
+            search: SearchOptions::default(),
+            context_lines: DEFAULT_CONTEXT_LINES,
+        }
+    }
+}
+
+struct App {
+    query: String,
+    results: Vec<SearchResult>,
+    list_state: ListState,
+    /// Preview lines, with the ID of the result they show.
+    preview: Option<(String, Vec<Line<'static>>)>,
+    status: String,
+    /// When the query last changed, if it was not searched since.
+    changed_at: Option<Instant>,
+}
+
+impl App {
+    fn selected(&self) -> Option<&SearchResult> {
+        self.results.get(self.list_state.selected()?)
+    }
+
+    fn edit_query(&mut self, edit: impl FnOnce(&mut String)) {
+        edit(&mut self.query);
+        self.changed_at = Some(Instant::now());
+    }
+
+    async fn search(&mut self, db_file_uri: &str, options: &SearchOptions) {
+        self.changed_at = None;
+        if self.query.trim().is_empty() {
+            self.results.clear();
+            self.status.clear();
+        } else {
+            match search::search(db_file_uri, &self.query, options).await {
+                Ok(results) => {
+                    self.status = format!("{} results", results.len());
+                    self.results = results;
+                }
+                Err(e) => self.status = e.to_string(),
+            }
+        }
+        self.list_state
+            .select((!self.results.is_empty()).then_some(0));
+    }
+
+    fn update_preview(&mut self, context_lines: usize) {
+        let Some(result) = self.selected() else {
+            self.preview = None;
+            return;
+        };
+        if self.preview.as_ref().map(|x| &x.0) != Some(&result.id) {
+            self.preview = Some((result.id.clone(), preview_lines(result, context_lines)));
+        }
+    }
+}
+
+/// Runs an interactive search of the database at `db_file_uri` in the terminal, until Esc or
+/// Ctrl-C is pressed.
+///
+/// Results are searched as the query is typed, and the selected one is previewed with the lines
+/// around it, read from disk. Enter opens it in `$EDITOR` at its first line.
+pub async fn run(db_file_uri: &str, options: &TuiOptions) -> Result<()> {
+    // Loaded before taking over the terminal, so that the first search does not stall it.
+    embed::load_models(options.search.rerank.is_some())?;
+
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, db_file_uri, options).await;
+    ratatui::restore();
+    result
+}
+
+async fn run_app(
+    terminal: &mut DefaultTerminal,
+    db_file_uri: &str,
+    options: &TuiOptions,
+) -> Result<()> {
+    let mut app = App {
+        query: String::new(),
+        results: vec![],
+        list_state: ListState::default(),
+        preview: None,
+        status: String::new(),
+        changed_at: None,
+    };
+    loop {
+        app.update_preview(options.context_lines);
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        if app
+            .changed_at
+            .is_some_and(|x| x.elapsed() >= SEARCH_DEBOUNCE)
+        {
+            app.search(db_file_uri, &options.search).await;
+            continue;
+        }
+        if !event::poll(POLL_INTERVAL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if control => return Ok(()),
+            KeyCode::Up => app.list_state.select_previous(),
+            KeyCode::Char('p') if control => app.list_state.select_previous(),
+            KeyCode::Down => app.list_state.select_next(),
+            KeyCode::Char('n') if control => app.list_state.select_next(),
+            KeyCode::Enter => {
+                if let Some(result) = app.selected() {
+                    let (file_path, line) = (result.file_path.clone(), result.start_line + 1);
+                    if let Err(e) = open_in_editor(terminal, &file_path, line) {
+                        app.status = format!("Failed to open {file_path}: {e}");
+                    }
+                }
+            }
+            KeyCode::Backspace => app.edit_query(|x| {
+                x.pop();
+            }),
+            KeyCode::Char('u') if control => app.edit_query(String::clear),
+            KeyCode::Char(c) if !control => app.edit_query(|x| x.push(c)),
+            _ => {}
+        }
+        // The list may select past its end until it is rendered.
+        if let Some(selected) = app.list_state.selected() {
+            app.list_state
+                .select(Some(selected.min(app.results.len().saturating_sub(1))));
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [query_area, main_area, status_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list_area, preview_area] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .areas(main_area);
+
+    frame.render_widget(
+        Paragraph::new(app.query.as_str()).block(Block::bordered().title(" Query ")),
+        query_area,
+    );
+    frame.set_cursor_position((
+        query_area.x + 1 + app.query.chars().count() as u16,
+        query_area.y + 1,
+    ));
+
+    let items: Vec<ListItem> = app
+        .results
+        .iter()
+        .map(|x| {
+            let mut spans = vec![
+                Span::styled(
+                    format!("{:.3} ", x.rerank_score.unwrap_or(x.score)),
+                    Style::new().fg(Color::Yellow),
+                ),
+                Span::raw(format!("{}:{}", x.file_path, x.start_line + 1)),
+            ];
+            if let Some(symbol_name) = &x.symbol_name {
+                spans.push(Span::styled(
+                    format!(" {symbol_name}"),
+                    Style::new().fg(Color::Cyan),
+                ));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::bordered().title(" Results "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+        list_area,
+        &mut app.list_state,
+    );
+
+    let (title, lines) = match (app.selected(), &app.preview) {
+        (Some(result), Some((_, lines))) => (
+            format!(
+                " {}:{}-{} ",
+                result.file_path,
+                result.start_line + 1,
+                result.end_line.max(result.start_line + 1)
+            ),
+            lines.clone(),
+        ),
+        _ => (" Preview ".to_string(), vec![]),
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        preview_area,
+    );
+
+    let help = "↑/↓ select · Enter open in $EDITOR · Ctrl-U clear · Esc quit";
+    let status = if app.status.is_empty() {
+        help.to_string()
+    } else {
+        format!("{} · {help}", app.status)
+    };
+    frame.render_widget(
+        Paragraph::new(status).style(Style::new().fg(Color::DarkGray)),
+        status_area,
+    );
+}
+
+/// Returns the highlighted lines of `result` and the `context_lines` around it, numbered from 1,
+/// with the lines of the chunk marked. Files that cannot be read are previewed from the chunk
+/// text alone.
+fn preview_lines(result: &SearchResult, context_lines: usize) -> Vec<Line<'static>> {
+    let path = Path::new(&result.file_path);
+    let (text, first_row) = match fs::read_to_string(path) {
+        Ok(text) => (text, 0),
+        Err(_) => (result.text.clone(), result.start_line as usize),
+    };
+    let lines = highlight_lines(get_language_for_path(path), &text);
+
+    let start_row = result.start_line as usize;
+    let end_row = (result.end_line as usize).max(start_row + 1);
+    let shown_start = start_row.saturating_sub(context_lines).max(first_row);
+    let shown_end = (end_row + context_lines).min(first_row + lines.len());
+    (shown_start..shown_end)
+        .map(|row| {
+            let in_chunk = (start_row..end_row).contains(&row);
+            let gutter_style = if in_chunk {
+                Style::new().fg(Color::Yellow)
+            } else {
+                Style::new().fg(Color::DarkGray)
+            };
+            let mut spans = vec![Span::styled(
+                format!("{:>5} {} ", row + 1, if in_chunk { "│" } else { " " }),
+                gutter_style,
+            )];
+            spans.extend(lines[row - first_row].iter().map(|(highlight, text)| {
+                Span::styled(
+                    text.replace('\t', &" ".repeat(TAB_WIDTH)),
+                    highlight_style(*highlight),
+                )
+            }));
+            Line::from(spans)
+        })
+        .collect()
+}
+
+fn highlight_style(highlight: Option<Highlight>) -> Style {
+    let style = Style::new();
+    match highlight {
+        Some(Highlight::Comment) => style.fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+        Some(Highlight::String) => style.fg(Color::Green),
+        Some(Highlight::Number) => style.fg(Color::Magenta),
+        Some(Highlight::Keyword) => style.fg(Color::Blue).add_modifier(Modifier::BOLD),
+        Some(Highlight::Type) => style.fg(Color::Cyan),
+        Some(Highlight::Function) => style.fg(Color::Yellow),
+        None => style,
+    }
+}
+
+/// Hands the terminal over to `$EDITOR`, or `$VISUAL`, or `vi`, opened on `file_path` at `line`,
+/// counted from 1, until it exits.
+fn open_in_editor(terminal: &mut DefaultTerminal, file_path: &str, line: u64) -> Result<()> {
+    let editor = env::var("EDITOR")
+        .or_else(|_| env::var("VISUAL"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let (program, args) = editor_command(&editor, file_path, line);
+
+    ratatui::restore();
+    let status = Command::new(program).args(args).status();
+    *terminal = ratatui::init();
+    status?;
+    Ok(())
+}
+
+/// Splits the `editor` command, which may carry its own arguments, and adds the arguments that
+/// open `file_path` at `line` in it.
+fn editor_command(editor: &str, file_path: &str, line: u64) -> (String, Vec<String>) {
+    let mut words = editor.split_whitespace().map(str::to_string);
+    let program = words.next().unwrap_or_else(|| "vi".to_string());
+    let mut args: Vec<String> = words.collect();
+    let name = Path::new(&program)
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match name.as_str() {
+        "code" | "code-insiders" | "codium" | "cursor" => {
+            args.extend(["--goto".to_string(), format!("{file_path}:{line}")]);
+        }
+        "subl" | "zed" | "hx" => args.push(format!("{file_path}:{line}")),
+        // Most terminal editors, such as vi, Emacs, nano and micro, take `+LINE`.
+        _ => args.extend([format!("+{line}"), file_path.to_string()]),
+    }
+    (program, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editor_command() {
+        assert_eq!(
+            editor_command("nvim", "src/lib.rs", 12),
+            (
+                "nvim".to_string(),
+                vec!["+12".to_string(), "src/lib.rs".to_string()]
+            )
+        );
+        assert_eq!(
+            editor_command("/usr/bin/code --wait", "src/lib.rs", 12),
+            (
+                "/usr/bin/code".to_string(),
+                vec![
+                    "--wait".to_string(),
+                    "--goto".to_string(),
+                    "src/lib.rs:12".to_string()
+                ]
+            )
+        );
+        assert_eq!(editor_command("", "a.py", 1).0, "vi");
+    }
+
+    #[test]
+    fn test_preview_lines() -> anyhow::Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("lib.py");
+        let text: String = (1..=20).map(|i| format!("x{i} = {i}\n")).collect();
+        fs::write(&path, &text)?;
+        let result = SearchResult {
+            id: "id".to_string(),
+            file_path: path.to_string_lossy().to_string(),
+            file_name: "lib.py".to_string(),
+            start_line: 9,
+            end_line: 11,
+            text: "x10 = 10\nx11 = 11".to_string(),
+            language: "python".to_string(),
+            symbol_kind: None,
+            symbol_name: None,
+            repository: None,
+            branch: None,
+            last_author: None,
+            last_modified: None,
+            score: 1.0,
+            rerank_score: None,
+        };
+
+        let line_text =
+            |line: &Line| -> String { line.spans.iter().map(|x| &*x.content).collect() };
+        let lines = preview_lines(&result, 2);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(line_text(&lines[0]), "    8   x8 = 8");
+        assert_eq!(line_text(&lines[2]), "   10 │ x10 = 10");
+        assert_eq!(
+            lines[2].spans.last().unwrap().style.fg,
+            Some(Color::Magenta)
+        );
+
+        // Without the file, the chunk text alone is shown.
+        fs::remove_file(&path)?;
+        let lines = preview_lines(&result, 2);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(line_text(&lines[1]), "   11 │ x11 = 11");
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+use crate::Result;
+use crate::embed::create_embeddings_from_file;
+use crate::index::{IndexOptions, codebase_predicate, escape_like, quote_literal, replace_rows};
+use crate::languages::get_language_for_path;
+use crate::split::{SplitOptions, SplitReport, codebase_of, is_ignored, split_paths};
+use notify_debouncer_mini::new_debouncer;
+use notify_debouncer_mini::notify::RecursiveMode;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Time a path must go without changes before it is re-indexed.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Time a path must go without changes before it is re-indexed, so that a burst of saves
+    /// results in a single update.
+    pub debounce: Duration,
+    /// Options for splitting the changed files.
+    pub split: SplitOptions,
+    /// Options for indexing the changed files.
+    pub index: IndexOptions,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            debounce: DEFAULT_DEBOUNCE,
+            split: SplitOptions::default(),
+            index: IndexOptions::default(),
+        }
+    }
+}
+
+/// Outcome of re-indexing a batch of changed paths.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WatchReport {
+    /// Files and directories that were split again.
+    pub changed: Vec<String>,
+    /// Paths that no longer exist.
+    pub deleted: Vec<String>,
+    /// Number of chunks removed for changed or deleted paths.
+    pub chunks_deleted: u64,
+    /// Number of chunks inserted for changed paths.
+    pub chunks_inserted: u64,
+    /// Report for the files that were split again.
+    pub split: SplitReport,
+}
+
+/// Keeps the `options.index.table_name` table up to date with the files under `root_dir` as they
+/// change.
+///
+/// The table is expected to hold the directory as written by
+/// [`crate::split::find_and_split`]; only changes made after the watch starts are picked up.
+/// Changes are debounced, then the changed files are split, embedded and indexed again, replacing
+/// their previous chunks, and the chunks of deleted files are removed. Paths the initial scan
+/// would not split, such as files in unsupported languages or under `.git`, are ignored.
+///
+/// `on_update` is called with the report for every batch of changes, or with the error that
+/// batch failed with, such as a file removed while it was split or a locked table. The watch goes
+/// on either way, so that the next changes are picked up; it only ends if the watcher cannot be
+/// started.
+pub async fn watch(
+    root_dir: String,
+    db_file_uri: String,
+    options: &WatchOptions,
+    mut on_update: impl FnMut(Result<WatchReport>),
+) -> Result<()> {
+    let root = PathBuf::from(root_dir);
+    // Events may carry canonical paths, while chunks keep the root as given.
+    let canonical_root = fs::canonicalize(&root)?;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut debouncer = new_debouncer(options.debounce, move |x| {
+        // The receiver is only dropped once the watch is over.
+        let _ = sender.send(x);
+    })?;
+    debouncer
+        .watcher()
+        .watch(&canonical_root, RecursiveMode::Recursive)?;
+
+    while let Some(events) = receiver.recv().await {
+        let mut batches = vec![events];
+        // Changes made while the previous batch was indexed are merged into a single batch.
+        while let Ok(events) = receiver.try_recv() {
+            batches.push(events);
+        }
+        let mut paths = BTreeSet::new();
+        for events in batches {
+            match events {
+                Ok(events) => paths.extend(events.into_iter().map(|x| x.path)),
+                Err(e) => on_update(Err(e.into())),
+            }
+        }
+
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|x| to_root_path(&root, &canonical_root, x))
+            .collect();
+        if !paths.is_empty() {
+            on_update(reindex_paths(&root, &paths, &db_file_uri, options).await);
+        }
+    }
+    Ok(())
+}
+
+/// Maps a path reported by the watcher or an editor to the same path below `root`, unless it is
+/// ignored.
+pub(crate) fn to_root_path(root: &Path, canonical_root: &Path, path: &Path) -> Option<PathBuf> {
+    let relative_path = path
+        .strip_prefix(canonical_root)
+        .or_else(|_| path.strip_prefix(root))
+        .ok()?;
+    if relative_path.as_os_str().is_empty() || is_ignored(relative_path) {
+        return None;
+    }
+    Some(root.join(relative_path))
+}
+
+/// Replaces the chunks of `paths`, all of them below `root`, with their current contents.
+///
+/// Existing files in supported languages and existing directories are split again; paths that no
+/// longer exist are treated as deleted files or directories. Only the chunks of the codebase
+/// `root` belongs to are replaced, so other checkouts of the same paths in the table are kept.
+/// New chunks are inserted before the old ones are deleted, so searches made meanwhile still
+/// find the files, and a failed run leaves the old chunks in place.
+pub async fn reindex_paths(
+    root: &Path,
+    paths: &[PathBuf],
+    db_file_uri: &str,
+    options: &WatchOptions,
+) -> Result<WatchReport> {
+    let mut changed = Vec::new();
+    let mut deleted = Vec::new();
+    let mut conditions = Vec::new();
+    for path in paths {
+        let file_path = path.to_string_lossy().to_string();
+        // Chunks of a directory are stored under paths starting with the directory path.
+        let directory_condition = format!("file_path LIKE {}", like_prefix(&file_path));
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => {
+                conditions.push(directory_condition);
+                changed.push(path.clone());
+            }
+            Ok(_) => {
+                if get_language_for_path(path).is_some() {
+                    conditions.push(format!("file_path = {}", quote_literal(&file_path)));
+                    changed.push(path.clone());
+                }
+            }
+            Err(_) => {
+                conditions.push(format!("file_path = {}", quote_literal(&file_path)));
+                conditions.push(directory_condition);
+                deleted.push(file_path);
+            }
+        }
+    }
+
+    let temp_dir = tempfile::tempdir()?;
+    let split_file_uri = temp_dir.path().join("split.parquet");
+    let embed_file_uri = temp_dir.path().join("embed.parquet");
+    // Splitting and embedding keep the CPU busy, so they run off the async workers.
+    let split = {
+        let (root, changed) = (root.to_path_buf(), changed.clone());
+        let (split_file_uri, embed_file_uri) = (split_file_uri.clone(), embed_file_uri.clone());
+        let split_options = options.split.clone();
+        tokio::task::spawn_blocking(move || -> Result<SplitReport> {
+            let split = split_paths(
+                &root,
+                &changed,
+                split_file_uri.to_string_lossy().to_string(),
+                &split_options,
+            )?;
+            if split.chunks_written > 0 {
+                create_embeddings_from_file(
+                    split_file_uri.to_string_lossy().to_string(),
+                    embed_file_uri.to_string_lossy().to_string(),
+                )?;
+            }
+            Ok(split)
+        })
+        .await
+        .map_err(std::io::Error::from)??
+    };
+
+    // Every changed path has a condition, so no conditions means nothing was split either.
+    let chunks_deleted = if conditions.is_empty() {
+        0
+    } else {
+        let (repository, branch) = codebase_of(root, &options.split);
+        let predicate = format!(
+            "({}) AND {}",
+            conditions.join(" OR "),
+            codebase_predicate(&repository, branch.as_deref())
+        );
+        let input_file_uri =
+            (split.chunks_written > 0).then(|| embed_file_uri.to_string_lossy().to_string());
+        replace_rows(input_file_uri, db_file_uri, &predicate, &options.index).await?
+    };
+
+    Ok(WatchReport {
+        changed: changed
+            .iter()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect(),
+        deleted,
+        chunks_deleted,
+        chunks_inserted: split.chunks_written,
+        split,
+    })
+}
+
+/// Quotes a `LIKE` pattern matching every path below the directory `path`.
+fn like_prefix(path: &str) -> String {
+    let escaped = escape_like(path);
+    quote_literal(&format!("{}/%", escaped.trim_end_matches('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::index;
+    use crate::split::{find_and_split, find_and_split_with_options};
+    use lancedb::arrow::IntoPolars;
+    use lancedb::connect;
+    use lancedb::query::ExecutableQuery;
+    use tempfile::TempDir;
+
+    fn content(name: &str) -> String {
+        format!(
+            r#"
+            fn {name}() {{
+                println!("{name}");
+            }}
+            "#
+        )
+    }
+
+    #[test]
+    fn test_to_root_path() {
+        let root = Path::new("project");
+        let canonical_root = Path::new("/home/user/project");
+
+        assert_eq!(
+            to_root_path(
+                root,
+                canonical_root,
+                Path::new("/home/user/project/src/lib.rs")
+            ),
+            Some(PathBuf::from("project/src/lib.rs"))
+        );
+        assert_eq!(
+            to_root_path(root, canonical_root, Path::new("project/src/lib.rs")),
+            Some(PathBuf::from("project/src/lib.rs"))
+        );
+        assert_eq!(
+            to_root_path(
+                root,
+                canonical_root,
+                Path::new("/home/user/project/.git/index")
+            ),
+            None
+        );
+        assert_eq!(
+            to_root_path(root, canonical_root, Path::new("/home/user/other/lib.rs")),
+            None
+        );
+        assert_eq!(
+            to_root_path(root, canonical_root, Path::new("/home/user/project")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_like_prefix() {
+        assert_eq!(like_prefix("src/"), "'src/%'");
+        assert_eq!(like_prefix("my_src"), "'my\\_src/%'");
+        assert_eq!(like_prefix("it's"), "'it''s/%'");
+    }
+
+    #[tokio::test]
+    async fn test_reindex_paths() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(root.join("src/nested"))?;
+        fs::write(root.join("src/modified.rs"), content("modified"))?;
+        fs::write(root.join("src/deleted.rs"), content("deleted"))?;
+        fs::write(root.join("src/unchanged.rs"), content("unchanged"))?;
+        fs::write(root.join("src/nested/removed.rs"), content("removed"))?;
+
+        let split_file_uri = temp_dir.path().join("split.parquet");
+        let embed_file_uri = temp_dir.path().join("embed.parquet");
+        let db_path_str = temp_dir
+            .path()
+            .join("test.db")
+            .to_string_lossy()
+            .to_string();
+        find_and_split(
+            root.to_string_lossy().to_string(),
+            split_file_uri.to_string_lossy().to_string(),
+        )?;
+        create_embeddings_from_file(
+            split_file_uri.to_string_lossy().to_string(),
+            embed_file_uri.to_string_lossy().to_string(),
+        )?;
+        index(
+            embed_file_uri.to_string_lossy().to_string(),
+            db_path_str.clone(),
+        )
+        .await?;
+        // The same paths, indexed as another codebase, are left alone.
+        find_and_split_with_options(
+            root.to_string_lossy().to_string(),
+            split_file_uri.to_string_lossy().to_string(),
+            &SplitOptions {
+                repository: Some("other".to_string()),
+                ..Default::default()
+            },
+        )?;
+        create_embeddings_from_file(
+            split_file_uri.to_string_lossy().to_string(),
+            embed_file_uri.to_string_lossy().to_string(),
+        )?;
+        index(
+            embed_file_uri.to_string_lossy().to_string(),
+            db_path_str.clone(),
+        )
+        .await?;
+
+        fs::write(root.join("src/modified.rs"), content("rewritten"))?;
+        fs::remove_file(root.join("src/deleted.rs"))?;
+        fs::remove_dir_all(root.join("src/nested"))?;
+        fs::write(root.join("src/added.rs"), content("added"))?;
+        fs::write(root.join("src/notes.txt"), "Not code")?;
+
+        let report = reindex_paths(
+            &root,
+            &[
+                root.join("src/added.rs"),
+                root.join("src/deleted.rs"),
+                root.join("src/modified.rs"),
+                root.join("src/nested"),
+                root.join("src/notes.txt"),
+            ],
+            &db_path_str,
+            &WatchOptions::default(),
+        )
+        .await?;
+        assert_eq!(report.changed.len(), 2);
+        assert_eq!(report.deleted.len(), 2);
+        assert!(report.chunks_deleted > 0);
+        assert!(report.chunks_inserted > 0);
+
+        let db = connect(&db_path_str).execute().await?;
+        let table = db.open_table("codebases").execute().await?;
+        let df = table.query().execute().await?.into_polars().await?;
+        let repositories = df.column("repository")?.str()?.clone();
+        let texts: Vec<String> = df
+            .column("text")?
+            .str()?
+            .iter()
+            .zip(repositories.iter())
+            .filter(|(_, x)| *x != Some("other"))
+            .map(|(x, _)| x.unwrap_or_default().to_string())
+            .collect();
+        let contains = |name: &str| texts.iter().any(|x| x.contains(&format!("fn {name}()")));
+        assert!(contains("added"));
+        assert!(contains("rewritten"));
+        assert!(contains("unchanged"));
+        assert!(!contains("modified"));
+        assert!(!contains("deleted"));
+        assert!(!contains("removed"));
+        assert_eq!(
+            repositories.iter().filter(|x| *x == Some("other")).count(),
+            4,
+            "the chunks of the other codebase are kept"
+        );
+
+        Ok(())
+    }
+}